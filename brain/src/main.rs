@@ -52,7 +52,7 @@ where
     let mut reader = BufReader::new(file);
     reader.read_to_end(&mut buf).expect("to read file");
     let markov = bincode::deserialize(&buf).expect("deserialize file");
-    host::Server::new(&addr, markov).start();
+    host::Server::new(addr, markov).start();
 }
 
 fn main() {
@@ -75,9 +75,7 @@ fn main() {
 
     match (next!(), next!(), next!(), next!()) {
         (Some("train"), Some(input), Some(output), depth) => {
-            let depth = depth
-                .and_then(|d| d.parse::<usize>().ok())
-                .unwrap_or_else(|| 5);
+            let depth = depth.and_then(|d| d.parse::<usize>().ok()).unwrap_or(5);
 
             let output = data_dir.join(output);
 
@@ -89,7 +87,7 @@ fn main() {
                     err
                 ),
                 (Ok(in_file), Ok(out_file)) => {
-                    let size = util::get_file_size(&input).unwrap();
+                    let size = util::get_file_size(input).unwrap();
                     info!("size: {} KB", size.commas());
                     train(in_file, size as usize, out_file, depth);
                     let size = util::get_file_size(&output).unwrap();
@@ -109,7 +107,7 @@ fn main() {
             let size = util::get_file_size(&input).unwrap();
             info!("size: {} KB", size.commas());
 
-            let addr = addr.unwrap_or_else(|| "localhost:7878");
+            let addr = addr.unwrap_or("localhost:7878");
 
             let len = file.metadata().unwrap().len() as usize;
             load(file, len, addr)
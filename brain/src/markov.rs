@@ -55,11 +55,11 @@ impl<'a> Markov<'a> {
     fn train_link(&mut self, context: &[&'a str], token: &Token<'a>) {
         let ctx = context.to_vec();
         if let Some(link_set) = self.chain.get_mut(&ctx) {
-            link_set.insert(&token);
+            link_set.insert(token);
             return;
         }
 
-        self.chain.entry(ctx).or_default().insert(&token);
+        self.chain.entry(ctx).or_default().insert(token);
     }
 
     pub fn generate(&self, rng: &mut impl Rng) -> String {
@@ -69,7 +69,7 @@ impl<'a> Markov<'a> {
 
         fn context<'a, 'b>(words: &'a [&'b str], depth: usize) -> &'a [&'b str] {
             &words[words.len().saturating_sub(depth)..]
-        };
+        }
 
         while let Token::Word(word) = self.next_word(rng, context(&words, self.depth)) {
             words.push(word);
@@ -135,7 +135,7 @@ pub enum Token<'a> {
     End,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialOrd, Eq)]
+#[derive(Serialize, Deserialize, Clone, Eq)]
 pub struct Link<'a> {
     #[serde(borrow)]
     pub token: Token<'a>,
@@ -159,6 +159,12 @@ impl<'a> PartialEq for Link<'a> {
     }
 }
 
+impl<'a> PartialOrd for Link<'a> {
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
 impl<'a> Ord for Link<'a> {
     fn cmp(&self, rhs: &Self) -> Ordering {
         self.count.cmp(&rhs.count)
@@ -172,7 +178,7 @@ impl<'a> LinkSet<'a> {
     pub fn insert(&mut self, token: &Token<'a>) {
         let link = Link::from(token.clone());
 
-        if let Some(existing) = self.existing(&token) {
+        if let Some(existing) = self.existing(token) {
             existing.merge(&link);
             self.sort_unstable_by(|a, b| b.cmp(a)); // reverse
         } else {
@@ -18,7 +18,7 @@ fn get_log_level() -> LevelFilter {
         "ERROR" => LevelFilter::Error,
 
         // default
-        "INFO" | _ => LevelFilter::Info,
+        _ => LevelFilter::Info,
     }
 }
 
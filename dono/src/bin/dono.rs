@@ -16,7 +16,7 @@ fn main() {
 
     let mut size = [0u8; 4];
     stdin.read_exact(&mut size).unwrap();
-    let n: u32 = unsafe { std::mem::transmute(size) }; // (* int*)size
+    let n: u32 = u32::from_ne_bytes(size); // (* int*)size
 
     let mut buf = vec![0; n as usize];
     stdin.read_exact(&mut buf).unwrap();
@@ -7,6 +7,7 @@ pub mod youtube;
 // TODO don't do this
 pub use self::database::*;
 pub use self::error::*;
+#[allow(ambiguous_glob_reexports)]
 pub use self::local::*;
 pub use self::server::*;
 pub use self::youtube::*;
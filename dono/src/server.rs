@@ -83,7 +83,7 @@ impl HttpServer {
                     .list_regex
                     .captures(other)
                     .and_then(|c| c.name("ty"))
-                    .and_then(|s| Some(s.as_str()))
+                    .map(|s| s.as_str())
                     .map(str::to_lowercase);
 
                 match namespace.unwrap_or_else(|| "".into()).as_str() {
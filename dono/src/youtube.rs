@@ -58,7 +58,7 @@ impl super::Storage<Song> for Youtube {
         };
 
         let id = PATTERN
-            .captures(&url)
+            .captures(url)
             .and_then(|s| s.name("id"))
             .map(|s| s.as_str())
             .ok_or_else(|| Error::InvalidYoutubeUrl(url.to_string()))?;
@@ -159,7 +159,10 @@ impl YoutubeItem {
 
         let data: Response =
             serde_json::from_reader(resp.into_reader()).map_err(Error::Serialize)?;
-        let item = &data.items.get(0).ok_or_else(|| Error::InvalidYoutubeData)?;
+        let item = &data
+            .items
+            .first()
+            .ok_or_else(|| Error::InvalidYoutubeData)?;
         Ok(Self {
             title: item.snippet.title.to_string(),
             duration: from_iso8601(&item.details.duration),
@@ -178,7 +181,7 @@ fn from_iso8601(period: &str) -> i64 {
             'H' => (a + parse(p, i) * 60 * 60, i),
             'M' => (a + parse(p, i) * 60, i),
             'S' => (a + parse(p, i), i),
-            'P' | 'T' | _ => (a, i),
+            _ => (a, i),
         })
-        .0 as i64
+        .0
 }
@@ -0,0 +1,101 @@
+// `CommandMap::dispatch` with this many commands registered, which every module does
+// for every message. the commands are found in the `Registry`, shared by the modules
+use bencher::{benchmark_group, benchmark_main, black_box, Bencher};
+use shaken::prelude::*;
+
+use std::sync::Once;
+
+struct Commands;
+
+impl Module for Commands {}
+
+impl Commands {
+    fn ok(&mut self, _req: &Request) -> Option<Response> {
+        None
+    }
+}
+
+// commands are checked against the config and the database, so they're kept in a
+// temporary directory. requests need to know who the bot is
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let dir = std::env::temp_dir().join("shaken-bench");
+        std::fs::create_dir_all(&dir).unwrap();
+        let _ = std::fs::remove_file(dir.join("shaken.db"));
+        database::use_path(dir.join("shaken.db"));
+        let bot = User {
+            display: "shaken_bot".into(),
+            userid: 42,
+            color: RGB::from("#ffffff"),
+        };
+        UserStore::create_user(&database::get_connection(), &bot, true);
+
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        if !config::get_config_file().unwrap().exists() {
+            Config::default().save()
+        }
+    })
+}
+
+fn commands(n: usize) -> Vec<Command<Commands>> {
+    (0..n)
+        .map(|i| match i % 4 {
+            0 => format!("!command{} sub", i),
+            _ => format!("!command{}", i),
+        })
+        .map(|name| Command::new(name, Commands::ok))
+        .collect()
+}
+
+// a command, a subcommand, a longer word that shouldn't match and a miss
+fn requests(n: usize) -> Vec<Request> {
+    vec![
+        format!("!command{} some arguments here", n / 2 + 1),
+        format!("!command{} sub 30s", n / 2),
+        format!("!command{}0 some arguments here", n / 2 + 1),
+        "!nothing like this".to_string(),
+    ]
+    .into_iter()
+    .map(|line| {
+        let msg = irc::Message::parse(&format!(
+            "@user-id=1000;display-name=bench :bench!bench@irc.test PRIVMSG #bench :{}",
+            line
+        ))
+        .unwrap();
+        Request::try_from(&msg).unwrap()
+    })
+    .collect()
+}
+
+fn dispatch(b: &mut Bencher, n: usize) {
+    setup();
+    Registry::unregister_namespace("Bench");
+    let map = CommandMap::create("Bench", commands(n)).unwrap();
+    let requests = requests(n);
+    let mut this = Commands;
+    b.iter(|| {
+        for req in &requests {
+            black_box(map.dispatch(&mut this, black_box(req)));
+        }
+    })
+}
+
+macro_rules! sizes {
+    ($($n:expr => $name:ident);* $(;)?) => {
+        $(
+            fn $name(b: &mut Bencher) {
+                dispatch(b, $n)
+            }
+        )*
+        benchmark_group!(benches, $($name),*);
+    };
+}
+
+sizes! {
+    10 => dispatch_10;
+    100 => dispatch_100;
+    1000 => dispatch_1000;
+}
+
+benchmark_main!(benches);
@@ -20,7 +20,6 @@ fn git_version() -> Option<(String, String)> {
     }
 
     let branch = option_env!("SHAKEN_BRANCH").unwrap_or_else(|| "dev");
-    do_git(&["rev-parse", "--short=12", branch]).and_then(|rev| {
-        do_git(&["rev-parse", "--abbrev-ref", branch]).and_then(|branch| Some((rev, branch)))
-    })
+    do_git(&["rev-parse", "--short=12", branch])
+        .and_then(|rev| do_git(&["rev-parse", "--abbrev-ref", branch]).map(|branch| (rev, branch)))
 }
@@ -0,0 +1,386 @@
+use crate::prelude::*;
+
+use std::time::Duration;
+
+/// What an argument can be
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// A whole number
+    Int,
+    /// A whole number, or one of these words
+    IntOr(&'static [&'static str]),
+    /// A single word
+    Word,
+    /// A user we've seen, with or without the @
+    User,
+    /// Like `90`, `90s`, `5m` or `1h30m`. A plain number is in seconds
+    Duration,
+    /// One of these words
+    Choice(&'static [&'static str]),
+    /// The rest of the line
+    Rest,
+}
+
+/// An argument a command takes, see `CommandMap::create`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Arg {
+    name: &'static str,
+    kind: Kind,
+    optional: bool,
+}
+
+impl Arg {
+    pub const fn new(name: &'static str, kind: Kind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    /// Marks this argument as optional. It is only skipped if the line ran out
+    pub const fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    fn parse(&self, input: &str) -> Result<Value, Error> {
+        let invalid = || Error::Invalid(*self, input.to_string());
+        let word = |words: &[&'static str]| {
+            words
+                .iter()
+                .find(|word| word.eq_ignore_ascii_case(input))
+                .copied()
+        };
+
+        let value = match self.kind {
+            Kind::Int => Value::Int(input.parse().map_err(|_| invalid())?),
+            Kind::IntOr(words) => match input.parse() {
+                Ok(n) => Value::Int(n),
+                Err(..) => Value::Word(word(words).ok_or_else(invalid)?),
+            },
+            Kind::Word | Kind::Rest => Value::Text(input.to_string()),
+            Kind::User => {
+                let name = input.trim_start_matches('@');
+                let user = UserStore::get_user_by_name(&get_connection(), name)
+                    .ok_or_else(|| Error::UnknownUser(name.to_string()))?;
+                Value::User(user)
+            }
+            Kind::Duration => Value::Duration(parse_duration(input).ok_or_else(invalid)?),
+            Kind::Choice(words) => Value::Word(word(words).ok_or_else(invalid)?),
+        };
+        Ok(value)
+    }
+}
+
+impl std::fmt::Display for Arg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.kind {
+            Kind::Choice(words) => words.join("|"),
+            Kind::IntOr(words) => format!("{}|{}", self.name, words.join("|")),
+            Kind::Rest => format!("{}...", self.name),
+            _ => self.name.to_string(),
+        };
+        if self.optional {
+            write!(f, "[{}]", name)
+        } else {
+            write!(f, "<{}>", name)
+        }
+    }
+}
+
+pub const fn int(name: &'static str) -> Arg {
+    Arg::new(name, Kind::Int)
+}
+
+pub const fn int_or(name: &'static str, words: &'static [&'static str]) -> Arg {
+    Arg::new(name, Kind::IntOr(words))
+}
+
+pub const fn word(name: &'static str) -> Arg {
+    Arg::new(name, Kind::Word)
+}
+
+pub const fn user(name: &'static str) -> Arg {
+    Arg::new(name, Kind::User)
+}
+
+pub const fn duration(name: &'static str) -> Arg {
+    Arg::new(name, Kind::Duration)
+}
+
+pub const fn choice(name: &'static str, words: &'static [&'static str]) -> Arg {
+    Arg::new(name, Kind::Choice(words))
+}
+
+pub const fn rest(name: &'static str) -> Arg {
+    Arg::new(name, Kind::Rest)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Word(&'static str), // from a choice
+    Text(String),
+    User(User),
+    Duration(Duration),
+}
+
+/// The parsed arguments of a command, by name
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Args(Vec<(&'static str, Value)>);
+
+impl Args {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.iter().find(|(k, _)| *k == name).map(|(_, v)| v)
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn word(&self, name: &str) -> Option<&'static str> {
+        match self.get(name)? {
+            Value::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        match self.get(name)? {
+            Value::User(user) => Some(user),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, name: &str) -> Option<Duration> {
+        match self.get(name)? {
+            Value::Duration(dur) => Some(*dur),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Missing(Arg),
+    Invalid(Arg, String),
+    UnknownUser(String),
+    Unexpected(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Missing(arg) => write!(f, "{} is missing", arg),
+            Error::Invalid(arg, input) => match arg.kind {
+                Kind::Int => write!(f, "'{}' isn't a number", input),
+                Kind::Duration => write!(f, "'{}' isn't a duration", input),
+                Kind::IntOr(words) => write!(
+                    f,
+                    "'{}' isn't a number or one of: {}",
+                    input,
+                    words.join(", ")
+                ),
+                Kind::Choice(words) => {
+                    write!(f, "'{}' isn't one of: {}", input, words.join(", "))
+                }
+                _ => write!(f, "'{}' isn't a valid {}", input, arg.name),
+            },
+            Error::UnknownUser(name) => write!(f, "I don't know who {} is", name),
+            Error::Unexpected(input) => write!(f, "I don't know what '{}' is for", input),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses the `input` for a command that takes `args`
+pub fn parse(args: &[Arg], input: &str) -> Result<Args, Error> {
+    let mut parsed = Args::default();
+    let mut input = input.trim();
+    for arg in args {
+        if input.is_empty() {
+            if arg.optional {
+                continue;
+            }
+            return Err(Error::Missing(*arg));
+        }
+
+        let (head, tail) = match arg.kind {
+            Kind::Rest => (input, ""),
+            _ => match input.find(char::is_whitespace) {
+                Some(pos) => (&input[..pos], input[pos..].trim_start()),
+                None => (input, ""),
+            },
+        };
+        parsed.0.push((arg.name, arg.parse(head)?));
+        input = tail;
+    }
+
+    if !input.is_empty() {
+        return Err(Error::Unexpected(input.to_string()));
+    }
+    Ok(parsed)
+}
+
+/// Generates the usage for `command`, e.g. `!give <user> <amount>`
+pub fn usage(command: &str, args: &[Arg]) -> String {
+    std::iter::once(command.to_string())
+        .chain(args.iter().map(ToString::to_string))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_duration(input: &str) -> Option<Duration> {
+    if let Ok(secs) = input.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let (mut total, mut num) = (0u64, String::new());
+    for ch in input.chars() {
+        if ch.is_ascii_digit() {
+            num.push(ch);
+            continue;
+        }
+        let unit = match ch.to_ascii_lowercase() {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let n = num.parse::<u64>().ok()?;
+        total = total.checked_add(n.checked_mul(unit)?)?;
+        num.clear();
+    }
+
+    if !num.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMOUNT: Arg = int_or("amount", &["all", "half"]);
+
+    #[test]
+    fn parse_args() {
+        let args = [int("n"), choice("mode", &["fast", "slow"]), rest("message")];
+        let parsed = parse(&args, "  42 SLOW hello   world ").unwrap();
+        assert_eq!(parsed.int("n"), Some(42));
+        assert_eq!(parsed.word("mode"), Some("slow"));
+        assert_eq!(parsed.text("message"), Some("hello   world"));
+        assert_eq!(parsed.text("n"), None);
+
+        assert_eq!(
+            parse(&[AMOUNT], "half").unwrap().word("amount"),
+            Some("half")
+        );
+        assert_eq!(parse(&[AMOUNT], "10").unwrap().int("amount"), Some(10));
+
+        let parsed = parse(&[word("name"), int("n").optional()], "foo").unwrap();
+        assert_eq!(parsed.text("name"), Some("foo"));
+        assert_eq!(parsed.get("n"), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let args = [int("n"), choice("mode", &["fast", "slow"])];
+        assert_eq!(parse(&args, ""), Err(Error::Missing(args[0])));
+        assert_eq!(parse(&args, "1"), Err(Error::Missing(args[1])));
+        assert_eq!(
+            parse(&args, "one fast"),
+            Err(Error::Invalid(args[0], "one".into()))
+        );
+        assert_eq!(
+            parse(&args, "1 fast please"),
+            Err(Error::Unexpected("please".into()))
+        );
+        assert_eq!(
+            parse(&args, "1 medium").unwrap_err().to_string(),
+            "'medium' isn't one of: fast, slow"
+        );
+        assert_eq!(
+            parse(&[AMOUNT], "some").unwrap_err().to_string(),
+            "'some' isn't a number or one of: all, half"
+        );
+        // optional arguments are only skipped when the line runs out
+        assert!(parse(&[int("n").optional()], "many").is_err());
+    }
+
+    #[test]
+    fn parse_user() {
+        let db = get_connection();
+        let user = User {
+            display: "Someone".into(),
+            userid: 1000,
+            color: RGB::from("#ffffff"),
+        };
+        UserStore::create_user(&db, &user, false);
+
+        for input in &["someone", "@Someone"] {
+            let parsed = parse(&[self::user("target")], input).unwrap();
+            assert_eq!(parsed.user("target"), Some(&user));
+        }
+        assert_eq!(
+            parse(&[self::user("target")], "@nobody")
+                .unwrap_err()
+                .to_string(),
+            "I don't know who nobody is"
+        );
+    }
+
+    #[test]
+    fn parse_durations() {
+        let secs = |s| parse_duration(s).map(|d| d.as_secs());
+        assert_eq!(secs("90"), Some(90));
+        assert_eq!(secs("90s"), Some(90));
+        assert_eq!(secs("5m"), Some(300));
+        assert_eq!(secs("1h30m"), Some(5400));
+        assert_eq!(secs("1d"), Some(86400));
+        assert_eq!(secs("h"), None);
+        assert_eq!(secs("10x"), None);
+        assert_eq!(secs("1h30"), None);
+    }
+
+    #[test]
+    fn generated_usage() {
+        let args = [
+            user("user"),
+            AMOUNT,
+            choice("mode", &["a", "b"]).optional(),
+            rest("message").optional(),
+        ];
+        assert_eq!(
+            usage("!give", &args),
+            "!give <user> <amount|all|half> [a|b] [message...]"
+        );
+        assert_eq!(usage("!top", &[]), "!top");
+    }
+}
@@ -1,16 +1,16 @@
-use crossbeam_channel as channel;
 use hashbrown::HashMap;
 use log::{error, info, warn};
-use scoped_threadpool::Pool;
 use simplelog::{Config as LogConfig, TermLogger};
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
 use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::{thread::sleep, time};
+use std::time;
 
 use shaken::modules::*;
 use shaken::prelude::*;
+use shaken::recorder;
+
+const STABLE_CONNECTION: time::Duration = time::Duration::from_secs(60);
 
 fn main() {
     TermLogger::init(
@@ -19,10 +19,17 @@ fn main() {
     )
     .expect("initialize logger");
 
-    parse_args();
+    let replay = parse_args();
+    // so replaying can't change the real database
+    let copy = replay.as_ref().map(|_| {
+        database::use_copy().unwrap_or_else(|err| {
+            error!("cannot copy the database: {}", err);
+            std::process::exit(1)
+        })
+    });
 
-    let config = Config::load();
-    let (modules, disabled) = create_modules(&config);
+    let mut config = Config::load();
+    let (mut modules, disabled) = create_modules(&config);
 
     let printer = Printer::new(&disabled);
     printer.modules();
@@ -34,87 +41,175 @@ fn main() {
         printer.user_commands();
     }
 
+    if let Some(file) = replay {
+        let code = replay_recording(&file, &config, modules.loaded());
+        if let Some(copy) = copy {
+            let _ = std::fs::remove_file(copy);
+        }
+        std::process::exit(code)
+    }
+
+    let shutdown = connection::Shutdown::default();
+    {
+        let shutdown = shutdown.clone();
+        ctrlc::set_handler(move || {
+            if shutdown.request() {
+                warn!("forcing shutdown");
+                std::process::exit(130)
+            }
+            info!("shutting down. send the signal again to force it");
+        })
+        .expect("install signal handler");
+    }
+
     let address = format!("{}:{}", &config.twitch.address, &config.twitch.port);
-    let mut delay = 0;
+    let mut backoff = connection::Backoff::default();
+    let mut delay: Option<time::Duration> = None;
     loop {
-        if delay > 0 {
-            warn!("sleeping for {} seconds", delay);
-            sleep(time::Duration::from_secs(delay));
+        if let Some(delay) = delay.take() {
+            warn!("reconnecting in {:.1} seconds", delay.as_secs_f64());
+            if shutdown.wait(delay) {
+                modules.shutdown();
+                break;
+            }
         }
 
-        info!("trying to connect to {}", address);
-        let conn = match irc::TcpConn::connect(&address) {
-            Ok(conn) => {
-                delay = 0;
-                conn
+        let conn = match (&config.twitch.websocket, config.twitch.tls) {
+            (Some(url), _) => {
+                info!("trying to connect to {}", url);
+                irc::WebSocketConn::connect(url).map(|c| Box::new(c) as Box<dyn irc::Transport>)
+            }
+            (None, true) => {
+                info!("trying to connect to {} (tls)", address);
+                irc::TcpConn::connect_tls(&address, &config.twitch.address)
+                    .map(|c| Box::new(c) as Box<dyn irc::Transport>)
             }
+            (None, false) => {
+                info!("trying to connect to {}", address);
+                irc::TcpConn::connect(&address).map(|c| Box::new(c) as Box<dyn irc::Transport>)
+            }
+        };
+
+        let conn = match conn {
+            Ok(conn) => conn,
             Err(err) => {
                 error!("error: {}", err);
-                delay += 5;
+                connection::record_failure();
+                delay.replace(backoff.next_delay());
                 continue;
             }
         };
 
         info!("connected and running");
-        run(&config, conn, &modules);
-        info!("disconnected, respawning");
+        connection::record_connect();
+        let connected = time::Instant::now();
+        let reason = Bot::run(conn, &mut config, &mut modules, &shutdown);
+        if shutdown.is_requested() {
+            // the bot only shuts the modules down if it was still connected
+            if reason != Disconnect::Shutdown {
+                modules.shutdown();
+            }
+            break;
+        }
+        info!("disconnected ({:?}), respawning", reason);
+
+        // a connection that stayed up for a while isn't part of a bad streak
+        if connected.elapsed() >= STABLE_CONNECTION {
+            backoff.reset()
+        }
 
-        delay += 5;
+        match reason {
+            // twitch wants us to move to a new server right away
+            Disconnect::Reconnect => {}
+            _ => {
+                delay.replace(backoff.next_delay());
+            }
+        }
     }
+
+    info!("shut down");
+    std::process::exit(0)
 }
 
-fn parse_args() {
-    if let "config" = std::env::args().nth(1).unwrap_or_default().as_str() {
-        let file = config::get_config_file().unwrap_or_else(|| {
-            error!("system does not have a standard directory for configuration files. aborting");
+/// Returns the recording to replay, if one was given
+fn parse_args() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    match args.next().unwrap_or_default().as_str() {
+        "config" => generate_config(),
+        "replay" => Some(args.next().unwrap_or_else(|| {
+            error!("usage: shaken replay <file>");
             std::process::exit(1)
-        });
-
-        if std::fs::metadata(&file).is_ok() {
-            warn!(
-                "configuration file already exists at: {}",
-                file.to_string_lossy()
-            );
-            warn!("delete it and rerun command to generate a default configuration");
-            std::process::exit(1)
-        }
+        })),
+        _ => None,
+    }
+}
+
+fn generate_config() -> ! {
+    let file = config::get_config_file().unwrap_or_else(|| {
+        error!("system does not have a standard directory for configuration files. aborting");
+        std::process::exit(1)
+    });
 
-        info!(
-            "creating a default configuration in: {}",
+    if std::fs::metadata(&file).is_ok() {
+        warn!(
+            "configuration file already exists at: {}",
             file.to_string_lossy()
         );
-        Config::default().save();
-        std::process::exit(0)
+        warn!("delete it and rerun command to generate a default configuration");
+        std::process::exit(1)
+    }
+
+    info!(
+        "creating a default configuration in: {}",
+        file.to_string_lossy()
+    );
+    Config::default().save();
+    std::process::exit(0)
+}
+
+/// Replays `file` through the modules, returning the exit code
+fn replay_recording(file: &str, config: &Config, modules: &[LoadedModule]) -> i32 {
+    let entries = match recorder::load(file) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    info!("replaying {} lines from {}", entries.len(), file);
+    let mismatches = recorder::replay(&entries, config, modules);
+    for mismatch in &mismatches {
+        println!("<- {}", mismatch.line);
+        for line in &mismatch.missing {
+            println!("- {}", line);
+        }
+        for line in &mismatch.extra {
+            println!("+ {}", line);
+        }
+    }
+
+    if mismatches.is_empty() {
+        info!("all responses matched");
+        0
+    } else {
+        warn!("{} lines had different responses", mismatches.len());
+        2
     }
 }
 
-type LoadedModule = Arc<Mutex<dyn Module>>;
-fn create_modules(config: &Config) -> (Vec<LoadedModule>, Vec<&'static str>) {
-    let mut modules: Vec<Arc<Mutex<dyn Module>>> = vec![];
-    let mut disabled = vec![];
+fn create_modules(config: &Config) -> (module::Manager, Vec<&'static str>) {
+    let mut modules = module::Manager::default();
 
     macro_rules! create {
         ($e:path) => {{
             let name = stringify!($e).split("::").next().unwrap();
-            if config.enabled.iter().any(|m| m == name) {
-                if let Ok(m) = $e() {
-                    info!("loaded module: {}", name);
-                    modules.push(Arc::new(Mutex::new(m)))
-                }
-            } else {
-                disabled.push(name);
-            }
+            modules.add(name, $e);
         }};
         ($e:path, $($f:expr),+) => {{
             let name = stringify!($e).split("::").next().unwrap();
-            if config.enabled.iter().any(|m| m == name) {
-                if let Ok(m) = $e($($f)*) {
-                    info!("loaded module: {}", name);
-                    modules.push(Arc::new(Mutex::new(m)))
-                }
-            } else {
-                disabled.push(name)
-            }
+            // the arguments are evaluated again each time the module is created
+            modules.add(name, move || $e($($f)*));
         }};
     }
 
@@ -124,54 +219,20 @@ fn create_modules(config: &Config) -> (Vec<LoadedModule>, Vec<&'static str>) {
     create!(Invest::create);
     create!(RustStuff::create);
 
-    let brains = config
-        .shakespeare
-        .brains
-        .to_vec()
-        .into_iter()
-        .inspect(|brain| info!("creating BrainMarkov for: {}", brain))
-        .map(|url| Box::new(BrainMarkov(url.into())) as Box<dyn Markov + 'static>)
-        .collect::<Vec<_>>();
-
+    let brains = config.shakespeare.brains.clone();
     if !brains.is_empty() {
-        create!(Shakespeare::create, brains);
+        create!(Shakespeare::create, make_brains(&brains));
     }
 
+    let disabled = modules.load(config);
     (modules, disabled)
 }
 
-fn run(config: &Config, conn: irc::TcpConn, modules: &[LoadedModule]) {
-    let (bot, events) = Bot::create(conn);
-    bot.register(&config.twitch.name);
-
-    let (inputs, outputs) = {
-        let (mut inputs, mut outputs) = (vec![], vec![]);
-        for _ in 0..modules.len() {
-            // probably should be bounded
-            let (tx, rx) = channel::unbounded();
-            inputs.push(tx);
-            outputs.push(rx);
-        }
-        (inputs, outputs)
-    };
-
-    let mut pool = Pool::new((modules.len() as u32) + 1);
-    pool.scoped(|scope| {
-        let (tx, rx) = channel::unbounded();
-        for (module, outputs) in modules.iter().zip(outputs) {
-            let (sender, outputs) = (tx.clone(), outputs.clone());
-            scope.execute(move || module.lock().unwrap().handle(outputs, sender));
-        }
-
-        scope.execute(move || bot.process(rx));
-
-        for event in events {
-            for input in &inputs {
-                let _ = input.send(event.clone());
-            }
-        }
-        drop(inputs)
-    });
+fn make_brains(urls: &[String]) -> Vec<Box<dyn Markov>> {
+    urls.iter()
+        .inspect(|brain| info!("creating BrainMarkov for: {}", brain))
+        .map(|url| Box::new(BrainMarkov(url.clone().into())) as Box<dyn Markov + 'static>)
+        .collect()
 }
 
 macro_rules! colorln {
@@ -228,7 +289,7 @@ impl<'a> Printer<'a> {
         color!(&mut buffer, self.green, "- ");
         for (i, m) in MODULES
             .iter()
-            .filter(|s| !self.disabled.contains(&s))
+            .filter(|s| !self.disabled.contains(s))
             .enumerate()
         {
             color!(&mut buffer, self.white, "{}", m);
@@ -308,7 +369,7 @@ impl<'a> Printer<'a> {
                     warn!("they own {} commands: {}", v.len(), v.join(", "));
                     None
                 })
-                .and_then(|User { display, .. }| Some((k, display, v)))
+                .map(|User { display, .. }| (k, display, v))
         }) {
             color!(&mut buffer, self.yellow, "commands from ");
             color!(&mut buffer, self.green, "{}", n);
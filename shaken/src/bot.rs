@@ -1,4 +1,7 @@
 use crate::prelude::*;
+use crate::ratelimit::Outbound;
+use crate::recorder::{Direction, Recorder};
+use crate::supervisor::Supervisor;
 use crossbeam_channel as channel;
 use log::*;
 use std::thread;
@@ -7,36 +10,124 @@ use std::time::{Duration, Instant};
 pub type Receiver = channel::Receiver<Event>;
 pub type Sender = channel::Sender<(Option<irc::Message>, Response)>;
 
+/// How long to keep writing queued responses after a shutdown is requested
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
 pub enum Event {
-    Message(irc::Message, Option<Box<Request>>),
+    Message(
+        irc::Message,
+        Option<Box<Request>>,
+        Option<Box<irc::TwitchEvent>>,
+    ),
     Inspect(irc::Message, Box<Response>),
     Tick(Instant),
+    Disconnected(Disconnect),
+    Shutdown,
 }
 
 pub struct Bot {
     out_tx: channel::Sender<String>,
     inspect_tx: channel::Sender<(irc::Message, Box<Response>)>,
+    disconnect_rx: channel::Receiver<Disconnect>,
+    closed_rx: channel::Receiver<()>,
+    messages: config::Messages,
 }
 
 impl Bot {
-    pub fn create(mut conn: irc::TcpConn) -> (Self, Receiver) {
+    /// Spawns the read/write loop for `conn`
+    ///
+    /// The connection is closed once the remote end hangs up, twitch asks us to
+    /// reconnect, a PING goes unanswered, or the Bot is dropped
+    pub fn create<T>(conn: T) -> (Self, Receiver)
+    where
+        T: irc::Transport + 'static,
+    {
+        Self::create_with(
+            conn,
+            connection::Keepalive::default(),
+            None,
+            connection::Shutdown::default(),
+        )
+    }
+
+    /// Like `create`, but with the given `keepalive`, and optionally recording every line
+    ///
+    /// Once `shutdown` is requested, reading stops. The lines sent before the Bot is
+    /// dropped are still written, then we PART the channels we joined and QUIT
+    pub fn create_with<T>(
+        mut conn: T,
+        keepalive: connection::Keepalive,
+        mut recorder: Option<Recorder>,
+        shutdown: connection::Shutdown,
+    ) -> (Self, Receiver)
+    where
+        T: irc::Transport + 'static,
+    {
         let (in_tx, in_rx) = channel::unbounded();
         let (out_tx, out_rx) = channel::unbounded::<String>();
         let (inspect_tx, inspect_rx) = channel::bounded(4);
+        let (disconnect_tx, disconnect_rx) = channel::bounded(1);
+        let (closed_tx, closed_rx) = channel::bounded::<()>(0);
 
         thread::spawn(move || {
             let tick = channel::tick(Duration::from_millis(1000));
+            let mut outbound = Outbound::new();
+            let mut health = connection::Health::new(keepalive, Instant::now());
+            let mut joined = vec![];
+
+            let reason = loop {
+                if shutdown.is_requested() {
+                    info!("shutting down");
+                    break Disconnect::Shutdown;
+                }
 
-            loop {
-                if let Ok(data) = out_rx.try_recv() {
-                    conn.write(&data);
-                    trace!("done writing")
+                let mut dropped = false;
+                loop {
+                    match out_rx.try_recv() {
+                        Ok(data) => outbound.push(data),
+                        Err(channel::TryRecvError::Disconnected) => {
+                            dropped = true;
+                            break;
+                        }
+                        Err(channel::TryRecvError::Empty) => break,
+                    }
+                }
+                if dropped {
+                    trace!("bot was dropped, closing connection");
+                    break Disconnect::Shutdown;
+                }
+
+                match health.poll(Instant::now()) {
+                    Ok(Some(ping)) => outbound.push(ping),
+                    Ok(None) => {}
+                    Err(reason) => {
+                        warn!("no response to our ping, disconnecting");
+                        break reason;
+                    }
+                }
+
+                while let Some(data) = outbound.pop(Instant::now()) {
+                    write(&mut conn, recorder.as_mut(), &mut joined, &data)
+                }
+                if !outbound.is_empty() {
+                    trace!("rate limited, {} lines queued", outbound.len());
                 }
-                match conn.try_read() {
+
+                match conn.read_line() {
                     Some(irc::ReadStatus::Data(msg)) => {
                         trace!("read line");
-                        let msg = irc::Message::parse(&msg);
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.record(Direction::In, &msg)
+                        }
+                        let msg = match irc::Message::parse(&msg) {
+                            Ok(msg) => msg,
+                            Err(err) => {
+                                warn!("skipping a bad line ({}): {:?}", err, msg);
+                                continue;
+                            }
+                        };
+                        health.on_read(&msg, Instant::now());
                         if let "GLOBALUSERSTATE" = msg.command() {
                             if let Some(user) = User::from_msg(&msg) {
                                 debug!("our user: {}", user);
@@ -44,15 +135,27 @@ impl Bot {
                                 warn!("cannot get our user")
                             }
                         }
-                        let req = Request::try_from(&msg);
-                        let _ = in_tx.send(Event::Message(msg, req.map(Box::new)));
+                        permission::observe(&msg);
+                        let req = Request::try_from(&msg).map(Box::new);
+                        let ev = irc::TwitchEvent::from_msg(&msg).map(Box::new);
+                        if let Some(irc::TwitchEvent::UserState { channel, badges }) = ev.as_deref()
+                        {
+                            let moderator = badges.contains(&irc::Badge::Moderator)
+                                || badges.contains(&irc::Badge::Broadcaster);
+                            outbound.set_moderator(channel, moderator);
+                        }
+                        let reconnect = ev.as_deref() == Some(&irc::TwitchEvent::Reconnect);
+                        let _ = in_tx.send(Event::Message(msg, req, ev));
                         trace!("done dispatching message");
+                        if reconnect {
+                            info!("twitch asked us to reconnect");
+                            break Disconnect::Reconnect;
+                        }
                     }
                     Some(irc::ReadStatus::Nothing) => {}
-                    _ => {
+                    None => {
                         trace!("dropping read channel");
-                        drop(in_tx);
-                        return;
+                        break Disconnect::Closed;
                     }
                 };
 
@@ -63,10 +166,165 @@ impl Bot {
                 if let Ok((msg, resp)) = inspect_rx.try_recv() {
                     let _ = in_tx.send(Event::Inspect(msg, resp));
                 }
+            };
+
+            connection::record_disconnect(reason);
+            let _ = disconnect_tx.send(reason);
+            drop(in_tx);
+
+            if reason == Disconnect::Shutdown && shutdown.is_requested() {
+                let deadline = Instant::now() + FLUSH_TIMEOUT;
+                // the modules are finishing up, so wait for the Bot to be dropped
+                loop {
+                    match out_rx.recv_timeout(Duration::from_millis(50)) {
+                        Ok(data) => outbound.push(data),
+                        Err(channel::RecvTimeoutError::Disconnected) => break,
+                        Err(channel::RecvTimeoutError::Timeout) => {}
+                    }
+                    while let Some(data) = outbound.pop(Instant::now()) {
+                        write(&mut conn, recorder.as_mut(), &mut joined, &data)
+                    }
+                    if Instant::now() >= deadline {
+                        warn!("modules are taking too long to finish, quitting anyway");
+                        break;
+                    }
+                }
+
+                if !outbound.is_empty() {
+                    warn!("dropping {} queued lines", outbound.len());
+                }
+
+                // these aren't rate limited, so they are always written
+                let parts = joined
+                    .iter()
+                    .map(|ch| format!("PART {}", ch))
+                    .collect::<Vec<_>>();
+                for data in parts
+                    .iter()
+                    .map(String::as_str)
+                    .chain(Some("QUIT :shutting down"))
+                {
+                    write(&mut conn, recorder.as_mut(), &mut joined, data)
+                }
             }
+
+            conn.close();
+            drop(closed_tx);
+        });
+
+        (
+            Bot {
+                out_tx,
+                inspect_tx,
+                disconnect_rx,
+                closed_rx,
+                messages: config::Messages::default(),
+            },
+            in_rx,
+        )
+    }
+
+    /// Why the connection was closed, if it has been
+    pub fn disconnect_reason(&self) -> Option<Disconnect> {
+        self.disconnect_rx.try_recv().ok()
+    }
+
+    /// Registers and runs the `modules` until `conn` is disconnected, returning why
+    ///
+    /// Each module gets its own thread, and their responses are written back to `conn`.
+    /// Modules only see messages from the channels they are enabled in, and a module
+    /// that panics is rebuilt (see `Supervisor`). The owners can start and stop
+    /// modules with `!module`, which updates the `config`
+    ///
+    /// Once `shutdown` is requested, the modules finish what they are doing and are
+    /// shut down, and their responses are written before we QUIT
+    pub fn run<T>(
+        conn: T,
+        config: &mut Config,
+        modules: &mut module::Manager,
+        shutdown: &connection::Shutdown,
+    ) -> Disconnect
+    where
+        T: irc::Transport + 'static,
+    {
+        let recorder = config.twitch.record.as_ref().and_then(|path| {
+            Recorder::create(path)
+                .map_err(|err| error!("cannot record to {}: {}", path, err))
+                .ok()
         });
+        let (mut bot, events) = Self::create_with(
+            conn,
+            connection::Keepalive::default(),
+            recorder,
+            shutdown.clone(),
+        );
+        bot.messages = config.messages.clone();
+        permission::set_owners(&config.twitch.owners);
+        // the Bot handles this itself, it has the modules
+        permission::declare("!module", Role::Owner);
+        cooldown::configure(&config.cooldowns);
+        let disconnect = bot.disconnect_rx.clone();
+        let closed = bot.closed_rx.clone();
+        bot.register(&config.twitch.name);
+
+        // the modules kept their state through a disconnect
+        modules.forget_states();
+
+        let (tx, rx) = channel::unbounded();
+        let mut running = modules
+            .loaded()
+            .iter()
+            .map(|loaded| Running::start(loaded.clone(), config, tx.clone()))
+            .collect::<Vec<_>>();
+
+        let process = thread::spawn(move || bot.process(rx));
+
+        for event in events {
+            if let Event::Message(msg, Some(req), ..) = &event {
+                if let Some(req) = req.search("!module") {
+                    if permission::check("!module", &req) {
+                        let resp = manage_modules(&req, config, modules, &mut running, &tx);
+                        if let Some(resp) = resp {
+                            let _ = tx.send((Some(msg.clone()), resp));
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let channel = match &event {
+                Event::Message(msg, ..) => msg
+                    .args
+                    .first()
+                    .filter(|s| s.starts_with('#'))
+                    .map(|s| s.as_str()),
+                _ => None,
+            };
+            for module in &running {
+                // ticks and messages that aren't from a channel go to every module
+                match channel {
+                    Some(ch) if !config.is_enabled(module.name, Some(ch)) => continue,
+                    _ => {}
+                }
+                let _ = module.input.send(event.clone());
+            }
+        }
 
-        (Bot { out_tx, inspect_tx }, in_rx)
+        let reason = disconnect.recv().unwrap_or(Disconnect::Closed);
+        for module in running {
+            let _ = module.input.send(Event::Disconnected(reason));
+            if reason == Disconnect::Shutdown {
+                let _ = module.input.send(Event::Shutdown);
+            }
+            module.stop()
+        }
+        modules.save_states();
+        drop(tx);
+        let _ = process.join();
+        // wait for everything to be written
+        let _ = closed.recv();
+
+        reason
     }
 
     pub fn send<S>(&self, data: S)
@@ -84,10 +342,11 @@ impl Bot {
                 let _ = self.inspect_tx.send((msg.clone(), Box::new(resp.clone())));
             }
 
-            if let Some(resp) = resp.build(msg) {
-                resp.into_iter()
-                    .inspect(|s| trace!("writing response: {}", s))
-                    .for_each(|m| self.send(m));
+            if let Some(resp) = resp.build(msg, &self.messages) {
+                for m in resp {
+                    trace!("writing response: {}", m);
+                    self.send(m)
+                }
             }
             trace!("done processing message/response pair");
         }
@@ -112,3 +371,405 @@ impl Bot {
         trace!("registered");
     }
 }
+
+// writes a line, keeping track of the channels we are in
+fn write<T>(conn: &mut T, recorder: Option<&mut Recorder>, joined: &mut Vec<String>, data: &str)
+where
+    T: irc::Transport,
+{
+    if let Some(recorder) = recorder {
+        recorder.record(Direction::Out, data)
+    }
+    conn.write_line(data);
+    trace!("done writing");
+
+    let mut parts = data.splitn(2, ' ');
+    let (join, channels) = match (parts.next(), parts.next()) {
+        (Some("JOIN"), Some(channels)) => (true, channels),
+        (Some("PART"), Some(channels)) => (false, channels),
+        _ => return,
+    };
+    for channel in channels.split(',').map(str::trim) {
+        let channel = channel.to_ascii_lowercase();
+        joined.retain(|ch| *ch != channel);
+        if join {
+            joined.push(channel)
+        }
+    }
+}
+
+// a module's thread, and where its events are sent
+struct Running {
+    name: &'static str,
+    input: channel::Sender<Event>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Running {
+    fn start(loaded: LoadedModule, config: &Config, tx: Sender) -> Self {
+        let (input, rx) = channel::unbounded();
+        let name = loaded.name;
+        let owners = config.twitch.owners.clone();
+        let handle = thread::spawn(move || {
+            let backoff =
+                connection::Backoff::new(Duration::from_secs(1), Duration::from_secs(5 * 60));
+            Supervisor::new(&loaded, &owners, backoff).run(rx, tx)
+        });
+        Self {
+            name,
+            input,
+            handle,
+        }
+    }
+
+    // waits for the module to finish what it is doing
+    fn stop(self) {
+        drop(self.input);
+        if self.handle.join().is_err() {
+            warn!("module {} didn't stop cleanly", self.name)
+        }
+    }
+}
+
+// !module list|enable|disable|reload <name>
+fn manage_modules(
+    req: &Request,
+    config: &mut Config,
+    modules: &mut module::Manager,
+    running: &mut Vec<Running>,
+    tx: &Sender,
+) -> Option<Response> {
+    let target = req.target();
+    let mut args = req.args_iter();
+    let (cmd, name) = (args.next(), args.next());
+    if let Some("list") | None = cmd {
+        let (mut enabled, mut disabled) = (vec![], vec![]);
+        for name in modules.names() {
+            if !running.iter().any(|m| m.name == name) {
+                disabled.push(name.to_string());
+                continue;
+            }
+            match crate::supervisor::status(name).filter(|s| s.panics > 0) {
+                Some(status) => enabled.push(format!("{} ({} panics)", name, status.panics)),
+                None => enabled.push(name.to_string()),
+            }
+        }
+        let list = |list: Vec<String>| match list.len() {
+            0 => "none".to_string(),
+            _ => list.join(", "),
+        };
+        return privmsg!(
+            target,
+            "enabled: {} | disabled: {}",
+            list(enabled),
+            list(disabled)
+        );
+    }
+
+    let name = match name.map(|name| (name, modules.find(name))) {
+        Some((_, Some(name))) => name,
+        Some((name, None)) => return privmsg!(target, "unknown module: {}", name),
+        None => return privmsg!(target, "usage: !module list|enable|disable|reload <name>"),
+    };
+    let pos = running.iter().position(|m| m.name == name);
+
+    match (cmd, pos) {
+        (Some("enable"), Some(..)) => privmsg!(target, "{} is already enabled", name),
+        (Some("enable"), None) => match modules.enable(name) {
+            Ok(loaded) => {
+                running.push(Running::start(loaded, config, tx.clone()));
+                if !config.enabled.iter().any(|m| m == name) {
+                    config.enabled.push(name.to_string());
+                }
+                config.save();
+                privmsg!(target, "enabled {}", name)
+            }
+            Err(err) => privmsg!(target, "cannot start {}: {:?}", name, err),
+        },
+
+        (Some("disable"), None) => privmsg!(target, "{} isn't enabled", name),
+        // it answers PINGs, without it twitch would hang up on us
+        (Some("disable"), Some(..)) if name == "Builtin" => {
+            privmsg!(target, "{} cannot be disabled", name)
+        }
+        (Some("disable"), Some(pos)) => {
+            // the manager shuts it down once its thread has stopped
+            running.remove(pos).stop();
+            modules.disable(name);
+            config.enabled.retain(|m| m != name);
+            for channel in config.channels.values_mut() {
+                channel.enabled.retain(|m| m != name);
+            }
+            config.save();
+            privmsg!(target, "disabled {}", name)
+        }
+
+        (Some("reload"), None) => privmsg!(target, "{} isn't enabled", name),
+        (Some("reload"), Some(..)) => match modules.reload(name)? {
+            Ok(..) => privmsg!(target, "reloaded {}", name),
+            Err(err) => privmsg!(target, "cannot reload {}: {:?}", name, err),
+        },
+
+        _ => privmsg!(target, "usage: !module list|enable|disable|reload <name>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ping;
+    impl Module for Ping {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            // the test database is per-thread, so this can't look up the user for a reply
+            req.search("!ping")?;
+            privmsg!(req.target(), "pong")
+        }
+    }
+
+    // counts the pings it answered, and keeps the count when it's reloaded
+    #[derive(Default)]
+    struct Pings(u64);
+    impl Module for Pings {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            req.search("!ping")?;
+            self.0 += 1;
+            privmsg!(req.target(), "pong {}", self.0)
+        }
+
+        fn save_state(&self) -> Option<serde_json::Value> {
+            Some(self.0.into())
+        }
+
+        fn restore_state(&mut self, state: serde_json::Value) {
+            self.0 = state.as_u64().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn run_in_memory() {
+        let (conn, mut server) = irc::MemoryConn::pair();
+        let mut modules = module::Manager::from(vec![LoadedModule::new("Ping", Ping)]);
+        let mut config = Config::default();
+        config.enabled.push("Ping".into());
+        config.channels.insert(
+            "disabled".into(),
+            config::Channel {
+                disabled: vec!["Ping".into()],
+                ..Default::default()
+            },
+        );
+        let bot =
+            thread::spawn(move || Bot::run(conn, &mut config, &mut modules, &Default::default()));
+
+        let timeout = Duration::from_secs(5);
+        let mut registration = vec![];
+        while registration.len() < 5 {
+            registration.push(server.recv_timeout(timeout).expect("registration"));
+        }
+        assert_eq!(registration[0], "CAP REQ :twitch.tv/tags");
+        assert!(registration[3].starts_with("PASS "));
+        assert_eq!(registration[4], "NICK shaken_bot");
+
+        use irc::Transport as _;
+        server.write_line(
+            "@badges=;color=#FF0000;display-name=shaken_bot;user-id=42 :tmi.twitch.tv \
+             GLOBALUSERSTATE",
+        );
+        for channel in &["#disabled", "#test"] {
+            server.write_line(&format!(
+                "@user-id=1000;display-name=test;color=#FFFFFF :test!user@irc.test PRIVMSG {} \
+                 :!ping",
+                channel
+            ));
+        }
+        assert_eq!(
+            server.recv_timeout(timeout).expect("response"),
+            "PRIVMSG #test :pong"
+        );
+
+        // hanging up should stop the bot
+        server.close();
+        assert_eq!(bot.join().unwrap(), Disconnect::Closed);
+        assert!(server.recv_timeout(timeout).is_none());
+    }
+
+    #[test]
+    fn manage_modules() {
+        let (conn, mut server) = irc::MemoryConn::pair();
+        let mut modules = module::Manager::default();
+        modules.add("Ping", || Ok(Pings::default()));
+        let mut config = Config::default();
+        config.enabled.push("Ping".into());
+        let mut channel = config::Channel::default();
+        channel.enabled.push("Ping".into());
+        config.channels.insert("test".into(), channel);
+        assert!(modules.load(&config).is_empty());
+
+        let bot = thread::spawn(move || {
+            let reason = Bot::run(conn, &mut config, &mut modules, &Default::default());
+            (reason, config, modules)
+        });
+
+        let timeout = Duration::from_secs(5);
+        for _ in 0..5 {
+            server.recv_timeout(timeout).expect("registration");
+        }
+
+        use irc::Transport as _;
+        server.write_line(
+            "@badges=;color=#FF0000;display-name=shaken_bot;user-id=42 :tmi.twitch.tv \
+             GLOBALUSERSTATE",
+        );
+        let line = |id: i64, data: &str| {
+            format!(
+                "@user-id={};display-name=test;color=#FFFFFF :test!user@irc.test PRIVMSG #test \
+                 :{}",
+                id, data
+            )
+        };
+        let owner = Config::default().twitch.owners[0];
+        let mut send = |lines: &[(i64, &str)]| {
+            for (id, data) in lines {
+                server.write_line(&line(*id, data));
+            }
+            server.recv_timeout(timeout).expect("response")
+        };
+
+        assert_eq!(
+            send(&[(owner, "!module disable ping")]),
+            "PRIVMSG #test :disabled Ping"
+        );
+        // the ping isn't answered, so the next response is for the list
+        assert_eq!(
+            send(&[(owner, "!ping"), (owner, "!module list")]),
+            "PRIVMSG #test :enabled: none | disabled: Ping"
+        );
+
+        assert_eq!(
+            send(&[(owner, "!module enable Ping")]),
+            "PRIVMSG #test :enabled Ping"
+        );
+        assert_eq!(send(&[(owner, "!ping")]), "PRIVMSG #test :pong 1");
+        assert_eq!(
+            send(&[(owner, "!module reload Ping")]),
+            "PRIVMSG #test :reloaded Ping"
+        );
+        // it was rebuilt with the count it had
+        assert_eq!(send(&[(owner, "!ping")]), "PRIVMSG #test :pong 2");
+
+        // only owners can manage modules
+        assert_eq!(
+            send(&[(1000, "!module disable Ping"), (owner, "!module list")]),
+            "PRIVMSG #test :enabled: Ping | disabled: none"
+        );
+
+        server.close();
+        let (reason, config, modules) = bot.join().unwrap();
+        assert_eq!(reason, Disconnect::Closed);
+        assert!(config.enabled.iter().any(|m| m == "Ping"));
+        // disabling it took it out of the channels too
+        assert!(config.channels["test"].enabled.is_empty());
+        assert_eq!(modules.loaded().len(), 1);
+    }
+
+    #[test]
+    fn reconnect() {
+        let (conn, mut server) = irc::MemoryConn::pair();
+        let bot = thread::spawn(move || {
+            Bot::run(
+                conn,
+                &mut Config::default(),
+                &mut Default::default(),
+                &Default::default(),
+            )
+        });
+
+        use irc::Transport as _;
+        server.write_line(":tmi.twitch.tv RECONNECT");
+        assert_eq!(bot.join().unwrap(), Disconnect::Reconnect);
+    }
+
+    #[test]
+    fn ping_timeout() {
+        let (conn, server) = irc::MemoryConn::pair();
+        let keepalive = connection::Keepalive {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(100),
+        };
+        let (bot, events) = Bot::create_with(conn, keepalive, None, Default::default());
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(server.recv_timeout(timeout).unwrap(), "PING :shaken");
+
+        // never answer it
+        for _ in events {}
+        assert_eq!(bot.disconnect_reason(), Some(Disconnect::Timeout));
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+        struct Slow;
+        impl Module for Slow {
+            fn command(&mut self, req: &Request) -> Option<Response> {
+                if req.search("!join").is_some() {
+                    return join("#test");
+                }
+                req.search("!slow")?;
+                STARTED.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(200));
+                privmsg!(req.target(), "done")
+            }
+            fn on_shutdown(&mut self) {
+                SHUT_DOWN.store(true, Ordering::SeqCst)
+            }
+        }
+
+        let (conn, mut server) = irc::MemoryConn::pair();
+        let mut modules = module::Manager::from(vec![LoadedModule::new("Slow", Slow)]);
+        let mut config = Config::default();
+        config.enabled.push("Slow".into());
+        let shutdown = connection::Shutdown::default();
+        let bot = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || Bot::run(conn, &mut config, &mut modules, &shutdown))
+        };
+
+        let timeout = Duration::from_secs(5);
+        for _ in 0..5 {
+            server.recv_timeout(timeout).expect("registration");
+        }
+
+        use irc::Transport as _;
+        server.write_line(
+            "@badges=;color=#FF0000;display-name=shaken_bot;user-id=42 :tmi.twitch.tv \
+             GLOBALUSERSTATE",
+        );
+        let line = |data: &str| {
+            format!(
+                "@user-id=1000;display-name=test;color=#FFFFFF :test!user@irc.test PRIVMSG #test \
+                 :{}",
+                data
+            )
+        };
+        server.write_line(&line("!join"));
+        assert_eq!(server.recv_timeout(timeout).unwrap(), "JOIN #test");
+
+        server.write_line(&line("!slow"));
+        while !STARTED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!shutdown.request());
+
+        // the module finishes what it was doing before we leave
+        assert_eq!(server.recv_timeout(timeout).unwrap(), "PRIVMSG #test :done");
+        assert_eq!(server.recv_timeout(timeout).unwrap(), "PART #test");
+        assert_eq!(server.recv_timeout(timeout).unwrap(), "QUIT :shutting down");
+        assert_eq!(bot.join().unwrap(), Disconnect::Shutdown);
+        assert!(SHUT_DOWN.load(Ordering::SeqCst));
+    }
+}
@@ -32,13 +32,13 @@ impl From<&str> for RGB {
             _ => return Self::default(),
         };
 
-        u32::from_str_radix(&s, 16)
-            .and_then(|s| {
-                Ok(RGB(
+        u32::from_str_radix(s, 16)
+            .map(|s| {
+                RGB(
                     ((s >> 16) & 0xFF) as u8,
                     ((s >> 8) & 0xFF) as u8,
                     (s & 0xFF) as u8,
-                ))
+                )
             })
             .unwrap_or_default()
     }
@@ -80,7 +80,7 @@ impl fmt::Display for HSL {
 
 impl From<RGB> for HSL {
     fn from(RGB(r, g, b): RGB) -> Self {
-        #![allow(clippy::unknown_clippy_lints, clippy::many_single_char_names)]
+        #![allow(unknown_lints, clippy::many_single_char_names)]
         use std::cmp::{max, min};
 
         let max = max(max(r, g), b);
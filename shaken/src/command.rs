@@ -2,28 +2,41 @@ use crate::prelude::*;
 use log::*;
 
 // this is used so modules can express their commands
-pub struct Command<T>
-where
-    T: Module + ?Sized,
-{
-    name: String,
+pub struct Command<T: ?Sized> {
+    pub(crate) name: String,
+    pub(crate) help: Option<String>,
+    pub(crate) args: Vec<Arg>,
     func: fn(&mut T, &Request) -> Option<Response>,
 }
 
-impl<T> Command<T>
-where
-    T: Module,
-{
+impl<T> Command<T> {
     pub fn new<S>(name: S, func: fn(&mut T, &Request) -> Option<Response>) -> Self
     where
         S: ToString,
     {
         Self {
             name: name.to_string(),
+            help: None,
+            args: vec![],
             func,
         }
     }
 
+    /// What the command does, for `!help`
+    pub fn help<S>(mut self, help: S) -> Self
+    where
+        S: ToString,
+    {
+        self.help.replace(help.to_string());
+        self
+    }
+
+    /// The arguments the command takes, see `CommandMap::create`
+    pub fn args(mut self, args: &[Arg]) -> Self {
+        self.args = args.to_vec();
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -1,23 +1,49 @@
 use hashbrown::HashMap;
 use log::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::path::{Path, PathBuf};
 
+use crate::cooldown::Cooldown;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub enabled: Vec<String>,
     pub twitch: Twitch,
     pub shakespeare: Shakespeare,
     pub invest: Invest,
+    #[serde(default)]
+    pub messages: Messages,
+    #[serde(default)]
+    pub cooldowns: Cooldowns,
+    #[serde(rename = "channel", default, skip_serializing_if = "HashMap::is_empty")]
+    pub channels: HashMap<String, Channel>, // per-channel overrides, keyed by the twitch channel
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Twitch {
     pub address: String,
     pub port: u32,
+    #[serde(default)]
+    pub tls: bool, // use port 6697 for tls
+    #[serde(default)]
+    pub websocket: Option<String>, // e.g. wss://irc-ws.chat.twitch.tv, used instead of address:port
     pub name: String,
     pub owners: Vec<i64>,
-    pub channel: String,
+    // twitch channels, not irc channels
+    #[serde(alias = "channel", deserialize_with = "one_or_many")]
+    pub channels: Vec<String>,
+    #[serde(default)]
+    pub record: Option<String>, // append every line read and written to this file, as jsonl
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct Channel {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enabled: Vec<String>, // enabled in this channel, even if they aren't globally
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+    pub shakespeare: Option<Shakespeare>,
+    pub invest: Option<Invest>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -35,6 +61,45 @@ pub struct Invest {
     pub interval: usize,
     pub chance: f64,
     pub kappas: String,
+    #[serde(default)]
+    pub separate: bool, // in a channel override, keep a separate economy for that channel
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct Messages {
+    #[serde(default)]
+    pub overflow: Overflow,
+    #[serde(default)]
+    pub continuation: Option<String>, // e.g. "(cont)", added to every split part but the last
+    #[serde(default)]
+    pub replies: Replies,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct Cooldowns {
+    #[serde(default)]
+    pub whisper: bool, // whisper the time left to whoever is waiting on a cooldown
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub commands: HashMap<String, Cooldown>, // overrides, keyed by the command (e.g. "!uptime")
+}
+
+/// What to do with a response that is too long for a single message
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    #[default]
+    Split,
+    Truncate, // with an ellipsis
+    Whisper,  // whisper the user instead of flooding the channel
+}
+
+/// How a `Response::Reply` shows up in chat
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Replies {
+    #[default]
+    Thread, // a native reply to the message, using `reply-parent-msg-id`
+    Mention, // the message prefixed with `@display:`
 }
 
 impl Default for Config {
@@ -48,10 +113,13 @@ impl Default for Config {
                 .collect::<Vec<_>>(),
             twitch: Twitch {
                 address: "irc.chat.twitch.tv".into(),
-                port: 6667,
+                port: 6697,
+                tls: true,
+                websocket: None,
                 name: "shaken_bot".into(),
                 owners: vec![23196011],
-                channel: "museun".into(), // twitch channel, not irc channel
+                channels: vec!["museun".into()],
+                record: None,
             },
             shakespeare: Shakespeare {
                 interval: 5,
@@ -65,18 +133,61 @@ impl Default for Config {
                 chance: 1.0 / 2.0,
                 interval: 60,
                 kappas: "5:1,3:3,1:1".into(),
+                separate: false,
             },
+            messages: Messages::default(),
+            cooldowns: Cooldowns::default(),
+            channels: HashMap::new(),
         }
     }
 }
 
 impl Config {
+    /// Whether `module` should see events from `channel`
+    ///
+    /// Events that aren't from a channel (e.g. whispers) use the global list
+    pub fn is_enabled(&self, module: &str, channel: Option<&str>) -> bool {
+        let global = self.enabled.iter().any(|m| m == module);
+        match channel.and_then(|ch| self.channels.get(&channel_name(ch))) {
+            Some(ch) if ch.disabled.iter().any(|m| m == module) => false,
+            Some(ch) => global || ch.enabled.iter().any(|m| m == module),
+            None => global,
+        }
+    }
+
+    /// Whether `module` is enabled globally, or in any channel
+    pub fn is_enabled_anywhere(&self, module: &str) -> bool {
+        self.enabled.iter().any(|m| m == module)
+            || self
+                .channels
+                .values()
+                .any(|ch| ch.enabled.iter().any(|m| m == module))
+    }
+
+    pub fn shakespeare_for(&self, channel: &str) -> &Shakespeare {
+        self.channels
+            .get(&channel_name(channel))
+            .and_then(|ch| ch.shakespeare.as_ref())
+            .unwrap_or(&self.shakespeare)
+    }
+
+    pub fn invest_for(&self, channel: &str) -> &Invest {
+        self.channels
+            .get(&channel_name(channel))
+            .and_then(|ch| ch.invest.as_ref())
+            .unwrap_or(&self.invest)
+    }
+
     pub fn env(key: &str) -> Option<String> {
         let map = DotEnvLoader::load(".env").ok()?;
         map.get(key).cloned()
     }
 
     pub fn expect_env(key: &str) -> String {
+        if cfg!(test) {
+            return Self::env(key).unwrap_or_else(|| format!("test_{}", key.to_lowercase()));
+        }
+
         let mut map = DotEnvLoader::load(".env").expect("cannot load env vars");
         map.remove(key)
             .unwrap_or_else(|| abort!("env var '{}' not set", key))
@@ -171,13 +282,36 @@ impl DotEnvLoader {
     }
 }
 
+/// Turns an irc channel (`#Museun`) into a twitch channel (`museun`)
+pub fn channel_name(channel: &str) -> String {
+    channel.trim_start_matches('#').to_ascii_lowercase()
+}
+
+// so old configs with a single `channel = "name"` still work
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(list) => list,
+    })
+}
+
 pub fn get_config_file() -> Option<PathBuf> {
     use directories::ProjectDirs;
     ProjectDirs::from("com.github", "museun", "shaken").and_then(|dir| {
         let dir = dir.config_dir();
-        std::fs::create_dir_all(&dir)
+        std::fs::create_dir_all(dir)
             .ok()
-            .and_then(|_| Some(dir.join("shaken.toml")))
+            .map(|_| dir.join("shaken.toml"))
     })
 }
 
@@ -185,3 +319,47 @@ fn check_newer(_f: impl AsRef<Path>) -> bool {
     // TODO implement this garbage
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels() {
+        let mut config = Config::default();
+        let data = toml::to_string_pretty(&config).unwrap();
+        assert!(!data.contains("[channel"));
+
+        // the old single channel
+        let data = data.replace("channels = [\"museun\"]", "channel = \"museun\"");
+        let old: Config = toml::from_str(&data).unwrap();
+        assert_eq!(old.twitch.channels, vec!["museun"]);
+
+        config.enabled = vec!["Builtin".into(), "Invest".into()];
+        config.channels.insert(
+            "museun".into(),
+            Channel {
+                enabled: vec!["Shakespeare".into()],
+                disabled: vec!["Invest".into()],
+                invest: Some(Invest {
+                    line_value: 10,
+                    ..config.invest.clone()
+                }),
+                ..Channel::default()
+            },
+        );
+
+        let config: Config = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert!(config.is_enabled("Invest", None));
+        assert!(config.is_enabled("Invest", Some("#shaken_bot")));
+        assert!(!config.is_enabled("Invest", Some("#Museun")));
+        assert!(config.is_enabled("Shakespeare", Some("#museun")));
+        assert!(!config.is_enabled("Shakespeare", None));
+        assert!(config.is_enabled_anywhere("Shakespeare"));
+        assert!(!config.is_enabled_anywhere("TwitchPoll"));
+
+        assert_eq!(config.invest_for("#museun").line_value, 10);
+        assert_eq!(config.invest_for("#shaken_bot").line_value, 5);
+        assert_eq!(config.shakespeare_for("#museun").chance, 0.15);
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use once_cell::{sync::Lazy, sync_lazy};
+use rand::prelude::*;
+
+static STATS: Lazy<RwLock<Stats>> = sync_lazy! {
+    RwLock::new(Stats::default())
+};
+
+/// Why the Bot stopped reading from its connection
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Disconnect {
+    /// The remote end hung up, or a read failed
+    Closed,
+    /// Twitch asked us to reconnect (e.g. the server is restarting)
+    Reconnect,
+    /// Our PING wasn't answered in time
+    Timeout,
+    /// The Bot was dropped, or a shutdown was requested
+    Shutdown,
+}
+
+/// Statistics about the connections made by this process
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub connects: usize,   // successful connections
+    pub reconnects: usize, // successful connections after the first one
+    pub failures: usize,   // failed connection attempts
+    pub connected_at: Option<Instant>,
+    pub last_disconnect: Option<Disconnect>,
+}
+
+impl Stats {
+    /// How long the current connection has been up
+    pub fn uptime(&self) -> Option<Duration> {
+        self.connected_at.map(|at| at.elapsed())
+    }
+}
+
+/// Gets a snapshot of the connection statistics
+pub fn stats() -> Stats {
+    STATS.read().unwrap().clone()
+}
+
+pub fn record_connect() {
+    let mut stats = STATS.write().unwrap();
+    if stats.connects > 0 {
+        stats.reconnects += 1;
+    }
+    stats.connects += 1;
+    stats.connected_at.replace(Instant::now());
+}
+
+pub fn record_failure() {
+    STATS.write().unwrap().failures += 1;
+}
+
+pub fn record_disconnect(reason: Disconnect) {
+    let mut stats = STATS.write().unwrap();
+    stats.connected_at.take();
+    stats.last_disconnect.replace(reason);
+}
+
+/// Asks the Bot to shut down cleanly, e.g. from a signal handler
+///
+/// Clones share the same flag
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Requests the shutdown, returning whether it had already been requested
+    pub fn request(&self) -> bool {
+        self.0.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for `timeout`, waking up early if a shutdown is requested.
+    /// Returns whether one was
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.is_requested() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+        }
+        self.is_requested()
+    }
+}
+
+/// Capped exponential backoff, with jitter
+///
+/// Each delay is somewhere between half of and the full `base * 2^attempts`,
+/// but never more than `max`
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(2), Duration::from_secs(5 * 60))
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            attempts: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .base
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(self.max, |d| d.min(self.max));
+        self.attempts = self.attempts.saturating_add(1);
+
+        let half = delay / 2;
+        half + half.mul_f64(thread_rng().gen::<f64>())
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+/// How often the Bot checks that the connection is alive
+#[derive(Debug, Copy, Clone)]
+pub struct Keepalive {
+    pub interval: Duration, // send a PING after being idle for this long
+    pub timeout: Duration,  // disconnect if the PONG takes longer than this
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+pub(crate) struct Health {
+    keepalive: Keepalive,
+    last_read: Instant,
+    pending: Option<Instant>, // when our PING was sent
+}
+
+impl Health {
+    pub fn new(keepalive: Keepalive, now: Instant) -> Self {
+        Self {
+            keepalive,
+            last_read: now,
+            pending: None,
+        }
+    }
+
+    pub fn on_read(&mut self, msg: &crate::irc::Message, now: Instant) {
+        self.last_read = now;
+        if msg.command() == "PONG" {
+            self.pending.take();
+        }
+    }
+
+    /// Returns a PING to send if the connection has been idle,
+    /// or an error if the last one was never answered
+    pub fn poll(&mut self, now: Instant) -> Result<Option<String>, Disconnect> {
+        match self.pending {
+            Some(sent) if now.duration_since(sent) >= self.keepalive.timeout => {
+                Err(Disconnect::Timeout)
+            }
+            Some(..) => Ok(None),
+            None if now.duration_since(self.last_read) >= self.keepalive.interval => {
+                self.pending.replace(now);
+                Ok(Some("PING :shaken".into()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::Message;
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        for expected in &[1, 2, 4, 8, 10, 10] {
+            let max = Duration::from_secs(*expected);
+            let delay = backoff.next_delay();
+            assert!(delay >= max / 2 && delay <= max, "{:?} {:?}", delay, max);
+        }
+        assert_eq!(backoff.attempts(), 6);
+
+        // doesn't overflow
+        for _ in 0..100 {
+            assert!(backoff.next_delay() <= Duration::from_secs(10));
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn health() {
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+        let mut health = Health::new(
+            Keepalive {
+                interval: secs(10),
+                timeout: secs(5),
+            },
+            now,
+        );
+
+        assert_eq!(health.poll(now + secs(5)), Ok(None));
+        // reading anything resets the idle time
+        health.on_read(
+            &Message::parse("PING :tmi.twitch.tv").unwrap(),
+            now + secs(5),
+        );
+        assert_eq!(health.poll(now + secs(10)), Ok(None));
+
+        let ping = health.poll(now + secs(15)).unwrap();
+        assert_eq!(ping, Some("PING :shaken".into()));
+        assert_eq!(health.poll(now + secs(16)), Ok(None));
+
+        health.on_read(
+            &Message::parse(":tmi.twitch.tv PONG tmi.twitch.tv :shaken").unwrap(),
+            now + secs(17),
+        );
+        assert_eq!(health.poll(now + secs(20)), Ok(None));
+
+        assert!(health.poll(now + secs(30)).unwrap().is_some());
+        assert_eq!(health.poll(now + secs(35)), Err(Disconnect::Timeout));
+    }
+
+    #[test]
+    fn stats() {
+        record_connect();
+        record_disconnect(Disconnect::Reconnect);
+        record_connect();
+        record_failure();
+
+        // other tests can be connecting at the same time
+        let stats = super::stats();
+        assert!(stats.connects >= 2);
+        assert!(stats.reconnects >= 1);
+        assert!(stats.failures >= 1);
+    }
+}
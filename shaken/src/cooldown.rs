@@ -0,0 +1,265 @@
+use crate::prelude::*;
+
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use once_cell::{sync::Lazy, sync_lazy};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(test))]
+static CONFIG: Lazy<RwLock<config::Cooldowns>> = sync_lazy! {
+    RwLock::new(Config::load().cooldowns)
+};
+
+// each test thread has its own, so tests can't change it under each other
+#[cfg(test)]
+thread_local!(static CONFIG: RwLock<config::Cooldowns> = RwLock::new(Config::load().cooldowns));
+
+// when each command was last used, per scope
+static LAST_USED: Lazy<Mutex<HashMap<Key, Used>>> = sync_lazy! {
+    Mutex::new(HashMap::new())
+};
+
+/// Who shares a cooldown
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Global,  // everyone, everywhere
+    User,    // each user, in every channel
+    Channel, // everyone in a channel
+}
+
+/// How long a command has to wait before it can be used again
+///
+/// Moderators and broadcasters aren't held to it
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Cooldown {
+    pub seconds: u64, // 0 turns it off
+    pub scope: Scope,
+}
+
+impl Cooldown {
+    pub const fn global(seconds: u64) -> Self {
+        Self {
+            seconds,
+            scope: Scope::Global,
+        }
+    }
+
+    pub const fn user(seconds: u64) -> Self {
+        Self {
+            seconds,
+            scope: Scope::User,
+        }
+    }
+
+    pub const fn channel(seconds: u64) -> Self {
+        Self {
+            seconds,
+            scope: Scope::Channel,
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        Duration::from_secs(self.seconds)
+    }
+
+    fn key(self, command: &str, req: &Request) -> Key {
+        let who = match self.scope {
+            Scope::Global => String::new(),
+            Scope::User => req.sender().to_string(),
+            Scope::Channel => config::channel_name(req.target()),
+        };
+        Key {
+            command: command.to_string(),
+            scope: self.scope,
+            who,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    command: String,
+    scope: Scope,
+    who: String,
+}
+
+struct Used {
+    at: Instant,
+    duration: Duration,
+    warned: bool, // someone was told they are waiting on it
+}
+
+#[cfg(not(test))]
+fn with_config<R>(f: impl FnOnce(&RwLock<config::Cooldowns>) -> R) -> R {
+    f(&CONFIG)
+}
+
+#[cfg(test)]
+fn with_config<R>(f: impl FnOnce(&RwLock<config::Cooldowns>) -> R) -> R {
+    CONFIG.with(f)
+}
+
+/// Uses the overrides and options from `config`, instead of the ones it was loaded with
+pub fn configure(config: &config::Cooldowns) {
+    with_config(|lock| *lock.write().unwrap() = config.clone())
+}
+
+/// Whether the time left on a cooldown should be whispered to the user
+pub fn whisper() -> bool {
+    with_config(|lock| lock.read().unwrap().whisper)
+}
+
+/// The cooldown for `command`, the override from the config or the `declared` one
+pub fn lookup(command: &str, declared: Option<Cooldown>) -> Option<Cooldown> {
+    with_config(|lock| {
+        lock.read()
+            .unwrap()
+            .commands
+            .get(command)
+            .copied()
+            .or(declared)
+            .filter(|cooldown| cooldown.seconds > 0)
+    })
+}
+
+/// How long until `req` can use `command` again, if it has to wait
+pub fn remaining(
+    command: &str,
+    cooldown: Cooldown,
+    req: &Request,
+    now: Instant,
+) -> Option<Duration> {
+    if req.is_from_moderator() || req.is_from_broadcaster() {
+        return None;
+    }
+    let last = LAST_USED
+        .lock()
+        .unwrap()
+        .get(&cooldown.key(command, req))?
+        .at;
+    cooldown
+        .duration()
+        .checked_sub(now.saturating_duration_since(last))
+        .filter(|left| *left > Duration::from_secs(0))
+}
+
+/// Whether this is the first time someone waiting on the cooldown for `command`
+/// should be told about it, since it was started
+pub fn first_warning(command: &str, cooldown: Cooldown, req: &Request) -> bool {
+    match LAST_USED
+        .lock()
+        .unwrap()
+        .get_mut(&cooldown.key(command, req))
+    {
+        Some(used) => !std::mem::replace(&mut used.warned, true),
+        None => false,
+    }
+}
+
+/// Starts the cooldown for `command`, from `req` using it at `now`
+///
+/// The cooldowns that have run out are forgotten
+pub fn start(command: &str, cooldown: Cooldown, req: &Request, now: Instant) {
+    let mut last_used = LAST_USED.lock().unwrap();
+    last_used.retain(|_, used| now.saturating_duration_since(used.at) < used.duration);
+    let used = Used {
+        at: now,
+        duration: cooldown.duration(),
+        warned: false,
+    };
+    last_used.insert(cooldown.key(command, req), used);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn request(channel: &str, userid: i64, badges: &str) -> Request {
+        testing::request(channel, userid, badges, "!cmd")
+    }
+
+    #[test]
+    fn scopes() {
+        let _db = get_connection();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(10);
+        let (a, b, other) = (
+            request("#a", 1, ""),
+            request("#a", 2, ""),
+            request("#b", 1, ""),
+        );
+
+        let user = Cooldown::user(30);
+        start("!scope_user", user, &a, now);
+        assert_eq!(
+            remaining("!scope_user", user, &a, later),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            remaining("!scope_user", user, &other, later),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(remaining("!scope_user", user, &b, later), None);
+
+        let channel = Cooldown::channel(30);
+        start("!scope_channel", channel, &a, now);
+        assert!(remaining("!scope_channel", channel, &b, later).is_some());
+        assert_eq!(remaining("!scope_channel", channel, &other, later), None);
+
+        let global = Cooldown::global(30);
+        start("!scope_global", global, &a, now);
+        assert!(remaining("!scope_global", global, &other, later).is_some());
+        assert_eq!(
+            remaining("!scope_global", global, &other, now + global.duration()),
+            None
+        );
+    }
+
+    #[test]
+    fn moderators_bypass() {
+        let _db = get_connection();
+        let now = Instant::now();
+        let cooldown = Cooldown::global(30);
+        start("!bypass", cooldown, &request("#a", 1, ""), now);
+        for badges in &["moderator/1", "broadcaster/1"] {
+            let req = request("#a", 2, badges);
+            assert_eq!(remaining("!bypass", cooldown, &req, now), None);
+        }
+        assert!(remaining("!bypass", cooldown, &request("#a", 2, "vip/1"), now).is_some());
+    }
+
+    #[test]
+    fn warnings() {
+        let _db = get_connection();
+        let now = Instant::now();
+        let (cooldown, req) = (Cooldown::global(30), request("#a", 1, ""));
+        assert!(!first_warning("!warned", cooldown, &req));
+
+        start("!warned", cooldown, &req, now);
+        assert!(first_warning("!warned", cooldown, &req));
+        assert!(!first_warning("!warned", cooldown, &req));
+
+        // starting it again warns again
+        start("!warned", cooldown, &req, now + cooldown.duration());
+        assert!(first_warning("!warned", cooldown, &req));
+    }
+
+    #[test]
+    fn forget_expired() {
+        let _db = get_connection();
+        let now = Instant::now();
+        let req = request("#a", 1, "");
+        let (short, long) = (Cooldown::global(1), Cooldown::global(60));
+        start("!expired_short", short, &req, now);
+        start("!expired_long", long, &req, now);
+
+        start("!expired_other", long, &req, now + Duration::from_secs(2));
+        let last_used = LAST_USED.lock().unwrap();
+        assert!(!last_used.contains_key(&short.key("!expired_short", &req)));
+        assert!(last_used.contains_key(&long.key("!expired_long", &req)));
+    }
+}
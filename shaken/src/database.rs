@@ -1,43 +1,105 @@
 use rusqlite::Connection;
 
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use once_cell::{sync::Lazy, sync_lazy};
+
+// used instead of the one in the data dir, see `use_copy`
+static OVERRIDE: Lazy<RwLock<Option<PathBuf>>> = sync_lazy! {
+    RwLock::new(None)
+};
+
 pub fn ensure_table(f: fn(&Connection)) -> Connection {
     let conn = get_connection();
     f(&conn);
     conn
 }
 
+/// Copies the database to a temporary file, and uses that from now on
+///
+/// Returns the path of the copy, which is left for the caller to remove
+pub fn use_copy() -> std::io::Result<PathBuf> {
+    let copy = std::env::temp_dir().join(format!("shaken-{}.db", std::process::id()));
+    let original = data_path();
+    if original.exists() {
+        std::fs::copy(&original, &copy)?;
+    }
+    use_path(&copy);
+    Ok(copy)
+}
+
+/// Uses the database at `path` from now on, instead of the one in the data dir
+pub fn use_path(path: impl Into<PathBuf>) {
+    *OVERRIDE.write().unwrap() = Some(path.into());
+}
+
+fn data_path() -> PathBuf {
+    static DATA_PATH: Lazy<PathBuf> = sync_lazy! {
+        use directories::ProjectDirs;
+        ProjectDirs::from("com.github", "museun", "shaken")
+            .and_then(|dir| {
+                let dir = dir.data_dir();
+                std::fs::create_dir_all(dir)
+                    .ok()
+                    .map(|_| dir.join("shaken.db"))
+            })
+            .expect("data dir should be available to store bot data files")
+    };
+    DATA_PATH.clone()
+}
+
+#[cfg(not(test))]
+fn path() -> PathBuf {
+    OVERRIDE.read().unwrap().clone().unwrap_or_else(data_path)
+}
+
+/// Names the database `get_connection` opens, so what is read from it can be kept
+/// apart from what was read from another one
+#[cfg(not(test))]
+pub(crate) fn id() -> String {
+    path().to_string_lossy().into_owned()
+}
+
 #[cfg(not(test))]
 pub fn get_connection() -> Connection {
-    use directories::ProjectDirs;
-    let dir = ProjectDirs::from("com.github", "museun", "shaken")
-        .and_then(|dir| {
-            let dir = dir.data_dir();
-            std::fs::create_dir_all(&dir)
-                .ok()
-                .and_then(|_| Some(dir.join("shaken.db")))
-        })
-        .expect("data dir should be available to store bot data files");
-    Connection::open(dir).unwrap()
+    Connection::open(path()).unwrap()
 }
 
+// each test thread has its own database
 #[cfg(test)]
-pub fn get_connection() -> Connection {
+thread_local!(static TEST_DB_ID: String = {
     use rand::{distributions::Alphanumeric, prelude::*};
-    use rusqlite::OpenFlags;
-
-    thread_local!(static TEST_DB_ID: String = format!(
+    format!(
         "file:{}?mode=memory&cache=shared",
         thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(10)
-        .collect::<String>()
-    ));
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .collect::<String>()
+    )
+});
+
+#[cfg(test)]
+pub(crate) fn id() -> String {
+    TEST_DB_ID.with(Clone::clone)
+}
 
-    TEST_DB_ID.with(|id| {
+#[cfg(test)]
+pub fn get_connection() -> Connection {
+    use rusqlite::OpenFlags;
+
+    fn open(id: &str) -> Connection {
         Connection::open_with_flags(
-            &id,
+            id,
             OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_READ_WRITE,
         )
         .unwrap()
-    })
+    }
+
+    // the memory db goes away when its last connection is closed, so keep one
+    // open for as long as the thread is alive
+    thread_local!(static KEEP_ALIVE: Connection = TEST_DB_ID.with(|id| open(id)));
+    KEEP_ALIVE.with(|_| {});
+
+    TEST_DB_ID.with(|id| open(id))
 }
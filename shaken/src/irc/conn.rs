@@ -1,5 +1,7 @@
+use super::Transport;
 use log::*;
-use std::io::{self, prelude::*, BufRead, BufReader, BufWriter, Lines};
+use native_tls::{HandshakeError, TlsConnector, TlsStream};
+use std::io::{self, prelude::*, BufRead, BufReader};
 use std::net::{self, TcpStream, ToSocketAddrs};
 use std::time::Duration;
 use std::{fmt, str};
@@ -7,6 +9,8 @@ use std::{fmt, str};
 pub enum ConnError {
     InvalidAddress(net::AddrParseError),
     CannotConnect(io::Error),
+    Tls(native_tls::Error),
+    WebSocket(Box<tungstenite::Error>),
 }
 
 impl fmt::Display for ConnError {
@@ -14,6 +18,8 @@ impl fmt::Display for ConnError {
         match self {
             ConnError::InvalidAddress(e) => write!(f, "invalid address: {}", e),
             ConnError::CannotConnect(e) => write!(f, "cannot connect: {}", e),
+            ConnError::Tls(e) => write!(f, "tls error: {}", e),
+            ConnError::WebSocket(e) => write!(f, "websocket error: {}", e),
         }
     }
 }
@@ -23,9 +29,51 @@ pub enum ReadStatus {
     Nothing,
 }
 
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Stream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.shutdown(net::Shutdown::Both),
+            Stream::Tls(s) => {
+                let _ = s.shutdown(); // try to send close_notify
+                s.get_ref().shutdown(net::Shutdown::Both)
+            }
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
 pub struct TcpConn {
-    reader: Lines<BufReader<TcpStream>>,
-    writer: BufWriter<TcpStream>,
+    stream: BufReader<Stream>,
+    buf: Vec<u8>, // partial line, kept between reads
 }
 
 impl TcpConn {
@@ -38,32 +86,63 @@ impl TcpConn {
             .expect("set read timeout");
 
         debug!("connected");
+        Ok(Self::new(Stream::Plain(conn)))
+    }
 
-        let reader = {
-            let conn = conn.try_clone().expect("clone stream");
-            BufReader::new(conn).lines()
-        };
+    /// Connects to `addr`, and then verifies the certificate against `domain`
+    pub fn connect_tls<A>(addr: A, domain: &str) -> Result<Self, ConnError>
+    where
+        A: ToSocketAddrs,
+    {
+        let connector = TlsConnector::new().map_err(ConnError::Tls)?;
+        Self::connect_tls_with(addr, domain, &connector)
+    }
 
-        let writer = {
-            let conn = conn.try_clone().expect("clone stream");
-            BufWriter::new(conn)
-        };
+    fn connect_tls_with<A>(
+        addr: A,
+        domain: &str,
+        connector: &TlsConnector,
+    ) -> Result<Self, ConnError>
+    where
+        A: ToSocketAddrs,
+    {
+        let conn = TcpStream::connect(&addr).map_err(ConnError::CannotConnect)?;
+        let tls = connector.connect(domain, conn).map_err(|err| match err {
+            HandshakeError::Failure(err) => ConnError::Tls(err),
+            HandshakeError::WouldBlock(..) => ConnError::CannotConnect(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "tls handshake was interrupted",
+            )),
+        })?;
+
+        // only set this after the handshake, it needs a blocking socket
+        tls.get_ref()
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .expect("set read timeout");
+
+        debug!("connected (tls)");
+        Ok(Self::new(Stream::Tls(Box::new(tls))))
+    }
 
-        Ok(Self { reader, writer })
+    fn new(stream: Stream) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            buf: vec![],
+        }
     }
 
     pub fn write(&mut self, data: &str) {
-        let writer = &mut self.writer;
+        let writer = self.stream.get_mut();
 
-        for part in split(data) {
+        for line in split(data) {
             // don't log the password
-            if &part[..4] != "PASS" {
-                let line = &part[..part.len() - 2];
-                trace!("--> {}", &line); // trim the \r\n
+            if !line.starts_with("PASS") {
+                trace!("--> {}", &line);
             }
 
             trace!("trying to write to socket");
-            if writer.write_all(part.as_bytes()).is_ok() {
+            let ok = writer.write_all(line.as_bytes()).is_ok();
+            if ok && writer.write_all(b"\r\n").is_ok() {
                 trace!("wrote to socket");
             } else {
                 error!("cannot write to socket");
@@ -74,33 +153,52 @@ impl TcpConn {
     }
 
     pub fn try_read(&mut self) -> Option<ReadStatus> {
-        let reader = &mut self.reader;
-
-        if let Some(line) = reader.next() {
-            return match line {
-                Ok(line) => {
-                    trace!("trying to read from socket");
-                    trace!("<-- {}", &line);
-                    Some(ReadStatus::Data(line))
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Some(ReadStatus::Nothing),
-                // TODO read docs on iocp to make sure this isn't a real error
-                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Some(ReadStatus::Nothing),
-                Err(e) => {
-                    warn!("unknown error: {:?}", e);
-                    None
-                }
-            };
-        };
+        match self.stream.read_until(b'\n', &mut self.buf) {
+            Ok(0) => {
+                warn!("couldn't read line");
+                None
+            }
+            Ok(_) if !self.buf.ends_with(b"\n") => Some(ReadStatus::Nothing),
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&self.buf)
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string();
+                self.buf.clear();
 
-        warn!("couldn't read line");
-        None
+                trace!("trying to read from socket");
+                trace!("<-- {}", &line);
+                Some(ReadStatus::Data(line))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Some(ReadStatus::Nothing),
+            // TODO read docs on iocp to make sure this isn't a real error
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Some(ReadStatus::Nothing),
+            Err(e) => {
+                warn!("unknown error: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Transport for TcpConn {
+    fn read_line(&mut self) -> Option<ReadStatus> {
+        self.try_read()
+    }
+
+    fn write_line(&mut self, data: &str) {
+        self.write(data)
+    }
+
+    fn close(&mut self) {
+        if let Err(err) = self.stream.get_mut().shutdown() {
+            warn!("cannot shutdown socket: {}", err)
+        }
     }
 }
 
 use std::borrow::Cow;
 
-enum SplitLine<'a> {
+pub(crate) enum SplitLine<'a> {
     List(Vec<Cow<'a, str>>),
     Single(Cow<'a, str>),
 }
@@ -117,28 +215,206 @@ impl<'a> IntoIterator for SplitLine<'a> {
     }
 }
 
-#[inline]
-fn split(raw: &str) -> SplitLine<'_> {
-    if raw.len() > 510 - 2 && raw.contains(':') {
-        let mut split = raw.splitn(2, ':').map(str::trim);
-        let (head, tail) = (split.next().unwrap(), split.next().unwrap());
-        let mut vec = vec![];
-        for part in tail
-            .as_bytes()
-            .chunks(510 - head.len() - 2)
-            .map(str::from_utf8)
-        {
-            match part {
-                Ok(part) => vec.push(format!("{} :{}\r\n", head, part).into()),
-                Err(err) => {
-                    warn!("dropping a slice: {}", err);
-                    continue;
-                }
+/// Splits `raw` into lines that fit on the wire, without the trailing \r\n
+///
+/// Only the trailing parameter is split, at the last space that fits. The tags,
+/// prefix and command are repeated on every line
+pub(crate) fn split(raw: &str) -> SplitLine<'_> {
+    const MAX: usize = 510;
+    if raw.len() <= MAX {
+        return SplitLine::Single(raw.into());
+    }
+
+    // skip over the tags and the prefix, they can't have the trailing parameter
+    let mut skip = 0;
+    for sigil in &['@', ':'] {
+        if raw[skip..].starts_with(*sigil) {
+            skip += raw[skip..].find(' ').map_or(raw.len() - skip, |i| i + 1);
+        }
+    }
+    let start = match raw[skip..].find(" :") {
+        Some(start) => skip + start,
+        None => return SplitLine::Single(raw.into()),
+    };
+
+    let (head, mut tail) = (&raw[..start], &raw[start + 2..]);
+    // always leave room for a character
+    let max = MAX.saturating_sub(head.len() + 2).max(4);
+    let mut vec = vec![];
+    while tail.len() > max {
+        let mut end = max;
+        while !tail.is_char_boundary(end) {
+            end -= 1;
+        }
+        // only break a word when it doesn't fit on a line of its own
+        if !tail[end..].starts_with(' ') {
+            if let Some(space) = tail[..end].rfind(' ').filter(|&i| i > 0) {
+                end = space;
             }
         }
+        let (part, rest) = tail.split_at(end);
+        vec.push(format!("{} :{}", head, part.trim_end()).into());
+        tail = rest.trim_start();
+    }
+    if !tail.is_empty() {
+        vec.push(format!("{} :{}", head, tail).into());
+    }
+    SplitLine::List(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use native_tls::{Certificate, Identity, TlsAcceptor};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn self_signed() -> (Vec<u8>, Vec<u8>) {
+        use openssl::asn1::Asn1Time;
+        use openssl::bn::BigNum;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::x509::{extension::SubjectAlternativeName, X509NameBuilder, X509};
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
 
-        return SplitLine::List(vec);
+        let cert = builder.build().to_pem().unwrap();
+        let key = key.private_key_to_pem_pkcs8().unwrap();
+        (cert, key)
     }
 
-    SplitLine::Single([raw, "\r\n"].concat().into())
+    fn read_line(conn: &mut TcpConn) -> Option<String> {
+        for _ in 0..100 {
+            match conn.try_read()? {
+                ReadStatus::Data(line) => return Some(line),
+                ReadStatus::Nothing => continue,
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn split_multibyte() {
+        let text = "日本語".repeat(100);
+        let line = format!("PRIVMSG #test :{}", text);
+        let parts = split(&line).into_iter().collect::<Vec<_>>();
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|s| s.len() <= 510));
+
+        let joined = parts
+            .iter()
+            .map(|s| s.trim_start_matches("PRIVMSG #test :"))
+            .collect::<String>();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn split_words() {
+        let head = "@reply-parent-msg-id=abc :bot!bot@tmi PRIVMSG #test :";
+        let text = vec!["héllo wörld"; 60].join(" ");
+        let line = format!("{}{}", head, text);
+        let parts = split(&line).into_iter().collect::<Vec<_>>();
+        assert_eq!(parts.len(), 2);
+
+        let mut words = vec![];
+        for part in &parts {
+            assert!(part.len() <= 510);
+            words.extend(part.strip_prefix(head).unwrap().split(' '));
+        }
+        assert_eq!(words, text.split_whitespace().collect::<Vec<_>>());
+
+        assert_eq!(split("PRIVMSG #test :short").into_iter().count(), 1);
+    }
+
+    #[test]
+    fn tls_conn() {
+        let (cert, key) = self_signed();
+        let identity = Identity::from_pkcs8(&cert, &key).unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(acceptor.accept(stream).unwrap());
+            stream
+                .get_mut()
+                .write_all(b"PING :tmi.twitch.tv\r\n:tmi.twitch.tv 001 shaken_bot :Welcome\r\n")
+                .unwrap();
+
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            line
+        });
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+
+        let mut conn = match TcpConn::connect_tls_with(addr, "localhost", &connector) {
+            Ok(conn) => conn,
+            Err(err) => panic!("cannot connect: {}", err),
+        };
+
+        assert_eq!(read_line(&mut conn).unwrap(), "PING :tmi.twitch.tv");
+        assert_eq!(
+            read_line(&mut conn).unwrap(),
+            ":tmi.twitch.tv 001 shaken_bot :Welcome"
+        );
+
+        conn.write("PONG :tmi.twitch.tv");
+        assert_eq!(server.join().unwrap(), "PONG :tmi.twitch.tv\r\n");
+
+        // the server hung up
+        assert!(read_line(&mut conn).is_none());
+    }
+
+    #[test]
+    fn tls_bad_cert() {
+        let (cert, key) = self_signed();
+        let identity = Identity::from_pkcs8(&cert, &key).unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = acceptor.accept(stream);
+        });
+
+        // the self-signed cert isn't trusted by default
+        match TcpConn::connect_tls(addr, "localhost") {
+            Err(ConnError::Tls(..)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(..) => panic!("connected with an untrusted certificate"),
+        }
+    }
 }
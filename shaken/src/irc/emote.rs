@@ -0,0 +1,119 @@
+use std::ops::Range;
+
+/// An emote used in a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emote {
+    pub id: String, // newer emotes have ids like `emotesv2_...`
+    pub name: String,
+    pub range: Range<usize>, // in chars (code points) of the message, not bytes
+}
+
+impl Emote {
+    /// Finds the emotes from an `emotes` tag in `data`
+    ///
+    /// The tag looks like `25:0-4,12-16/1902:6-10`, where the ranges are inclusive
+    /// and count code points. Ranges that don't fit in `data` are skipped
+    pub fn parse(tag: &str, data: &str) -> Vec<Self> {
+        // byte offset of every char, and the end of the string
+        let offsets = data
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(data.len()))
+            .collect::<Vec<_>>();
+
+        let mut emotes = vec![];
+        for emote in tag.split('/').filter(|s| !s.is_empty()) {
+            let (id, ranges) = match emote.find(':') {
+                Some(pos) => (&emote[..pos], &emote[pos + 1..]),
+                None => continue,
+            };
+
+            for range in ranges.split(',') {
+                let mut parts = range.splitn(2, '-').map(str::parse::<usize>);
+                let (start, end) = match (parts.next(), parts.next()) {
+                    (Some(Ok(start)), Some(Ok(end))) if start <= end => (start, end + 1),
+                    _ => continue,
+                };
+                if end >= offsets.len() {
+                    continue;
+                }
+
+                emotes.push(Emote {
+                    id: id.to_string(),
+                    name: data[offsets[start]..offsets[end]].to_string(),
+                    range: start..end,
+                })
+            }
+        }
+
+        emotes.sort_unstable_by_key(|emote| emote.range.start);
+        emotes
+    }
+}
+
+/// Removes the `emotes` from `data`, leaving the words between them
+pub fn strip_emotes(data: &str, emotes: &[Emote]) -> String {
+    data.chars()
+        .enumerate()
+        .filter(|(i, _)| !emotes.iter().any(|e| e.range.contains(i)))
+        .map(|(_, ch)| ch)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emote(id: &str, name: &str, range: Range<usize>) -> Emote {
+        Emote {
+            id: id.into(),
+            name: name.into(),
+            range,
+        }
+    }
+
+    #[test]
+    fn parse_emotes() {
+        let data = "Kappa hello Kappa PogChamp";
+        assert_eq!(
+            Emote::parse("25:0-4,12-16/88:18-25", data),
+            vec![
+                emote("25", "Kappa", 0..5),
+                emote("25", "Kappa", 12..17),
+                emote("88", "PogChamp", 18..26),
+            ]
+        );
+        assert_eq!(Emote::parse("", data), vec![]);
+    }
+
+    #[test]
+    fn code_points() {
+        // the ranges count chars, so the byte offsets are all shifted
+        let data = "héllo wörld 🦀 Kappa";
+        let emotes = Emote::parse("emotesv2_abc:14-18", data);
+        assert_eq!(emotes, vec![emote("emotesv2_abc", "Kappa", 14..19)]);
+        assert_eq!(strip_emotes(data, &emotes), "héllo wörld 🦀");
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let data = "Kappa";
+        assert_eq!(Emote::parse("25:0-5", data), vec![]);
+        assert_eq!(Emote::parse("25:3-1/26:a-b/27", data), vec![]);
+        assert_eq!(
+            Emote::parse("25:0-4", data),
+            vec![emote("25", "Kappa", 0..5)]
+        );
+    }
+
+    #[test]
+    fn strip() {
+        let data = "Kappa hello Kappa world PogChamp";
+        let emotes = Emote::parse("25:0-4,12-16/88:24-31", data);
+        assert_eq!(strip_emotes(data, &emotes), "hello world");
+        assert_eq!(strip_emotes(data, &[]), data);
+    }
+}
@@ -0,0 +1,369 @@
+use super::{Badge, Message};
+use std::time::Duration;
+
+/// A typed view over the twitch-specific irc messages
+#[derive(Debug, Clone, PartialEq)]
+pub enum TwitchEvent {
+    /// RPL_WELCOME (001), registration was successful
+    Connected,
+    /// The server wants a PONG with this token
+    Ping {
+        token: String,
+    },
+    /// The server is going down, a new connection should be made
+    Reconnect,
+
+    Raid {
+        channel: String,
+        from: String,
+        viewers: usize,
+    },
+    Sub {
+        channel: String,
+        user: String,
+        tier: SubTier,
+        months: usize,
+        message: Option<String>,
+    },
+    GiftSub {
+        channel: String,
+        from: Option<String>, // None if it was anonymous
+        recipient: String,
+        tier: SubTier,
+    },
+    Host {
+        channel: String,
+        target: Option<String>, // None when hosting has stopped
+        viewers: Option<usize>,
+    },
+
+    Timeout {
+        channel: String,
+        user: String,
+        duration: Duration,
+    },
+    Ban {
+        channel: String,
+        user: String,
+    },
+    ChatCleared {
+        channel: String,
+    },
+    MessageDeleted {
+        channel: String,
+        user: String,
+        id: String,
+        message: String,
+    },
+
+    /// Only the settings that changed are sent after the initial join
+    RoomState {
+        channel: String,
+        slow: Option<Duration>,
+        emote_only: Option<bool>,
+        followers_only: Option<Duration>, // Some(0) for any follower
+        subs_only: Option<bool>,
+        r9k: Option<bool>,
+    },
+    /// Our state in the channel, sent on join and after we send a message
+    UserState {
+        channel: String,
+        badges: Vec<Badge>,
+    },
+    Notice {
+        channel: String,
+        msg_id: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SubTier {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+}
+
+impl SubTier {
+    fn parse(plan: &str) -> Option<Self> {
+        let tier = match plan {
+            "Prime" => SubTier::Prime,
+            "1000" => SubTier::Tier1,
+            "2000" => SubTier::Tier2,
+            "3000" => SubTier::Tier3,
+            _ => return None,
+        };
+        Some(tier)
+    }
+}
+
+impl TwitchEvent {
+    pub fn from_msg(msg: &Message) -> Option<Self> {
+        let channel = || msg.args.first().cloned();
+        let tag = |key: &str| msg.tags.get(key);
+        let parse = |key: &str| msg.tags.get(key).and_then(|s| s.parse::<i64>().ok());
+        let flag = |key: &str| parse(key).map(|n| n > 0);
+
+        let ev = match msg.command() {
+            "001" => TwitchEvent::Connected,
+            "PING" => TwitchEvent::Ping {
+                token: msg.data.clone()?,
+            },
+            "RECONNECT" => TwitchEvent::Reconnect,
+
+            "USERNOTICE" => match tag("msg-id")? {
+                "raid" => TwitchEvent::Raid {
+                    channel: channel()?,
+                    from: tag("msg-param-displayName")?.to_string(),
+                    viewers: tag("msg-param-viewerCount")?.parse().ok()?,
+                },
+                "sub" | "resub" => TwitchEvent::Sub {
+                    channel: channel()?,
+                    user: msg.tags.get_display()?.to_string(),
+                    tier: SubTier::parse(tag("msg-param-sub-plan")?)?,
+                    months: tag("msg-param-cumulative-months")
+                        .or_else(|| tag("msg-param-months"))
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(1),
+                    message: msg.data.clone(),
+                },
+                "subgift" | "anonsubgift" => TwitchEvent::GiftSub {
+                    channel: channel()?,
+                    from: match tag("msg-id")? {
+                        "anonsubgift" => None,
+                        _ => msg.tags.get_display().map(ToString::to_string),
+                    },
+                    recipient: tag("msg-param-recipient-display-name")?.to_string(),
+                    tier: SubTier::parse(tag("msg-param-sub-plan")?)?,
+                },
+                _ => return None,
+            },
+
+            "HOSTTARGET" => {
+                // :tmi.twitch.tv HOSTTARGET #channel :target viewers
+                let mut data = msg.data.as_ref()?.split_whitespace();
+                TwitchEvent::Host {
+                    channel: channel()?,
+                    target: data.next().filter(|s| *s != "-").map(ToString::to_string),
+                    viewers: data.next().and_then(|s| s.parse().ok()),
+                }
+            }
+
+            "CLEARCHAT" => match (msg.data.clone(), parse("ban-duration")) {
+                (Some(user), Some(secs)) => TwitchEvent::Timeout {
+                    channel: channel()?,
+                    user,
+                    duration: Duration::from_secs(secs as u64),
+                },
+                (Some(user), None) => TwitchEvent::Ban {
+                    channel: channel()?,
+                    user,
+                },
+                (None, _) => TwitchEvent::ChatCleared {
+                    channel: channel()?,
+                },
+            },
+            "CLEARMSG" => TwitchEvent::MessageDeleted {
+                channel: channel()?,
+                user: tag("login")?.to_string(),
+                id: tag("target-msg-id")?.to_string(),
+                message: msg.data.clone().unwrap_or_default(),
+            },
+
+            "ROOMSTATE" => TwitchEvent::RoomState {
+                channel: channel()?,
+                slow: parse("slow").map(|n| Duration::from_secs(n as u64)),
+                emote_only: flag("emote-only"),
+                // this is -1 when disabled
+                followers_only: parse("followers-only")
+                    .filter(|&n| n >= 0)
+                    .map(|n| Duration::from_secs(n as u64 * 60)),
+                subs_only: flag("subs-only"),
+                r9k: flag("r9k"),
+            },
+            "USERSTATE" => TwitchEvent::UserState {
+                channel: channel()?,
+                badges: msg.tags.get_badges().unwrap_or_default(),
+            },
+            "NOTICE" => TwitchEvent::Notice {
+                channel: channel()?,
+                msg_id: tag("msg-id")?.to_string(),
+                message: msg.data.clone().unwrap_or_default(),
+            },
+            _ => return None,
+        };
+        Some(ev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Option<TwitchEvent> {
+        TwitchEvent::from_msg(&Message::parse(input).unwrap())
+    }
+
+    #[test]
+    fn usernotice() {
+        assert_eq!(
+            parse(
+                "@badges=;display-name=museun;msg-id=raid;msg-param-displayName=museun;\
+                 msg-param-viewerCount=15 :tmi.twitch.tv USERNOTICE #shaken_bot"
+            ),
+            Some(TwitchEvent::Raid {
+                channel: "#shaken_bot".into(),
+                from: "museun".into(),
+                viewers: 15
+            })
+        );
+
+        assert_eq!(
+            parse(
+                "@display-name=museun;msg-id=resub;msg-param-cumulative-months=6;\
+                 msg-param-sub-plan=2000 :tmi.twitch.tv USERNOTICE #shaken_bot :hello world"
+            ),
+            Some(TwitchEvent::Sub {
+                channel: "#shaken_bot".into(),
+                user: "museun".into(),
+                tier: SubTier::Tier2,
+                months: 6,
+                message: Some("hello world".into()),
+            })
+        );
+
+        assert_eq!(
+            parse(
+                "@display-name=AnAnonymousGifter;msg-id=anonsubgift;msg-param-sub-plan=1000;\
+                 msg-param-recipient-display-name=museun :tmi.twitch.tv USERNOTICE #shaken_bot"
+            ),
+            Some(TwitchEvent::GiftSub {
+                channel: "#shaken_bot".into(),
+                from: None,
+                recipient: "museun".into(),
+                tier: SubTier::Tier1,
+            })
+        );
+
+        assert_eq!(
+            parse("@msg-id=ritual :tmi.twitch.tv USERNOTICE #shaken_bot"),
+            None
+        );
+    }
+
+    #[test]
+    fn clearchat() {
+        assert_eq!(
+            parse("@ban-duration=600 :tmi.twitch.tv CLEARCHAT #shaken_bot :museun"),
+            Some(TwitchEvent::Timeout {
+                channel: "#shaken_bot".into(),
+                user: "museun".into(),
+                duration: Duration::from_secs(600),
+            })
+        );
+        assert_eq!(
+            parse(":tmi.twitch.tv CLEARCHAT #shaken_bot :museun"),
+            Some(TwitchEvent::Ban {
+                channel: "#shaken_bot".into(),
+                user: "museun".into(),
+            })
+        );
+        assert_eq!(
+            parse(":tmi.twitch.tv CLEARCHAT #shaken_bot"),
+            Some(TwitchEvent::ChatCleared {
+                channel: "#shaken_bot".into(),
+            })
+        );
+        assert_eq!(
+            parse("@login=museun;target-msg-id=abc-123 :tmi.twitch.tv CLEARMSG #shaken_bot :Kappa"),
+            Some(TwitchEvent::MessageDeleted {
+                channel: "#shaken_bot".into(),
+                user: "museun".into(),
+                id: "abc-123".into(),
+                message: "Kappa".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn state() {
+        assert_eq!(
+            parse(
+                "@emote-only=0;followers-only=-1;r9k=0;slow=30;subs-only=0 :tmi.twitch.tv \
+                 ROOMSTATE #shaken_bot"
+            ),
+            Some(TwitchEvent::RoomState {
+                channel: "#shaken_bot".into(),
+                slow: Some(Duration::from_secs(30)),
+                emote_only: Some(false),
+                followers_only: None,
+                subs_only: Some(false),
+                r9k: Some(false),
+            })
+        );
+        assert_eq!(
+            parse("@emote-only=1 :tmi.twitch.tv ROOMSTATE #shaken_bot"),
+            Some(TwitchEvent::RoomState {
+                channel: "#shaken_bot".into(),
+                slow: None,
+                emote_only: Some(true),
+                followers_only: None,
+                subs_only: None,
+                r9k: None,
+            })
+        );
+        assert_eq!(
+            parse("@badges=moderator/1;color= :tmi.twitch.tv USERSTATE #shaken_bot"),
+            Some(TwitchEvent::UserState {
+                channel: "#shaken_bot".into(),
+                badges: vec![Badge::Moderator],
+            })
+        );
+        assert_eq!(
+            parse(
+                "@msg-id=slow_on :tmi.twitch.tv NOTICE #shaken_bot :This room is now in slow \
+                 mode."
+            ),
+            Some(TwitchEvent::Notice {
+                channel: "#shaken_bot".into(),
+                msg_id: "slow_on".into(),
+                message: "This room is now in slow mode.".into(),
+            })
+        );
+        assert_eq!(
+            parse(":tmi.twitch.tv HOSTTARGET #shaken_bot :museun 10"),
+            Some(TwitchEvent::Host {
+                channel: "#shaken_bot".into(),
+                target: Some("museun".into()),
+                viewers: Some(10),
+            })
+        );
+        assert_eq!(
+            parse(":tmi.twitch.tv HOSTTARGET #shaken_bot :- 0"),
+            Some(TwitchEvent::Host {
+                channel: "#shaken_bot".into(),
+                target: None,
+                viewers: Some(0),
+            })
+        );
+    }
+
+    #[test]
+    fn connection() {
+        assert_eq!(
+            parse(":tmi.twitch.tv 001 shaken_bot :Welcome, GLHF!"),
+            Some(TwitchEvent::Connected)
+        );
+        assert_eq!(
+            parse("PING :tmi.twitch.tv"),
+            Some(TwitchEvent::Ping {
+                token: "tmi.twitch.tv".into()
+            })
+        );
+        assert_eq!(
+            parse(":tmi.twitch.tv RECONNECT"),
+            Some(TwitchEvent::Reconnect)
+        );
+    }
+}
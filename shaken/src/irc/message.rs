@@ -1,5 +1,9 @@
 use crate::prelude::*;
 
+use std::fmt;
+
+use log::*;
+
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct Message {
     pub tags: irc::Tags,
@@ -9,61 +13,342 @@ pub struct Message {
     pub data: Option<String>,
 }
 
+/// Why a line couldn't be parsed as a `Message`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    EmptyLine,
+    MissingCommand,
+    InvalidPrefix,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::EmptyLine => write!(f, "the line was empty"),
+            ParseError::MissingCommand => write!(f, "the line has no command"),
+            ParseError::InvalidPrefix => write!(f, "the prefix is malformed"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 impl Message {
-    pub fn parse(input: &str) -> Message {
-        let (input, tags) =
-            if !input.starts_with(':') && !input.starts_with("PING") && input.starts_with('@') {
-                let pos = input.find(' ').unwrap();
-                let sub = &input[..pos];
-                let tags = irc::Tags::new(&sub);
-                (&input[pos + 1..], tags)
-            } else {
-                (input, irc::Tags::default())
-            };
-
-        let prefix = irc::Prefix::parse(&input);
+    pub fn parse(input: &str) -> Result<Message, ParseError> {
+        // only spaces separate the parts of a line
+        let input = input.trim_matches(' ').trim_end_matches([' ', '\r', '\n']);
+        if input.trim().is_empty() {
+            return Err(ParseError::EmptyLine);
+        }
+
+        let (input, tags) = if input.starts_with('@') {
+            let pos = input.find(' ').ok_or(ParseError::MissingCommand)?;
+            (
+                input[pos + 1..].trim_start_matches(' '),
+                irc::Tags::new(&input[..pos]),
+            )
+        } else {
+            (input, irc::Tags::default())
+        };
+
+        let prefix = if input.starts_with(':') {
+            let prefix = irc::Prefix::parse(input).ok_or(ParseError::InvalidPrefix)?;
+            Some(prefix)
+        } else {
+            None
+        };
 
         let skip = if prefix.is_some() { 1 } else { 0 };
         let mut args = input
-            .split_whitespace()
+            .split(' ')
+            .filter(|s| !s.is_empty())
             .skip(skip)
             .take_while(|s| !s.starts_with(':'))
             .map(|s| s.into());
 
-        let command = args.next().unwrap();
-        let data = if let Some(pos) = &input[1..].find(':') {
-            Some(input[*pos + 2..].into())
-        } else {
-            None
-        };
+        let command = args
+            .next()
+            .filter(|s: &String| !s.starts_with('@'))
+            .ok_or(ParseError::MissingCommand)?;
+        let data = input.find(" :").map(|pos| input[pos + 2..].into());
 
-        Self {
+        Ok(Self {
             tags,
             prefix,
             command,
             args: args.collect(),
             data,
-        }
+        })
     }
 
-    pub fn target(&self) -> &str {
-        let target = self.args.first().expect("should have a target");
-        let user = UserStore::get_bot(&get_connection()).expect("get our name");
+    /// Where a response to this should go
+    ///
+    /// For a message sent directly to us, this is the sender
+    pub fn target(&self) -> Option<&str> {
+        let target = self.args.first()?;
+        let user = UserStore::get_bot(&get_connection()).or_else(|| {
+            warn!("cannot get our name");
+            None
+        })?;
         if target.eq_ignore_ascii_case(&user.display) {
-            match self.prefix {
-                Some(irc::Prefix::User { ref nick, .. }) => &nick,
-                _ => unreachable!(),
-            }
+            self.nick()
         } else {
-            &target
+            Some(target)
+        }
+    }
+
+    /// The nick of the user that sent this, if a user sent it
+    pub fn nick(&self) -> Option<&str> {
+        match self.prefix {
+            Some(irc::Prefix::User { ref nick, .. }) => Some(nick),
+            _ => None,
         }
     }
 
-    pub fn expect_data(&self) -> &str {
-        self.data.as_ref().unwrap()
+    pub fn data(&self) -> Option<&str> {
+        self.data.as_deref()
     }
 
     pub fn command(&self) -> &str {
         &self.command
     }
+
+    /// The emotes in the data, in the order they appear
+    pub fn emotes(&self) -> Vec<irc::Emote> {
+        match (self.tags.get("emotes"), &self.data) {
+            (Some(tag), Some(data)) => irc::Emote::parse(tag, data),
+            _ => vec![],
+        }
+    }
+
+    /// The data with every emote removed
+    pub fn without_emotes(&self) -> String {
+        let data = self.data().unwrap_or_default();
+        super::emote::strip_emotes(data, &self.emotes())
+    }
+
+    /// Formats this message as a protocol line, without the trailing `\r\n`
+    pub fn to_wire(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tags.is_empty() {
+            write!(f, "{} ", self.tags)?;
+        }
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", prefix)?;
+        }
+        write!(f, "{}", self.command)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        if let Some(data) = &self.data {
+            write!(f, " :{}", data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emotes() {
+        let msg = Message::parse(
+            "@emotes=25:6-10/1902:18-22 :test!user@irc.test PRIVMSG #test :héllo Kappa wörld \
+             Keepo !",
+        )
+        .unwrap();
+        let emotes = msg.emotes();
+        assert_eq!(
+            emotes.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            vec!["Kappa", "Keepo"]
+        );
+        assert_eq!(emotes[1].range, 18..23);
+        assert_eq!(msg.without_emotes(), "héllo wörld !");
+
+        let msg = Message::parse(":test!user@irc.test PRIVMSG #test :no emotes").unwrap();
+        assert!(msg.emotes().is_empty());
+        assert_eq!(msg.without_emotes(), "no emotes");
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = &[
+            "PING :tmi.twitch.tv",
+            ":tmi.twitch.tv 001 shaken_bot :Welcome, GLHF!",
+            ":tmi.twitch.tv CAP * ACK :twitch.tv/tags",
+            ":shaken_bot!shaken_bot@shaken_bot.tmi.twitch.tv JOIN #museun",
+            ":shaken_bot.tmi.twitch.tv 353 shaken_bot = #museun :shaken_bot museun",
+            "@badges=broadcaster/1;color=#FF69B4;display-name=museun;emotes=;user-id=23196011 \
+             :museun!museun@museun.tmi.twitch.tv PRIVMSG #museun :hello: world :)",
+            "@login=foo;msg-id=raid;msg-param-viewerCount=9001;\
+             system-msg=9001\\sraiders\\sfrom\\sfoo\\shave\\sjoined\\s\\:) \
+             :tmi.twitch.tv USERNOTICE #museun",
+            "@ban-duration=600 :tmi.twitch.tv CLEARCHAT #museun :foo",
+            "@emote-only=0;slow=10 :tmi.twitch.tv ROOMSTATE #museun",
+        ];
+
+        for input in inputs {
+            let msg = Message::parse(input).unwrap();
+            assert_eq!(msg.to_wire(), *input);
+            assert_eq!(Message::parse(&msg.to_wire()), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn unescaped_tags() {
+        let msg = Message::parse(
+            "@display-name=foo\\sbar;system-msg=a\\:b\\\\c\\rd\\ne \
+             :tmi.twitch.tv USERNOTICE #museun",
+        )
+        .unwrap();
+        assert_eq!(msg.tags.get("display-name"), Some("foo bar"));
+        assert_eq!(msg.tags.get("system-msg"), Some("a;b\\c\rd\ne"));
+    }
+
+    #[test]
+    fn build_message() {
+        let mut tags = irc::Tags::default();
+        tags.insert("reply-parent-msg-id", "abc 123;");
+
+        let msg = Message {
+            tags,
+            prefix: None,
+            command: "PRIVMSG".into(),
+            args: vec!["#museun".into()],
+            data: Some("hello world".into()),
+        };
+
+        assert_eq!(
+            msg.to_string(),
+            "@reply-parent-msg-id=abc\\s123\\: PRIVMSG #museun :hello world"
+        );
+        assert_eq!(Message::parse(&msg.to_wire()), Ok(msg));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let inputs = &[
+            ("", ParseError::EmptyLine),
+            ("   \r\n", ParseError::EmptyLine),
+            ("@badges=", ParseError::MissingCommand),
+            ("@badges= ", ParseError::MissingCommand),
+            (":tmi.twitch.tv", ParseError::InvalidPrefix),
+            (": PRIVMSG #test", ParseError::InvalidPrefix),
+            (":foo!bar PRIVMSG #test", ParseError::InvalidPrefix),
+            (":foo@host!bar PRIVMSG #test", ParseError::InvalidPrefix),
+            (":tmi.twitch.tv :hello", ParseError::MissingCommand),
+        ];
+        for (input, err) in inputs {
+            assert_eq!(Message::parse(input), Err(err.clone()), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn fallible_accessors() {
+        let msg = Message::parse(":tmi.twitch.tv RECONNECT").unwrap();
+        assert_eq!(msg.target(), None);
+        assert_eq!(msg.nick(), None);
+        assert_eq!(msg.data(), None);
+        assert!(msg.emotes().is_empty());
+    }
+
+    // lines that have broken things before, or look like they could
+    const CORPUS: &[&str] = &[
+        "@",
+        "@ ",
+        "@;;;= PING",
+        "@a=b;c :",
+        ":",
+        ": ",
+        "::",
+        ":a!b@c",
+        ":a!@ X",
+        ":!@ X",
+        "PING",
+        "PING :",
+        " :",
+        "PRIVMSG #test :",
+        "PRIVMSG",
+        "@emotes=25:0-100 :a!b@c PRIVMSG #test :short",
+        "@emotes=25:5-2/:/::/25:-/25:1-1-1 :a!b@c PRIVMSG #test :Kappa",
+        "@emotes=25:0-0 :a!b@c PRIVMSG #test :🦀",
+        "@badges=/,//,subscriber/;badge-info=subscriber/x :a!b@c PRIVMSG #test :!hi",
+        "@ban-duration=-1 :tmi.twitch.tv CLEARCHAT #test :foo",
+        "@msg-id=sub :tmi.twitch.tv USERNOTICE",
+        ":tmi.twitch.tv HOSTTARGET #test :",
+        "@ @b/:! ;=a",
+        ":\r0é:é PING\r\\1 //",
+        "\r@\\ !\\:CLEARCHAT ,CLEARCHAT !",
+        "#test\r,;-\r ",
+        "@display-name=\\\\\\ :a!b@c PRIVMSG #test :\u{0}",
+    ];
+
+    fn check(input: &str) {
+        let msg = match Message::parse(input) {
+            Ok(msg) => msg,
+            Err(..) => return,
+        };
+
+        // none of these should panic either
+        let _ = msg.target();
+        let _ = msg.emotes();
+        let _ = msg.without_emotes();
+        let _ = msg.tags.badges();
+        let _ = irc::TwitchEvent::from_msg(&msg);
+        let _ = Request::try_from(&msg);
+
+        // whatever was parsed can be written out and parsed again
+        let again = Message::parse(&msg.to_wire())
+            .unwrap_or_else(|err| panic!("{:?} -> {:?}: {}", input, msg.to_wire(), err));
+        assert_eq!(again.command, msg.command, "{:?}", input);
+    }
+
+    #[test]
+    fn corpus() {
+        for input in CORPUS {
+            check(input)
+        }
+    }
+
+    #[test]
+    fn random_lines() {
+        use rand::prelude::*;
+
+        // mostly the characters that mean something in a line
+        const ALPHABET: &[char] = &[
+            '@', ':', ';', '=', '!', ' ', ' ', '#', '/', ',', '-', '\\', 'a', 'b', '1', '0', 'é',
+            '🦀', '\r',
+        ];
+        const PIECES: &[&str] = &[
+            "PRIVMSG",
+            "PING",
+            "USERNOTICE",
+            "CLEARCHAT",
+            "emotes=",
+            "badges=",
+            "msg-id=",
+            "#test",
+            "a!b@c",
+            "25:0-4",
+        ];
+
+        let mut rng = thread_rng();
+        for _ in 0..20000 {
+            let mut line = String::new();
+            for _ in 0..rng.gen_range(0, 40) {
+                if rng.gen_bool(0.2) {
+                    line.push_str(PIECES.choose(&mut rng).unwrap())
+                } else {
+                    line.push(*ALPHABET.choose(&mut rng).unwrap())
+                }
+            }
+            check(&line)
+        }
+    }
 }
@@ -1,9 +1,19 @@
 mod conn;
+mod emote;
+mod event;
 mod message;
 mod prefix;
+mod split;
 mod tags;
+mod transport;
+mod websocket;
 
 pub use self::conn::*;
-pub use self::message::Message;
+pub use self::emote::Emote;
+pub use self::event::{SubTier, TwitchEvent};
+pub use self::message::{Message, ParseError};
 pub use self::prefix::Prefix;
-pub use self::tags::{Badge, Kappa, Tags};
+pub use self::split::{split_message, truncate_message, MAX_MESSAGE_LENGTH};
+pub use self::tags::{Badge, Badges, Kappa, Tags};
+pub use self::transport::{MemoryConn, Transport};
+pub use self::websocket::WebSocketConn;
@@ -16,10 +16,13 @@ impl Prefix {
             return None;
         }
 
-        let s = input[1..input.find(' ')?].trim();
+        let s = &input[1..input.find(' ')?];
+        if s.is_empty() {
+            return None;
+        }
         match s.find('!') {
             Some(pos) => {
-                let at = s.find('@')?;
+                let at = pos + s[pos..].find('@')?;
                 Some(Prefix::User {
                     nick: s[..pos].into(),
                     user: s[pos + 1..at].into(),
@@ -30,3 +33,12 @@ impl Prefix {
         }
     }
 }
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Prefix::User { nick, user, host } => write!(f, "{}!{}@{}", nick, user, host),
+            Prefix::Server { host } => write!(f, "{}", host),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+/// Twitch drops anything after this many characters in a single message
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// Splits `text` into parts of at most `max` characters
+///
+/// Parts are broken on whitespace where possible, and a single word that is
+/// too long is broken between characters. If a `marker` is given, it is
+/// appended (after a space) to every part but the last, and counts towards `max`
+pub fn split_message(text: &str, max: usize, marker: Option<&str>) -> Vec<String> {
+    let text = text.trim();
+    if text.chars().count() <= max {
+        return vec![text.to_string()];
+    }
+
+    let marker = marker.filter(|s| !s.is_empty());
+    // leave room for the " marker", but always allow at least one character
+    let limit = match marker {
+        Some(marker) => max.saturating_sub(marker.chars().count() + 1).max(1),
+        None => max.max(1),
+    };
+
+    let mut parts = vec![];
+    let (mut part, mut len) = (String::new(), 0);
+    for word in text.split_whitespace() {
+        let mut word = word;
+        let mut count = word.chars().count();
+
+        if len > 0 && len + 1 + count > limit {
+            parts.push(std::mem::take(&mut part));
+            len = 0;
+        }
+
+        // the word doesn't fit in a part on its own
+        while count > limit {
+            let (head, tail) = word.split_at(byte_offset(word, limit));
+            parts.push(head.to_string());
+            word = tail;
+            count -= limit;
+        }
+
+        if len > 0 {
+            part.push(' ');
+            len += 1;
+        }
+        part.push_str(word);
+        len += count;
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+
+    if let Some(marker) = marker {
+        let last = parts.len() - 1;
+        for part in &mut parts[..last] {
+            part.push(' ');
+            part.push_str(marker);
+        }
+    }
+    parts
+}
+
+/// Truncates `text` to at most `max` characters, ending it with an ellipsis
+///
+/// The text is cut at the last word that fits, unless that would throw away
+/// more than half of it
+pub fn truncate_message(text: &str, max: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+
+    let head = &text[..byte_offset(text, max.saturating_sub(1))];
+    let head = match head.rfind(char::is_whitespace) {
+        Some(pos) if head[..pos].chars().count() >= max / 2 => &head[..pos],
+        _ => head,
+    };
+    format!("{}\u{2026}", head.trim_end())
+}
+
+// byte offset of the `n`th character, or the end of the string
+fn byte_offset(s: &str, n: usize) -> usize {
+    s.char_indices()
+        .nth(n)
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| s.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_messages() {
+        assert_eq!(split_message("hello world", 20, None), vec!["hello world"]);
+        assert_eq!(split_message("  hello  ", 5, Some("...")), vec!["hello"]);
+        assert_eq!(truncate_message("hello world", 11), "hello world");
+    }
+
+    #[test]
+    fn split_on_words() {
+        assert_eq!(
+            split_message("the quick brown fox jumps", 10, None),
+            vec!["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(
+            split_message("the quick brown fox jumps", 13, Some("(+)")),
+            vec!["the quick (+)", "brown fox (+)", "jumps"]
+        );
+
+        let text = "lorem ipsum ".repeat(100);
+        for part in split_message(&text, MAX_MESSAGE_LENGTH, Some("...")) {
+            assert!(part.chars().count() <= MAX_MESSAGE_LENGTH);
+            assert!(!part.starts_with(' '));
+        }
+    }
+
+    #[test]
+    fn split_long_words() {
+        assert_eq!(
+            split_message("abcdefghij", 4, None),
+            vec!["abcd", "efgh", "ij"]
+        );
+        assert_eq!(
+            split_message("hi abcdefghij", 4, None),
+            vec!["hi", "abcd", "efgh", "ij"]
+        );
+    }
+
+    #[test]
+    fn split_multibyte() {
+        let text = "日本語のテキスト".repeat(100);
+        let parts = split_message(&text, MAX_MESSAGE_LENGTH, None);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].chars().count(), MAX_MESSAGE_LENGTH);
+        assert_eq!(parts.concat(), text);
+
+        assert_eq!(
+            split_message("🦀🦀🦀 🦀🦀", 3, None),
+            vec!["🦀🦀🦀", "🦀🦀"]
+        );
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(
+            truncate_message("the quick brown fox jumps", 12),
+            "the quick\u{2026}"
+        );
+        assert_eq!(truncate_message("abcdefghij", 5), "abcd\u{2026}");
+        assert_eq!(truncate_message("a bcdefghij", 5), "a bc\u{2026}");
+        assert_eq!(truncate_message("日本語のテキスト", 4), "日本語\u{2026}");
+    }
+}
@@ -1,14 +1,16 @@
 use crate::color::RGB;
 
+use std::fmt;
 use std::ops::Range;
 use std::str::FromStr;
 
-use hashbrown::HashMap;
 use log::*;
 use serde::{Deserialize, Serialize};
 
+// in the order they were in, so they are displayed as they were read. a tag
+// without a value (`foo` rather than `foo=`) has `None`
 #[derive(Debug, Default, PartialEq, Clone)]
-pub struct Tags(HashMap<String, String>);
+pub struct Tags(Vec<(String, Option<String>)>);
 
 impl Tags {
     pub fn new(input: &str) -> Self {
@@ -16,15 +18,34 @@ impl Tags {
             return Self::default();
         }
 
-        let mut map = HashMap::new();
+        let mut tags = Self::default();
         let input = &input[1..];
-        for part in input.split_terminator(';') {
-            if let Some(index) = part.find('=') {
-                let (k, v) = (&part[..index], &part[index + 1..]);
-                map.insert(k.into(), v.into());
+        for part in input.split_terminator(';').filter(|s| !s.is_empty()) {
+            match part.find('=') {
+                Some(index) => tags.set(&part[..index], Some(unescape(&part[index + 1..]))),
+                None => tags.set(part, None),
             }
         }
-        Self { 0: map }
+        tags
+    }
+
+    pub fn insert<K, V>(&mut self, key: K, val: V)
+    where
+        K: ToString,
+        V: ToString,
+    {
+        self.set(&key.to_string(), Some(val.to_string()))
+    }
+
+    fn set(&mut self, key: &str, val: Option<String>) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = val,
+            None => self.0.push((key.to_string(), val)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     pub fn get_kappas(&self) -> Option<Vec<Kappa>> {
@@ -43,21 +64,21 @@ impl Tags {
     }
 
     pub fn get_badges(&self) -> Option<Vec<Badge>> {
-        let s = self
-            .get("badges")?
-            .split(',')
-            .map(|s| {
-                let mut t = s.split('/');
-                (t.next(), t.next()) // badge, version
-            })
-            .filter_map(|(s, _)| s.and_then(|s| Badge::from_str(s).ok()))
-            .collect::<Vec<_>>();
-        Some(s)
+        self.get("badges")?;
+        Some(self.badges().iter().collect())
+    }
+
+    /// The badges, with their versions and `badge-info`
+    pub fn badges(&self) -> Badges {
+        Badges::new(
+            self.get("badges").unwrap_or_default(),
+            self.get("badge-info").unwrap_or_default(),
+        )
     }
 
     pub fn get_color(&self) -> RGB {
         self.get("color")
-            .and_then(|s| Some(RGB::from(s)))
+            .map(RGB::from)
             .unwrap_or_else(|| RGB::from((255, 255, 255)))
     }
 
@@ -77,18 +98,82 @@ impl Tags {
 
     pub fn has_badge(&self, badge: Badge) -> bool {
         self.get_badges()
-            .and_then(|b| Some(b.contains(&badge)))
-            .unwrap_or_else(|| false)
+            .map(|b| b.contains(&badge))
+            .unwrap_or(false)
     }
 
     pub fn get<S>(&self, s: S) -> Option<&str>
     where
         S: AsRef<str>, // this should be a borrow
     {
-        self.0.get(s.as_ref()).map(AsRef::as_ref)
+        // a missing value is the same as an empty value
+        self.0
+            .iter()
+            .find(|(k, _)| k == s.as_ref())
+            .map(|(_, v)| v.as_deref().unwrap_or_default())
     }
 }
 
+impl fmt::Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "@")?;
+        for (i, (key, val)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            match val {
+                Some(val) => write!(f, "{}={}", key, escape(val))?,
+                None => write!(f, "{}", key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes a tag value, as described by the IRCv3 message-tags spec
+pub fn escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Unescapes a tag value, as described by the IRCv3 message-tags spec
+pub fn unescape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut iter = input.chars();
+    while let Some(ch) = iter.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match iter.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('\\') => out.push('\\'),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            // an invalid escape drops the backslash
+            Some(ch) => out.push(ch),
+            // and so does a trailing one
+            None => {}
+        }
+    }
+    out
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub enum Badge {
     Admin,
@@ -98,8 +183,13 @@ pub enum Badge {
     Subscriber,
     Staff,
     Turbo,
-    /*
-     * and bits */
+    Vip,
+    Founder,
+    Partner,
+    Premium, // prime gaming
+    Bits,
+    BitsLeader,
+    SubGifter,
 }
 
 impl FromStr for Badge {
@@ -113,12 +203,91 @@ impl FromStr for Badge {
             "subscriber" => Badge::Subscriber,
             "staff" => Badge::Staff,
             "turbo" => Badge::Turbo,
+            "vip" => Badge::Vip,
+            "founder" => Badge::Founder,
+            "partner" => Badge::Partner,
+            "premium" => Badge::Premium,
+            "bits" => Badge::Bits,
+            "bits-leader" => Badge::BitsLeader,
+            "sub-gifter" => Badge::SubGifter,
             _ => return Err(()),
         };
         Ok(res)
     }
 }
 
+/// The badges on a message, with their versions and any extra `badge-info`
+///
+/// Badges that aren't known are skipped
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Badges {
+    versions: Vec<(Badge, String)>, // e.g. (Bits, "1000")
+    info: Vec<(Badge, String)>,     // e.g. (Subscriber, "14"), the exact months
+}
+
+impl Badges {
+    pub fn new(badges: &str, info: &str) -> Self {
+        fn parse(input: &str) -> Vec<(Badge, String)> {
+            input
+                .split(',')
+                .filter_map(|s| {
+                    let mut t = s.splitn(2, '/');
+                    let badge = Badge::from_str(t.next()?).ok()?;
+                    Some((badge, t.next().unwrap_or_default().to_string()))
+                })
+                .collect()
+        }
+
+        Self {
+            versions: parse(badges),
+            info: parse(info),
+        }
+    }
+
+    pub fn has(&self, badge: Badge) -> bool {
+        self.versions.iter().any(|(b, _)| *b == badge)
+    }
+
+    pub fn version(&self, badge: Badge) -> Option<&str> {
+        self.versions
+            .iter()
+            .find(|(b, _)| *b == badge)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn info(&self, badge: Badge) -> Option<&str> {
+        self.info
+            .iter()
+            .find(|(b, _)| *b == badge)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Badge> + '_ {
+        self.versions.iter().map(|(b, _)| *b)
+    }
+
+    pub fn is_vip(&self) -> bool {
+        self.has(Badge::Vip)
+    }
+
+    /// Founders are subscribers too
+    pub fn is_subscriber(&self) -> bool {
+        self.has(Badge::Subscriber) || self.has(Badge::Founder)
+    }
+
+    /// How many months the user has been subscribed for
+    pub fn sub_months(&self) -> Option<u32> {
+        self.info(Badge::Subscriber)
+            .or_else(|| self.info(Badge::Founder))
+            .and_then(|s| s.parse().ok())
+    }
+
+    /// The amount of bits the bits badge is for (e.g. 100, 1000, 5000)
+    pub fn bits_tier(&self) -> Option<u32> {
+        self.version(Badge::Bits).and_then(|s| s.parse().ok())
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
 pub struct Kappa {
     pub ranges: Vec<Range<u16>>,
@@ -149,7 +318,7 @@ impl Kappa {
                 Some((s.next()?, s.next()?))
             })
             .filter_map(|(head, tail)| {
-                get_ranges(&tail).and_then(|ranges| {
+                get_ranges(tail).and_then(|ranges| {
                     Some(Kappa {
                         id: head.parse::<usize>().ok()?,
                         ranges,
@@ -160,6 +329,92 @@ impl Kappa {
     }
 }
 
+#[cfg(test)]
+mod escape_test {
+    use super::*;
+
+    #[test]
+    fn escape_values() {
+        let inputs = &[
+            ("", ""),
+            ("foo", "foo"),
+            ("foo bar", "foo\\sbar"),
+            ("a;b", "a\\:b"),
+            ("a\\b", "a\\\\b"),
+            ("\r\n", "\\r\\n"),
+            ("; \\\r\n", "\\:\\s\\\\\\r\\n"),
+        ];
+
+        for (raw, escaped) in inputs {
+            assert_eq!(escape(raw), *escaped);
+            assert_eq!(unescape(escaped), *raw);
+        }
+    }
+
+    #[test]
+    fn unescape_invalid() {
+        assert_eq!(unescape("\\b"), "b");
+        assert_eq!(unescape("foo\\"), "foo");
+        assert_eq!(unescape("foo\\\\"), "foo\\");
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let input = "@badge-info=;badges=moderator/1;display-name=foo\\sbar;msg-id=;\
+                     system-msg=a\\:b\\\\c";
+        let tags = Tags::new(input);
+        assert_eq!(tags.get("badge-info"), Some(""));
+        assert_eq!(tags.get("display-name"), Some("foo bar"));
+        assert_eq!(tags.get("system-msg"), Some("a;b\\c"));
+        assert_eq!(tags.to_string(), input);
+
+        let tags = Tags::new("@foo;bar=;baz=1");
+        assert_eq!(tags.get("foo"), Some(""));
+        assert_eq!(tags.to_string(), "@foo;bar=;baz=1");
+    }
+}
+
+#[cfg(test)]
+mod badge_test {
+    use super::*;
+
+    #[test]
+    fn badges() {
+        let tags = Tags::new(
+            "@badge-info=subscriber/14;badges=vip/1,subscriber/12,bits/1000,glitchcon2020/1",
+        );
+        assert_eq!(
+            tags.get_badges(),
+            Some(vec![Badge::Vip, Badge::Subscriber, Badge::Bits])
+        );
+
+        let badges = tags.badges();
+        assert!(badges.is_vip());
+        assert!(badges.is_subscriber());
+        assert_eq!(badges.version(Badge::Subscriber), Some("12"));
+        assert_eq!(badges.sub_months(), Some(14));
+        assert_eq!(badges.bits_tier(), Some(1000));
+        assert!(!badges.has(Badge::Moderator));
+    }
+
+    #[test]
+    fn founder() {
+        let badges = Tags::new("@badge-info=founder/3;badges=founder/0,bits-leader/2").badges();
+        assert!(badges.is_subscriber());
+        assert!(badges.has(Badge::BitsLeader));
+        assert_eq!(badges.sub_months(), Some(3));
+        assert_eq!(badges.bits_tier(), None);
+    }
+
+    #[test]
+    fn no_badges() {
+        let tags = Tags::new("@badge-info=;badges=");
+        assert_eq!(tags.get_badges(), Some(vec![]));
+        assert_eq!(tags.badges(), Badges::default());
+        assert_eq!(Tags::new("@color=").get_badges(), None);
+    }
+}
+
 #[cfg(test)]
 mod kappa_test {
     use super::*;
@@ -240,7 +495,7 @@ mod kappa_test {
         ];
 
         for (input, expect) in inputs {
-            let kappas = Kappa::parse(&input);
+            let kappas = Kappa::parse(input);
             assert_eq!(kappas.len(), expect.len());
             assert_eq!(kappas, *expect);
         }
@@ -0,0 +1,159 @@
+use super::conn::{split, ReadStatus};
+use crossbeam_channel as channel;
+use log::*;
+use std::time::Duration;
+
+/// A line-based connection that the Bot can be driven by
+pub trait Transport: Send {
+    /// Tries to read a single line (without the trailing \r\n)
+    ///
+    /// Returns `None` when the connection has been closed
+    fn read_line(&mut self) -> Option<ReadStatus>;
+
+    /// Writes a line, splitting it if its too long for the wire
+    fn write_line(&mut self, data: &str);
+
+    /// Closes the connection. Further reads will return `None`
+    fn close(&mut self);
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn read_line(&mut self) -> Option<ReadStatus> {
+        (**self).read_line()
+    }
+
+    fn write_line(&mut self, data: &str) {
+        (**self).write_line(data)
+    }
+
+    fn close(&mut self) {
+        (**self).close()
+    }
+}
+
+/// An in-memory duplex connection
+///
+/// Lines written to one end can be read from the other end
+pub struct MemoryConn {
+    tx: Option<channel::Sender<String>>,
+    rx: Option<channel::Receiver<String>>,
+}
+
+impl MemoryConn {
+    /// Creates both ends of the connection
+    pub fn pair() -> (Self, Self) {
+        let (left_tx, left_rx) = channel::unbounded();
+        let (right_tx, right_rx) = channel::unbounded();
+        (
+            Self {
+                tx: Some(left_tx),
+                rx: Some(right_rx),
+            },
+            Self {
+                tx: Some(right_tx),
+                rx: Some(left_rx),
+            },
+        )
+    }
+
+    /// Blocks until a line can be read, or the other end has closed
+    pub fn recv(&self) -> Option<String> {
+        self.rx.as_ref()?.recv().ok()
+    }
+
+    /// Blocks for up to `timeout` waiting for a line
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<String> {
+        self.rx.as_ref()?.recv_timeout(timeout).ok()
+    }
+}
+
+impl Transport for MemoryConn {
+    fn read_line(&mut self) -> Option<ReadStatus> {
+        // wait a bit so this behaves like the socket read timeout
+        match self.rx.as_ref()?.recv_timeout(Duration::from_millis(50)) {
+            Ok(line) => {
+                trace!("<-- {}", &line);
+                Some(ReadStatus::Data(line))
+            }
+            Err(channel::RecvTimeoutError::Timeout) => Some(ReadStatus::Nothing),
+            Err(channel::RecvTimeoutError::Disconnected) => {
+                warn!("couldn't read line");
+                None
+            }
+        }
+    }
+
+    fn write_line(&mut self, data: &str) {
+        let tx = match self.tx.as_ref() {
+            Some(tx) => tx,
+            None => {
+                error!("cannot write to a closed connection");
+                return;
+            }
+        };
+
+        for line in split(data) {
+            if !line.starts_with("PASS") {
+                trace!("--> {}", &line);
+            }
+            if tx.send(line.to_string()).is_err() {
+                error!("cannot write to connection");
+                return;
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.tx.take();
+        self.rx.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_conn() {
+        let (mut left, mut right) = MemoryConn::pair();
+
+        left.write_line("PING :foo");
+        match right.read_line() {
+            Some(ReadStatus::Data(line)) => assert_eq!(line, "PING :foo"),
+            _ => panic!("expected a line"),
+        }
+        match right.read_line() {
+            Some(ReadStatus::Nothing) => {}
+            _ => panic!("expected nothing"),
+        }
+
+        let long = format!("PRIVMSG #test :{}", "a".repeat(600));
+        right.write_line(&long);
+        let first = left.recv().unwrap();
+        let second = left.recv().unwrap();
+        assert_eq!(first.len(), 510);
+        assert_eq!(second, format!("PRIVMSG #test :{}", "a".repeat(105)));
+
+        right.write_line("PING :bar");
+        left.close();
+        assert!(right.read_line().is_none());
+        assert!(left.read_line().is_none());
+        assert_eq!(left.recv(), None);
+    }
+
+    #[test]
+    fn memory_conn_split() {
+        let (mut left, right) = MemoryConn::pair();
+        let text = vec!["日本語"; 60].join(" ");
+        left.write_line(&format!("PRIVMSG #test :{}", text));
+        drop(left);
+
+        let lines = std::iter::from_fn(|| right.recv()).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for line in &lines {
+            assert!(line.len() <= 510);
+            let data = line.trim_start_matches("PRIVMSG #test :");
+            assert!(data.split(' ').all(|word| word == "日本語"));
+        }
+    }
+}
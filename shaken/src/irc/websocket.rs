@@ -0,0 +1,177 @@
+use super::conn::{split, ConnError, ReadStatus};
+use super::Transport;
+use log::*;
+use std::collections::VecDeque;
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::{stream::MaybeTlsStream, Error, Message, WebSocket};
+
+/// A connection to an IRC-over-WebSocket server (e.g. `wss://irc-ws.chat.twitch.tv`)
+///
+/// Each line is sent as a single text frame
+pub struct WebSocketConn {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    lines: VecDeque<String>, // a frame can have more than one line in it
+}
+
+impl WebSocketConn {
+    pub fn connect(url: &str) -> Result<Self, ConnError> {
+        let (socket, _) =
+            tungstenite::connect(url).map_err(|err| ConnError::WebSocket(Box::new(err)))?;
+
+        // only set this after the handshake, it needs a blocking socket
+        let timeout = Some(Duration::from_millis(50));
+        let stream = match socket.get_ref() {
+            MaybeTlsStream::Plain(s) => s,
+            MaybeTlsStream::NativeTls(s) => s.get_ref(),
+            _ => {
+                let err = io::Error::other("unknown websocket stream");
+                return Err(ConnError::CannotConnect(err));
+            }
+        };
+        stream
+            .set_read_timeout(timeout)
+            .map_err(ConnError::CannotConnect)?;
+
+        debug!("connected (websocket)");
+        Ok(Self {
+            socket,
+            lines: VecDeque::new(),
+        })
+    }
+
+    pub fn write(&mut self, data: &str) {
+        for line in split(data) {
+            // don't log the password
+            if !line.starts_with("PASS") {
+                trace!("--> {}", &line);
+            }
+
+            if let Err(err) = self.socket.send(Message::Text(line.to_string())) {
+                error!("cannot write to websocket: {}", err);
+                return;
+            }
+        }
+    }
+
+    pub fn try_read(&mut self) -> Option<ReadStatus> {
+        if let Some(line) = self.lines.pop_front() {
+            trace!("<-- {}", &line);
+            return Some(ReadStatus::Data(line));
+        }
+
+        match self.socket.read() {
+            Ok(Message::Text(data)) => {
+                self.lines.extend(
+                    data.split("\r\n")
+                        .filter(|s| !s.is_empty())
+                        .map(ToString::to_string),
+                );
+                Some(ReadStatus::Nothing)
+            }
+            Ok(Message::Close(..)) => {
+                warn!("websocket was closed");
+                None
+            }
+            // pongs are queued up by tungstenite and sent on the next read/write
+            Ok(..) => Some(ReadStatus::Nothing),
+            Err(Error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                Some(ReadStatus::Nothing)
+            }
+            Err(Error::ConnectionClosed) | Err(Error::AlreadyClosed) => {
+                warn!("couldn't read line");
+                None
+            }
+            Err(e) => {
+                warn!("unknown error: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Transport for WebSocketConn {
+    fn read_line(&mut self) -> Option<ReadStatus> {
+        self.try_read()
+    }
+
+    fn write_line(&mut self, data: &str) {
+        self.write(data)
+    }
+
+    fn close(&mut self) {
+        if let Err(err) = self.socket.close(None) {
+            warn!("cannot close websocket: {}", err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn read_line(conn: &mut WebSocketConn) -> Option<String> {
+        for _ in 0..100 {
+            match conn.try_read()? {
+                ReadStatus::Data(line) => return Some(line),
+                ReadStatus::Nothing => continue,
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn websocket_conn() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            // twitch sends a few lines in a single frame
+            socket
+                .send(Message::Text(
+                    "PING :tmi.twitch.tv\r\n:tmi.twitch.tv 001 shaken_bot :Welcome\r\n".into(),
+                ))
+                .unwrap();
+
+            let line = match socket.read().unwrap() {
+                Message::Text(line) => line,
+                msg => panic!("unexpected message: {:?}", msg),
+            };
+            socket.close(None).unwrap();
+            line
+        });
+
+        let mut conn = match WebSocketConn::connect(&format!("ws://{}", addr)) {
+            Ok(conn) => conn,
+            Err(err) => panic!("cannot connect: {}", err),
+        };
+
+        assert_eq!(read_line(&mut conn).unwrap(), "PING :tmi.twitch.tv");
+        assert_eq!(
+            read_line(&mut conn).unwrap(),
+            ":tmi.twitch.tv 001 shaken_bot :Welcome"
+        );
+
+        conn.write("PONG :tmi.twitch.tv");
+        assert_eq!(server.join().unwrap(), "PONG :tmi.twitch.tv");
+
+        // the server hung up
+        assert!(read_line(&mut conn).is_none());
+    }
+
+    #[test]
+    fn websocket_bad_address() {
+        match WebSocketConn::connect("not a url") {
+            Err(ConnError::WebSocket(..)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(..) => panic!("connected to an invalid url"),
+        }
+    }
+}
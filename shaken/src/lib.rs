@@ -9,17 +9,26 @@ pub mod template;
 
 mod bot;
 mod command;
+mod ratelimit;
 mod registry;
 mod request;
 mod response;
+mod script;
 mod user;
 
 // useful things for use outside of the bot
+pub mod args;
 pub mod color;
 pub mod config;
+pub mod connection;
+pub mod cooldown;
 pub mod database;
 pub mod irc;
 pub mod module;
+pub mod permission;
+pub mod recorder;
+pub mod supervisor;
+pub mod trie;
 pub mod twitch;
 
 // actual bot modules
@@ -30,13 +39,17 @@ pub(crate) mod testing;
 
 // TODO: preludes are ugly. rework the re-exports
 pub mod prelude {
+    pub use crate::args::{self, Arg};
     pub use crate::bot::{Bot, Event, Receiver, Sender};
     pub use crate::color::{self, HSL, RGB};
     pub use crate::command::Command;
     pub use crate::config::{self, Config};
+    pub use crate::connection::{self, Disconnect};
+    pub use crate::cooldown::{self, Cooldown};
     pub use crate::database::{self, ensure_table, get_connection};
     pub use crate::irc;
-    pub use crate::module::{self, CommandMap, Error as ModuleError, Module};
+    pub use crate::module::{self, CommandMap, Error as ModuleError, LoadedModule, Module};
+    pub use crate::permission::{self, Role};
     pub use crate::request::Request;
     pub use crate::response::{join, multi, IrcCommand, Response};
     pub use crate::twitch::{self, TwitchClient};
@@ -46,9 +59,7 @@ pub mod prelude {
     pub use crate::template;
 
     pub use crate::registry::{
-        Command as RegistryCommand,
-        CommandBuilder,
-        Error as RegistryError,
+        Alias as RegistryAlias, Command as RegistryCommand, CommandBuilder, Error as RegistryError,
         Registry,
     };
 }
@@ -13,7 +13,7 @@ macro_rules! multi {
     (@one $($x:tt)*) => (());
     (@len $($e:expr),*) => (<[()]>::len(&[$(multi!(@one $e)),*]));
     ($($arg:expr),* $(,)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         let mut vec = Vec::with_capacity(multi!(@len $($arg),*));
         $( if let Some(arg) = $arg { vec.push(arg); } )*
         Some(Response::Multi{data: vec})
@@ -23,11 +23,11 @@ macro_rules! multi {
 #[macro_export]
 macro_rules! reply {
     ($f:expr, $($arg:tt)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Reply{data: format!($f, $($arg)*)})
     }};
     ($e:expr) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Reply{data: $e.to_string()})
     }}
 }
@@ -35,11 +35,11 @@ macro_rules! reply {
 #[macro_export]
 macro_rules! say {
     ($f:expr, $($arg:tt)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Say{data: format!($f, $($arg)*)})
     }};
     ($e:expr) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Say{data: $e.to_string()})
     }}
 }
@@ -47,11 +47,11 @@ macro_rules! say {
 #[macro_export]
 macro_rules! action {
     ($f:expr, $($arg:tt)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Action{data: format!($f, $($arg)*)})
     }};
     ($e:expr) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Action{data: $e.to_string()})
     }};
 }
@@ -59,11 +59,11 @@ macro_rules! action {
 #[macro_export]
 macro_rules! whisper {
     ($f:expr, $($arg:tt)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Whisper{data: format!($f, $($arg)*)})
     }};
     ($e:expr) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Whisper{data: $e.to_string()})
     }};
 }
@@ -71,11 +71,11 @@ macro_rules! whisper {
 #[macro_export]
 macro_rules! raw {
     ($f:expr, $($arg:tt)*) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Command{cmd: IrcCommand::Raw{ data: format!($f, $($arg)*)}})
     }};
     ($e:expr) => {{
-        use crate::prelude::Response;
+        use $crate::prelude::Response;
         Some(Response::Command{cmd: IrcCommand::Raw{ data: $e.to_string()}})
     }};
 }
@@ -83,7 +83,7 @@ macro_rules! raw {
 #[macro_export]
 macro_rules! privmsg {
     ($target:expr, $f:expr, $($arg:tt)*) => {{
-        use crate::prelude::{Response, IrcCommand};
+        use $crate::prelude::{Response, IrcCommand};
         Some(Response::Command {
             cmd: IrcCommand::Privmsg{
                 target: $target.to_string(),
@@ -93,7 +93,7 @@ macro_rules! privmsg {
     }};
 
     ($target:expr, $f:expr) => {{
-        use crate::prelude::{Response, IrcCommand};
+        use $crate::prelude::{Response, IrcCommand};
         Some(Response::Command {
             cmd: IrcCommand::Privmsg{
                 target: $target.to_string(),
@@ -103,70 +103,6 @@ macro_rules! privmsg {
     }};
 }
 
-#[macro_export]
-macro_rules! require_owner {
-    ($req:expr) => {{
-        if !$req.is_from_owner() {
-            return None;
-        };
-        $req
-    }};
-    ($req:expr, $reason:expr) => {{
-        if !$req.is_from_owner() {
-            return reply!($reason);
-        };
-        $req
-    }};
-}
-
-#[macro_export]
-macro_rules! require_broadcaster {
-    ($req:expr) => {{
-        if !$req.is_from_broadcaster() {
-            return None;
-        };
-        $req
-    }};
-    ($req:expr, $reason:expr) => {{
-        if !$req.is_from_broadcaster() {
-            return reply!($reason);
-        };
-        $req
-    }};
-}
-
-#[macro_export]
-macro_rules! require_moderator {
-    ($req:expr) => {{
-        if !$req.is_from_moderator() {
-            return None;
-        };
-        $req
-    }};
-    ($req:expr, $reason:expr) => {{
-        if !$req.is_from_moderator() {
-            return reply!($reason);
-        };
-        $req
-    }};
-}
-
-#[macro_export]
-macro_rules! require_privileges {
-    ($req:expr) => {{
-        if !$req.is_from_owner() && !$req.is_from_broadcaster() && !$req.is_from_moderator() {
-            return None;
-        };
-        $req
-    }};
-    ($req:expr, $reason:expr) => {{
-        if !$req.is_from_owner() && !$req.is_from_broadcaster() && !$req.is_from_moderator() {
-            return reply!($reason);
-        };
-        $req
-    }};
-}
-
 // unused but not forgotten
 #[macro_export]
 macro_rules! map {
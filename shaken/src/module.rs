@@ -1,77 +1,452 @@
 use crate::prelude::*;
 
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
 use hashbrown::HashMap;
 use log::*;
+use rusqlite::{types::ToSql, Connection, OptionalExtension, NO_PARAMS};
 
-type Func<T> = fn(&mut T, &Request) -> Option<Response>; // this is for you, clippy.
+struct Entry<T> {
+    command: Command<T>,
+    usage: String,
+}
 
-pub struct CommandMap<T>(Arc<HashMap<&'static str, Func<T>>>);
+pub struct CommandMap<T> {
+    namespace: String,
+    map: Arc<HashMap<String, Entry<T>>>,
+    cooldowns: Arc<HashMap<String, Cooldown>>, // kept apart so a clone can still be given them
+}
 
 impl<T> Clone for CommandMap<T> {
     fn clone(&self) -> Self {
-        CommandMap(Arc::clone(&self.0))
+        CommandMap {
+            namespace: self.namespace.clone(),
+            map: Arc::clone(&self.map),
+            cooldowns: Arc::clone(&self.cooldowns),
+        }
     }
 }
 
 impl<T> CommandMap<T> {
-    pub fn create<S>(
-        namespace: S,
-        commands: &[(&'static str, Func<T>)],
-    ) -> Result<CommandMap<T>, ModuleError>
+    /// Registers the `commands` under `namespace`, with what they do for `!help`
+    ///
+    /// Each command lists the arguments it takes with `Command::args`, and they are
+    /// parsed before it is called (see `Request::parsed`). If they can't be, the usage
+    /// for the command is replied instead. Commands that don't list any arguments get
+    /// the line as is
+    pub fn create<S>(namespace: S, commands: Vec<Command<T>>) -> Result<CommandMap<T>, ModuleError>
     where
         S: ToString,
     {
         let mut map = HashMap::new();
         let namespace = namespace.to_string();
-        for (k, v) in commands.iter() {
-            let cmd = CommandBuilder::command(*k)
+        for command in commands {
+            let usage = args::usage(command.name(), &command.args);
+            let mut cmd = CommandBuilder::command(command.name())
                 .namespace(namespace.clone())
-                .build();
+                .usage(&usage);
+            if let Some(help) = &command.help {
+                cmd = cmd.help(help)
+            }
+            let cmd = cmd.build();
 
             if let Err(RegistryError::AlreadyExists) = Registry::register(&cmd) {
                 warn!("{} already exists", cmd.name());
                 return Err(ModuleError::CommandAlreadyExists);
             }
-            map.insert(*k, *v);
+            map.insert(cmd.name().to_string(), Entry { command, usage });
         }
-        Ok(CommandMap(Arc::new(map)))
+        Ok(CommandMap {
+            namespace,
+            map: Arc::new(map),
+            cooldowns: Arc::default(),
+        })
     }
 
-    // TODO get rid of these dumb allocations
-    pub fn dispatch(&self, this: &mut T, req: &Request) -> Option<Response> {
-        let mut maybes = vec![];
-        for (cmd, func) in self.0.iter() {
-            if let Some(req) = req.search(cmd) {
-                maybes.push((cmd, func, req));
+    /// Adds other names for the `commands`, as `(alias, command)`
+    ///
+    /// These are resolved when the request is dispatched, see `Registry::resolve`
+    pub fn aliases(self, aliases: &[(&'static str, &'static str)]) -> Result<Self, ModuleError> {
+        for (alias, command) in aliases {
+            if !self.map.contains_key(*command) {
+                warn!("cannot alias unknown command: {}", command);
+                continue;
+            }
+            if let Err(RegistryError::AlreadyExists) =
+                Registry::add_alias(alias, command, Some(&self.namespace))
+            {
+                warn!("{} already exists", alias);
+                return Err(ModuleError::CommandAlreadyExists);
             }
         }
+        Ok(self)
+    }
 
-        if maybes.is_empty() {
-            return None;
+    /// Gives the `commands` a cooldown, which can be overridden in the config
+    pub fn cooldowns(mut self, commands: &[(&'static str, Cooldown)]) -> Self {
+        let mut cooldowns = (*self.cooldowns).clone();
+        for (k, cooldown) in commands {
+            if self.map.contains_key(*k) {
+                cooldowns.insert(k.to_string(), *cooldown);
+            } else {
+                warn!("cannot set a cooldown for unknown command: {}", k)
+            }
         }
+        self.cooldowns = Arc::new(cooldowns);
+        self
+    }
 
-        let first = maybes.remove(0);
-        let (_, func, req) = maybes.iter().fold(first, |acc, (cmd, func, req)| {
-            if cmd.len() < acc.0.len() {
-                acc
+    /// Sets the role the `commands` need by default. The rest can be used by everyone
+    ///
+    /// This can be changed with `!perm set`, see `permission::check`
+    pub fn permissions(self, commands: &[(&'static str, Role)]) -> Self {
+        for (k, role) in commands {
+            if self.map.contains_key(*k) {
+                permission::declare(k, *role)
             } else {
-                (cmd, func, req.clone()) // hmm
+                warn!("cannot set a role for unknown command: {}", k)
+            }
+        }
+        self
+    }
+
+    /// Calls the command the request starts with, if there is one
+    ///
+    /// Aliases are replaced with the command they are for. Only whole words match, and
+    /// the longest command of any module wins, see `Registry::find_prefix`. Nothing is
+    /// allocated unless a command matched
+    pub fn dispatch(&self, this: &mut T, req: &Request) -> Option<Response> {
+        let resolved = req.resolve_alias();
+        let req = resolved.as_ref().unwrap_or(req);
+        let (entry, rest) = Registry::find_prefix(req.args(), |cmd, rest| {
+            if cmd.namespace() != self.namespace {
+                return None;
+            }
+            self.map.get(cmd.name()).map(|entry| (entry, rest))
+        })??;
+        let cmd = entry.command.name();
+        let req = req.with_command(cmd, rest);
+
+        if !permission::check(cmd, &req) {
+            debug!("{} cannot use {}", req.sender(), cmd);
+            return None;
+        }
+
+        let now = Instant::now();
+        let cooldown = cooldown::lookup(cmd, self.cooldowns.get(cmd).copied());
+        if let Some(cooldown) = cooldown {
+            if let Some(left) = cooldown::remaining(cmd, cooldown, &req, now) {
+                debug!("{} is on cooldown for {}", cmd, req.sender());
+                // only once, so waiting on it can't be used to spam whispers
+                if !cooldown::whisper() || !cooldown::first_warning(cmd, cooldown, &req) {
+                    return None;
+                }
+                let args = template::TemplateArgs::new()
+                    .with("command", &cmd)
+                    .with(
+                        "remaining",
+                        &Duration::from_secs(left.as_secs().max(1)).as_readable_time(),
+                    )
+                    .build();
+                return whisper!(template::lookup("misc_cooldown", &args).unwrap());
+            }
+        }
+
+        let req = if entry.command.args.is_empty() {
+            req
+        } else {
+            match args::parse(&entry.command.args, req.args()) {
+                Ok(parsed) => req.with_parsed(parsed),
+                Err(err) => {
+                    return reply_template!(
+                        "misc_usage",
+                        ("error", &err.to_string()),
+                        ("usage", &entry.usage)
+                    )
+                }
+            }
+        };
+        if let Some(cooldown) = cooldown {
+            cooldown::start(cmd, cooldown, &req, now)
+        }
+        entry.command.call(this, &req)
+    }
+}
+
+type Factory = Arc<dyn Fn() -> Result<Box<dyn Module>, Error> + Send + Sync>;
+
+/// A created module, and the name it is enabled by in the config
+#[derive(Clone)]
+pub struct LoadedModule {
+    pub name: &'static str,
+    pub module: Arc<Mutex<Box<dyn Module>>>,
+    factory: Option<Factory>,
+}
+
+impl LoadedModule {
+    pub fn new<M>(name: &'static str, module: M) -> Self
+    where
+        M: Module + 'static,
+    {
+        Self {
+            name,
+            module: Arc::new(Mutex::new(Box::new(module))),
+            factory: None,
+        }
+    }
+
+    /// Creates the module with `create`, which is used again to rebuild it if it panics
+    pub fn supervised<M, F>(name: &'static str, create: F) -> Result<Self, Error>
+    where
+        M: Module + 'static,
+        F: Fn() -> Result<M, Error> + Send + Sync + 'static,
+    {
+        Self::from_factory(name, factory(create))
+    }
+
+    fn from_factory(name: &'static str, factory: Factory) -> Result<Self, Error> {
+        Ok(Self {
+            name,
+            module: Arc::new(Mutex::new(factory()?)),
+            factory: Some(factory),
+        })
+    }
+
+    /// Locks the module, even if it panicked while it was locked
+    pub fn lock(&self) -> MutexGuard<'_, Box<dyn Module>> {
+        self.module.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Saves the module's state to the database, if it has any
+    pub fn save_state(&self) {
+        let state = match self.lock().save_state() {
+            Some(state) => state,
+            None => return,
+        };
+        let conn = ensure_table(state_table);
+        if let Err(err) = conn.execute(
+            "INSERT OR REPLACE INTO ModuleState (name, state, saved_at) VALUES (?, ?, ?)",
+            &[
+                &self.name as &dyn ToSql,
+                &state.to_string(),
+                &(util::get_timestamp() as i64),
+            ],
+        ) {
+            warn!("cannot save the state of {}: {}", self.name, err)
+        }
+    }
+
+    /// Gives the module the state that was last saved for it, and forgets it
+    pub fn restore_state(&self) {
+        let conn = ensure_table(state_table);
+        let state = conn
+            .query_row(
+                "SELECT state FROM ModuleState WHERE name = ?",
+                &[&self.name],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .expect("valid sql");
+        let state = match state {
+            Some(state) => state,
+            None => return,
+        };
+
+        self.forget_state();
+        match serde_json::from_str(&state) {
+            Ok(state) => {
+                debug!("restoring the state of {}", self.name);
+                self.lock().restore_state(state)
             }
-        });
-        func(this, &req)
+            Err(err) => warn!("invalid state for {}: {}", self.name, err),
+        }
+    }
+
+    /// Deletes the state that was saved for the module
+    pub fn forget_state(&self) {
+        let conn = ensure_table(state_table);
+        conn.execute("DELETE FROM ModuleState WHERE name = ?", &[&self.name])
+            .expect("valid sql");
+    }
+
+    /// Replaces the module with a new one from its `create` function
+    ///
+    /// A module that wasn't created with `supervised` is kept as it is
+    pub fn rebuild(&self) -> Result<(), Error> {
+        if let Some(factory) = &self.factory {
+            let module = factory()?;
+            *self.lock() = module;
+        }
+        self.module.clear_poison();
+        Ok(())
+    }
+}
+
+fn state_table(conn: &Connection) {
+    conn.execute(
+        r#"CREATE TABLE IF NOT EXISTS ModuleState(
+            name        TEXT PRIMARY KEY NOT NULL,
+            state       TEXT NOT NULL,
+            saved_at    INTEGER NOT NULL
+        )"#,
+        NO_PARAMS,
+    )
+    .expect("create ModuleState table");
+}
+
+fn factory<M, F>(create: F) -> Factory
+where
+    M: Module + 'static,
+    F: Fn() -> Result<M, Error> + Send + Sync + 'static,
+{
+    Arc::new(move || create().map(|m| Box::new(m) as Box<dyn Module>))
+}
+
+/// Every module that can be created, and the ones that currently are
+#[derive(Default)]
+pub struct Manager {
+    available: Vec<(&'static str, Factory)>,
+    loaded: Vec<LoadedModule>,
+}
+
+impl From<Vec<LoadedModule>> for Manager {
+    fn from(loaded: Vec<LoadedModule>) -> Self {
+        Self {
+            available: vec![],
+            loaded,
+        }
+    }
+}
+
+impl Manager {
+    /// Makes the module available as `name`, without creating it
+    pub fn add<M, F>(&mut self, name: &'static str, create: F)
+    where
+        M: Module + 'static,
+        F: Fn() -> Result<M, Error> + Send + Sync + 'static,
+    {
+        self.available.push((name, factory(create)))
+    }
+
+    /// Creates the available modules that are enabled in `config`,
+    /// returning the names of the ones that weren't created
+    pub fn load(&mut self, config: &Config) -> Vec<&'static str> {
+        let mut disabled = vec![];
+        for (name, _) in self.available.clone() {
+            if !config.is_enabled_anywhere(name) {
+                disabled.push(name);
+                continue;
+            }
+            if let Err(err) = self.enable(name) {
+                warn!("cannot load module {}: {:?}", name, err);
+                disabled.push(name);
+            }
+        }
+        disabled
+    }
+
+    pub fn loaded(&self) -> &[LoadedModule] {
+        &self.loaded
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LoadedModule> {
+        self.loaded.iter().find(|m| m.name == name)
+    }
+
+    /// The names of every known module, loaded or not
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = self
+            .available
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(self.loaded.iter().map(|m| m.name))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Finds the module called `name`, ignoring case
+    pub fn find(&self, name: &str) -> Option<&'static str> {
+        self.names()
+            .into_iter()
+            .find(|m| m.eq_ignore_ascii_case(name))
+    }
+
+    /// Creates the module called `name`, which registers its commands
+    pub fn enable(&mut self, name: &str) -> Result<LoadedModule, Error> {
+        if let Some(loaded) = self.get(name) {
+            return Ok(loaded.clone());
+        }
+        let (name, factory) = self
+            .available
+            .iter()
+            .find(|(m, _)| *m == name)
+            .cloned()
+            .ok_or(Error::CannotStart)?;
+
+        let loaded = LoadedModule::from_factory(name, factory)?;
+        loaded.restore_state();
+        info!("loaded module: {}", name);
+        self.loaded.push(loaded.clone());
+        Ok(loaded)
+    }
+
+    /// Rebuilds the module called `name`, keeping its state
+    pub fn reload(&self, name: &str) -> Option<Result<(), Error>> {
+        let loaded = self.get(name)?;
+        loaded.save_state();
+        let res = loaded.rebuild();
+        loaded.restore_state();
+        Some(res)
+    }
+
+    /// Drops the module called `name`, and unregisters its commands
+    ///
+    /// The module is shut down, and its state is saved for when it's enabled again
+    pub fn disable(&mut self, name: &str) -> Option<LoadedModule> {
+        let pos = self.loaded.iter().position(|m| m.name == name)?;
+        let loaded = self.loaded.remove(pos);
+        loaded.lock().on_shutdown();
+        loaded.save_state();
+        Registry::unregister_namespace(loaded.name);
+        info!("unloaded module: {}", name);
+        Some(loaded)
+    }
+
+    /// Saves the state of every loaded module
+    pub fn save_states(&self) {
+        for loaded in &self.loaded {
+            loaded.save_state()
+        }
+    }
+
+    /// Forgets the saved state of every loaded module
+    ///
+    /// They are still running with their state, so after a reconnect the one saved
+    /// on disconnect would be stale if it was restored after a crash
+    pub fn forget_states(&self) {
+        for loaded in &self.loaded {
+            loaded.forget_state()
+        }
+    }
+
+    /// Shuts down every loaded module, and saves their state
+    pub fn shutdown(&self) {
+        for loaded in &self.loaded {
+            loaded.lock().on_shutdown();
+            loaded.save_state();
+        }
     }
 }
 
 pub trait Module: Send {
     fn handle(&mut self, rx: Receiver, tx: Sender) {
-        // TODO handle panics here
         let mut resp = vec![];
         while let Ok(ev) = rx.recv() {
             let msg = match ev {
-                Event::Message(msg, req) => {
+                Event::Message(msg, req, ev) => {
                     match msg.command.as_str() {
                         "PRIVMSG" | "WHISPER" => {
                             if let Some(req) = req {
@@ -79,7 +454,15 @@ pub trait Module: Send {
                             }
                             resp.push(self.passive(&msg))
                         }
-                        _ => resp.push(self.event(&msg)),
+                        _ => {
+                            resp.push(self.event(&msg));
+                            if let Some(ev) = ev {
+                                if *ev == irc::TwitchEvent::Connected {
+                                    resp.push(self.on_connect())
+                                }
+                                resp.push(self.twitch(&ev))
+                            }
+                        }
                     };
                     msg
                 }
@@ -93,9 +476,17 @@ pub trait Module: Send {
                     self.inspect(&msg, &resp);
                     continue;
                 }
+                Event::Disconnected(reason) => {
+                    self.on_disconnect(reason);
+                    continue;
+                }
+                Event::Shutdown => {
+                    self.on_shutdown();
+                    continue;
+                }
             };
 
-            for resp in resp.drain(..).filter_map(|s| s) {
+            for resp in resp.drain(..).flatten() {
                 let _ = tx.send((Some(msg.clone()), resp));
             }
         }
@@ -113,10 +504,36 @@ pub trait Module: Send {
         None
     }
 
+    /// called with the typed form of twitch-specific messages, after `event`
+    fn twitch(&mut self, _ev: &irc::TwitchEvent) -> Option<Response> {
+        None
+    }
+
     fn tick(&mut self, _dt: Instant) -> Option<Response> {
         None
     }
 
+    /// called once Twitch has accepted the connection, before `twitch`
+    fn on_connect(&mut self) -> Option<Response> {
+        None
+    }
+
+    /// called after the connection was closed, so nothing can be sent from here
+    fn on_disconnect(&mut self, _reason: Disconnect) {}
+
+    /// called before the module is stopped for good (it's being disabled, or the bot is exiting)
+    fn on_shutdown(&mut self) {}
+
+    /// State that should outlive this instance of the module
+    ///
+    /// This is saved on disconnect, on shutdown and before a reload, and is given
+    /// to `restore_state` once, when the module is next created
+    fn save_state(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn restore_state(&mut self, _state: serde_json::Value) {}
+
     /// don't block in this or you'll probably break the tests
     // TODO: make this async
     fn inspect(&mut self, _msg: &irc::Message, _resp: &Response) {}
@@ -127,3 +544,253 @@ pub enum Error {
     CommandAlreadyExists,
     CannotStart,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SHUTDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Counter(u64);
+    impl Module for Counter {
+        fn on_shutdown(&mut self) {
+            SHUTDOWNS.fetch_add(1, Ordering::SeqCst);
+        }
+        fn save_state(&self) -> Option<serde_json::Value> {
+            Some(self.0.into())
+        }
+        fn restore_state(&mut self, state: serde_json::Value) {
+            self.0 = state.as_u64().unwrap()
+        }
+    }
+
+    struct Cooled {
+        map: CommandMap<Cooled>,
+    }
+    impl Module for Cooled {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            let map = self.map.clone();
+            map.dispatch(self, req)
+        }
+    }
+    impl Cooled {
+        fn ok(&mut self, _req: &Request) -> Option<Response> {
+            reply!("ok")
+        }
+    }
+
+    #[test]
+    fn cooldowns() {
+        let db = get_connection();
+        let mut config = config::Cooldowns {
+            whisper: true,
+            ..Default::default()
+        };
+        config
+            .commands
+            .insert("!cooled_off".into(), Cooldown::global(0));
+        cooldown::configure(&config);
+
+        let map = CommandMap::create(
+            "Cooled",
+            vec![
+                Command::new("!cooled", Cooled::ok),
+                Command::new("!cooled_off", Cooled::ok),
+            ],
+        )
+        .unwrap();
+        // it can be given cooldowns after it was cloned
+        let _shared = map.clone();
+        let map = map.cooldowns(&[
+            ("!cooled", Cooldown::user(60)),
+            ("!cooled_off", Cooldown::user(60)),
+        ]);
+        let mut cooled = Cooled { map };
+        let mut env = Environment::new(&db, &mut cooled);
+
+        env.push("!cooled");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: ok");
+
+        env.push("!cooled");
+        env.step();
+        let whisper = env.pop().unwrap();
+        assert!(
+            whisper.starts_with("/w test !cooled can be used again in 59 seconds")
+                || whisper.starts_with("/w test !cooled can be used again in 1 minute"),
+            "{}",
+            whisper
+        );
+        env.push("!cooled");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        // moderators and other users aren't waiting on it
+        env.push_mod("!cooled");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: ok");
+        env.push_user("!cooled", ("other", 1001));
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@other: ok");
+
+        // turned off in the config
+        for _ in 0..2 {
+            env.push("!cooled_off");
+            env.step();
+            assert_eq!(env.pop().unwrap(), "@test: ok");
+        }
+    }
+
+    struct Words {
+        map: CommandMap<Words>,
+    }
+    impl Module for Words {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            let map = self.map.clone();
+            map.dispatch(self, req)
+        }
+    }
+    impl Words {
+        fn echo(&mut self, req: &Request) -> Option<Response> {
+            reply!(format!("{}: '{}'", req.name()?, req.args()))
+        }
+    }
+
+    #[test]
+    fn dispatch_whole_words() {
+        let db = get_connection();
+        let map = CommandMap::create(
+            "Words",
+            vec![
+                Command::new("!words", Words::echo),
+                Command::new("!words start", Words::echo),
+            ],
+        )
+        .unwrap();
+        let mut words = Words { map };
+        let mut env = Environment::new(&db, &mut words);
+
+        for (input, expected) in &[
+            ("!words", "!words: ''"),
+            ("!words a | b", "!words: 'a | b'"),
+            ("!words   start  30s", "!words start: '30s'"),
+            ("!words starting", "!words: 'starting'"),
+        ] {
+            env.push(input);
+            env.step();
+            assert_eq!(env.pop().unwrap(), format!("@test: {}", expected));
+        }
+
+        env.push("!wordsmith start");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        Registry::add_alias("!w", "!words", None).unwrap();
+        env.push("!w start now");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !words start: 'now'");
+    }
+
+    #[test]
+    fn dispatch_across_modules() {
+        let _db = get_connection();
+        let short = CommandMap::create("Short", vec![Command::new("!across", Words::echo)]);
+        let long = CommandMap::create("Long", vec![Command::new("!across the", Words::echo)]);
+        let (mut short, mut long) = (
+            Words {
+                map: short.unwrap(),
+            },
+            Words { map: long.unwrap() },
+        );
+
+        // the longest command wins, even if it is another module's
+        let req = request("#test", 1000, "", "!across the board");
+        assert_eq!(short.command(&req), None);
+        assert!(long.command(&req).is_some());
+
+        let req = request("#test", 1000, "", "!across board");
+        assert!(short.command(&req).is_some());
+        assert_eq!(long.command(&req), None);
+    }
+
+    #[test]
+    fn permission_before_args() {
+        let db = get_connection();
+        let map = CommandMap::create(
+            "Guarded",
+            vec![Command::new("!guarded", Words::echo).args(&[args::int("n")])],
+        )
+        .unwrap()
+        .permissions(&[("!guarded", Role::Moderator)]);
+        let mut words = Words { map };
+        let mut env = Environment::new(&db, &mut words);
+
+        // the usage isn't given away to someone who can't use it
+        env.push("!guarded");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        env.push_mod("!guarded");
+        env.step();
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: <n> is missing. usage: !guarded <n>"
+        );
+    }
+
+    #[test]
+    fn state_hand_off() {
+        let _db = get_connection();
+        let mut modules = Manager::default();
+        modules.add("Counter", || Ok(Counter::default()));
+        let mut config = Config::default();
+        config.enabled.push("Counter".into());
+        modules.load(&config);
+
+        let set = |modules: &Manager, n: u64| {
+            modules
+                .get("Counter")
+                .unwrap()
+                .lock()
+                .restore_state(n.into())
+        };
+        let get = |modules: &Manager| modules.get("Counter").unwrap().lock().save_state();
+
+        // a reload keeps the state
+        set(&modules, 3);
+        modules.reload("Counter").unwrap().unwrap();
+        assert_eq!(get(&modules), Some(3.into()));
+
+        // as does disabling and enabling it
+        let shutdowns = SHUTDOWNS.load(Ordering::SeqCst);
+        modules.disable("Counter").unwrap();
+        assert_eq!(SHUTDOWNS.load(Ordering::SeqCst), shutdowns + 1);
+        modules.enable("Counter").unwrap();
+        assert_eq!(get(&modules), Some(3.into()));
+
+        // the saved state is only given back once
+        modules.get("Counter").unwrap().rebuild().unwrap();
+        modules.get("Counter").unwrap().restore_state();
+        assert_eq!(get(&modules), Some(0.into()));
+
+        // restarting the process is the same as enabling it again
+        set(&modules, 5);
+        modules.shutdown();
+        let mut restarted = Manager::default();
+        restarted.add("Counter", || Ok(Counter::default()));
+        restarted.load(&config);
+        assert_eq!(get(&restarted), Some(5.into()));
+
+        // but not after a reconnect, if it crashed
+        set(&restarted, 7);
+        restarted.save_states();
+        restarted.forget_states();
+        let mut crashed = Manager::default();
+        crashed.add("Counter", || Ok(Counter::default()));
+        crashed.load(&config);
+        assert_eq!(get(&crashed), Some(0.into()));
+    }
+}
@@ -1,9 +1,11 @@
 use crate::prelude::*;
 use chrono::prelude::*;
+use hashbrown::HashMap;
 use log::*;
 use rusqlite::{types::ToSql, Connection, NO_PARAMS};
 
 use crate::module::CommandMap;
+use crate::script;
 
 #[derive(Default, Debug)]
 pub struct UserCommand {
@@ -34,11 +36,25 @@ submit! {
     template::Response("builtin_stream_offline", "the stream doesn't seem to be live");
     template::Response("builtin_viewers", "viewers: ${viewers}");
     template::Response("builtin_uptime", "uptime: ${uptime}");
+    template::Response("builtin_invalid_script", "couldn't compile \"${command}\": ${error}");
+    template::Response("builtin_script_failed", "\"${command}\" failed: ${error}");
+    template::Response("builtin_perm_required", "${command} needs ${role}");
+    template::Response("builtin_perm_set", "${command} now needs ${role}");
+    template::Response("builtin_perm_granted", "${user} is now at least ${role}");
+    template::Response("builtin_perm_allowed", "${user} can use ${command}");
+    template::Response("builtin_perm_denied", "${user} cannot use ${command}");
+    template::Response("builtin_perm_cleared", "${user} is back to their badges");
+    template::Response("builtin_alias_added", "${alias} is now an alias for ${command}");
+    template::Response("builtin_alias_removed", "${alias} is no longer an alias");
+    template::Response("builtin_alias_unknown", "${alias} isn't an alias");
+    template::Response("builtin_alias_list", "aliases: ${aliases}");
+    template::Response("builtin_alias_none", "there are no aliases");
+    template::Response("builtin_help_command", "${usage} -- ${description}");
 }
 
 pub struct Builtin {
     twitch: TwitchClient,
-    channel: String,
+    channels: Vec<String>,
     map: CommandMap<Builtin>,
 }
 
@@ -51,10 +67,14 @@ impl Module for Builtin {
         }
     }
 
-    fn event(&mut self, msg: &irc::Message) -> Option<Response> {
-        match msg.command() {
-            "001" => join(&format!("#{}", self.channel)),
-            "PING" => raw!("PONG :{}", msg.expect_data()),
+    fn twitch(&mut self, ev: &irc::TwitchEvent) -> Option<Response> {
+        match ev {
+            irc::TwitchEvent::Connected => multi(
+                self.channels
+                    .iter()
+                    .map(|channel| join(&format!("#{}", channel))),
+            ),
+            irc::TwitchEvent::Ping { token } => raw!("PONG :{}", token),
             _ => None,
         }
     }
@@ -75,19 +95,84 @@ impl Builtin {
             twitch: TwitchClient::new(&Config::expect_env("SHAKEN_TWITCH_CLIENT_ID")),
             map: CommandMap::create(
                 NAME,
-                &[
-                    ("!version", Builtin::version_command),
-                    ("!viewers", Builtin::viewers_command),
-                    ("!uptime", Builtin::uptime_command),
-                    ("!add", Builtin::add_command),
-                    ("!edit-body", Builtin::edit_command),
-                    ("!edit-desc", Builtin::edit_command),
-                    ("!info", Builtin::info_command),
-                    ("!remove", Builtin::remove_command),
-                    ("!help", Builtin::help_command),
+                vec![
+                    Command::new("!version", Builtin::version_command)
+                        .help("links the source code, and which version is running"),
+                    Command::new("!viewers", Builtin::viewers_command)
+                        .help("shows how many people are watching"),
+                    Command::new("!uptime", Builtin::uptime_command)
+                        .help("shows how long the stream has been live"),
+                    Command::new("!add", Builtin::add_command)
+                        .help("adds a user command. a body starting with script: is a script")
+                        .args(&[args::word("command"), args::rest("body")]),
+                    Command::new("!edit-body", Builtin::edit_command)
+                        .help("changes what a user command says")
+                        .args(&[args::word("command"), args::rest("body")]),
+                    Command::new("!edit-desc", Builtin::edit_command)
+                        .help("changes the description of a user command")
+                        .args(&[args::word("command"), args::rest("description")]),
+                    Command::new("!info", Builtin::info_command)
+                        .help("shows who made a user command, and how often it was used")
+                        .args(&[args::word("command")]),
+                    Command::new("!remove", Builtin::remove_command)
+                        .help("removes a user command")
+                        .args(&[args::word("command")]),
+                    Command::new("!help", Builtin::help_command)
+                        .help("lists the commands, or shows what one does")
+                        .args(&[args::rest("command").optional()]),
+                    Command::new("!perm", Builtin::perm_command)
+                        .help("shows the role a command needs")
+                        .args(&[args::word("command")]),
+                    Command::new("!perm set", Builtin::perm_set_command)
+                        .help("changes the role a command needs")
+                        .args(&[args::word("command"), args::choice("role", Role::NAMES)]),
+                    Command::new("!perm reset", Builtin::perm_set_command)
+                        .help("goes back to the role a command was declared with")
+                        .args(&[args::word("command")]),
+                    Command::new("!perm grant", Builtin::perm_grant_command)
+                        .help("gives someone a role, on top of their badges")
+                        .args(&[args::user("user"), args::choice("role", Role::NAMES)]),
+                    Command::new("!perm allow", Builtin::perm_allow_command)
+                        .help("lets someone use a command, whatever their role")
+                        .args(&[args::user("user"), args::word("command")]),
+                    Command::new("!perm deny", Builtin::perm_allow_command)
+                        .help("stops someone from using a command, whatever their role")
+                        .args(&[args::user("user"), args::word("command")]),
+                    Command::new("!perm clear", Builtin::perm_clear_command)
+                        .help("removes the roles and commands someone was given")
+                        .args(&[args::user("user")]),
+                    Command::new("!alias add", Builtin::alias_add_command)
+                        .help("makes another name for a command")
+                        .args(&[args::word("alias"), args::rest("command")]),
+                    Command::new("!alias remove", Builtin::alias_remove_command)
+                        .help("removes an alias")
+                        .args(&[args::word("alias")]),
+                    Command::new("!alias list", Builtin::alias_list_command)
+                        .help("lists the aliases"),
                 ],
-            )?,
-            channel: Config::load().twitch.channel.to_string(),
+            )?
+            .permissions(&[
+                ("!add", Role::Moderator),
+                ("!edit-body", Role::Moderator),
+                ("!edit-desc", Role::Moderator),
+                ("!info", Role::Moderator),
+                ("!remove", Role::Moderator),
+                ("!perm", Role::Moderator),
+                ("!perm set", Role::Moderator),
+                ("!perm reset", Role::Moderator),
+                ("!perm grant", Role::Moderator),
+                ("!perm allow", Role::Moderator),
+                ("!perm deny", Role::Moderator),
+                ("!perm clear", Role::Moderator),
+                ("!alias add", Role::Moderator),
+                ("!alias remove", Role::Moderator),
+            ])
+            // these ask twitch every time
+            .cooldowns(&[
+                ("!viewers", Cooldown::channel(30)),
+                ("!uptime", Cooldown::channel(30)),
+            ]),
+            channels: Config::load().twitch.channels,
         })
     }
 
@@ -115,43 +200,70 @@ impl Builtin {
             disabled: bool,
         }
 
+        let resolved = req.resolve_alias();
+        let req = resolved.as_ref().unwrap_or(req);
+        let name = req.args_iter().next()?;
         let conn = database::get_connection();
-        let mut statement = conn
+        let result = conn
             .prepare("SELECT body, disabled FROM UserCommands WHERE command = ?")
-            .expect("valid sql");
-
-        let mut result = statement
-            .query_map(&[&req.args()], |row| {
+            .expect("valid sql")
+            .query_map(&[&name], |row| {
                 Ok(Command {
                     body: row.get(0)?,
                     disabled: row.get(1)?,
                 })
             })
-            .expect("valid sql");
+            .expect("valid sql")
+            .next();
 
-        match result.next() {
-            Some(Ok(ref command)) if !command.disabled => say!(command.body),
-            _ => None,
+        // the statement has to be finished before a script can use the database
+        let command = match result {
+            Some(Ok(command)) if !command.disabled => command,
+            _ => return None,
+        };
+        if !permission::check(name, req) {
+            return None;
         }
-    }
-
-    fn add_command(&mut self, req: &Request) -> Option<Response> {
-        require_privileges!(&req, "you cannot do that");
 
-        let (command, body) = match Self::arg_parts(&req) {
-            Some((head, tail)) => (head, tail),
-            None => return reply_template!("misc_invalid_args"),
+        let source = match script::source(&command.body) {
+            Some(source) => source,
+            None => return say!(command.body),
         };
+        match script::run(name, source, &req.search(name)?) {
+            Ok(lines) => multi(lines.iter().map(|s| say!(s))),
+            Err(err) => {
+                warn!("script for {} failed: {}", name, err);
+                reply_template!(
+                    "builtin_script_failed",
+                    ("command", &name.to_string()),
+                    ("error", &err.to_string())
+                )
+            }
+        }
+    }
 
-        let command = if !command.starts_with('!') {
-            format!("!{}", command)
-        } else {
-            command
-        };
+    // scripts have to compile before they can be saved
+    fn check_script(command: &str, body: &str) -> Result<(), Option<Response>> {
+        match script::source(body).map(script::compile) {
+            Some(Err(err)) => Err(reply_template!(
+                "builtin_invalid_script",
+                ("command", &command.to_string()),
+                ("error", &err.to_string())
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_command(&mut self, req: &Request) -> Option<Response> {
+        let (command, body) = Self::command_parts(req, "body")?;
+        let command = Self::command_name(&command);
 
         if !Self::is_available(&command) {
             return reply_template!("builtin_reserved_name", ("command", &command));
         }
+        if let Err(resp) = Self::check_script(&command, &body) {
+            return resp;
+        }
 
         let command = UserCommand {
             command,
@@ -180,7 +292,7 @@ impl Builtin {
                 &command.disabled,
             ],
         ) {
-            Ok(row) if row == 0 => {
+            Ok(0) => {
                 reply_template!("builtin_already_exists", ("command", &command.command))
             }
             Ok(_row) => reply_template!("builtin_added_command", ("command", &command.command)),
@@ -198,16 +310,22 @@ impl Builtin {
     }
 
     fn edit_command(&mut self, req: &Request) -> Option<Response> {
-        require_privileges!(&req, "you cannot do that");
-
-        let (command, data) = match Self::arg_parts(&req) {
-            Some((head, tail)) => (head, tail),
-            None => return reply_template!("misc_invalid_args"),
+        let head = req.name().unwrap();
+        let rest = if head == "!edit-body" {
+            "body"
+        } else {
+            "description"
         };
+        let (command, data) = Self::command_parts(req, rest)?;
 
         let conn = database::get_connection();
 
-        let head = req.name().unwrap();
+        if head == "!edit-body" {
+            if let Err(resp) = Self::check_script(&command, &data) {
+                return resp;
+            }
+        }
+
         let (sql, template) = match head.as_str() {
             "!edit-body" => (
                 conn.execute(
@@ -241,10 +359,9 @@ impl Builtin {
     }
 
     fn info_command(&mut self, req: &Request) -> Option<Response> {
-        require_privileges!(&req, "you cannot do that");
-
-        let command = match Self::try_get_command(req.args()) {
-            None => return reply_template!("builtin_invalid_command", ("command", &req.args())),
+        let name = req.parsed().text("command")?;
+        let command = match Self::try_get_command(name) {
+            None => return reply_template!("builtin_invalid_command", ("command", &name)),
             Some(command) => command,
         };
 
@@ -273,21 +390,16 @@ impl Builtin {
     }
 
     fn remove_command(&mut self, req: &Request) -> Option<Response> {
-        require_privileges!(&req, "you cannot do that");
-
-        let (command, _) = match Self::arg_parts(&req) {
-            Some((head, tail)) => (head, tail),
-            None => return reply_template!("misc_invalid_args"),
-        };
-
+        let command = req.parsed().text("command")?;
         let conn = database::get_connection();
-        conn.execute("DELETE FROM UserCommands WHERE command = ?", &[&command])
+        conn.execute("DELETE FROM UserCommands WHERE command = ?", &[command])
             .expect("valid sql");
 
         reply_template!("builtin_command_deleted")
     }
 
-    fn help_command(&mut self, _req: &Request) -> Option<Response> {
+    // !help [command]
+    fn help_command(&mut self, req: &Request) -> Option<Response> {
         fn wrap(input: impl IntoIterator<Item = String>) -> Vec<String> {
             const WIDTH: usize = 40;
             let (mut lines, mut line) = (vec![], String::new());
@@ -307,21 +419,49 @@ impl Builtin {
             lines
         }
 
-        // TODO look up specific commands
-
-        let system = wrap(
-            Registry::commands()
-                .into_iter()
-                .map(|cmd| cmd.name().to_string()),
-        );
-        let user = wrap(Self::fetch_command_names());
+        if let Some(command) = req.parsed().text("command") {
+            let command = Self::command_name(command);
+            if let Some(cmd) = Registry::find(&command) {
+                return reply_template!(
+                    "builtin_help_command",
+                    ("usage", &cmd.usage()),
+                    ("description", &cmd.help())
+                );
+            }
+            return match Self::try_get_command(&command) {
+                Some(cmd) => reply_template!(
+                    "builtin_command_description",
+                    ("command", &cmd.command),
+                    ("description", &cmd.description)
+                ),
+                None => reply_template!("builtin_invalid_command", ("command", &command)),
+            };
+        }
 
-        multi!(
-            whisper!("system commands:"),
-            multi(system.iter().map(|s| whisper!(s))),
-            whisper!("user commands:"),
-            multi(user.iter().map(|s| whisper!(s))),
-        )
+        let mut namespaces = HashMap::<_, Vec<_>>::new();
+        for cmd in Registry::commands() {
+            namespaces
+                .entry(cmd.namespace().to_string())
+                .or_default()
+                .push(cmd.name().to_string());
+        }
+        let mut namespaces = namespaces.into_iter().collect::<Vec<_>>();
+        namespaces.sort_unstable();
+
+        let mut lines = vec![];
+        for (namespace, mut commands) in namespaces {
+            commands.sort_unstable();
+            lines.extend(wrap(
+                std::iter::once(format!("{}:", namespace)).chain(commands),
+            ));
+        }
+        let user = Self::fetch_command_names();
+        if !user.is_empty() {
+            lines.extend(wrap(
+                std::iter::once("user commands:".to_string()).chain(user),
+            ));
+        }
+        multi(lines.iter().map(|s| whisper!(s)))
     }
 
     pub fn try_get_command(name: &str) -> Option<UserCommand> {
@@ -378,34 +518,168 @@ impl Builtin {
         Registry::is_available(cmd)
     }
 
-    fn arg_parts(req: &Request) -> Option<(String, String)> {
-        let mut iter = req.args_iter();
-        let head = iter.next()?.to_string();
-        let tail = iter.map(str::trim).fold(String::new(), |mut acc, c| {
-            if !acc.is_empty() {
-                acc.push_str(" ");
-            }
-            acc.push_str(c);
-            acc
-        });
-        Some((head, tail))
+    // the command being changed, and the rest of the line
+    fn command_parts(req: &Request, rest: &str) -> Option<(String, String)> {
+        let args = req.parsed();
+        Some((
+            args.text("command")?.to_string(),
+            args.text(rest)?.to_string(),
+        ))
     }
     // end of user commands
 
+    // !perm <command>
+    fn perm_command(&mut self, req: &Request) -> Option<Response> {
+        let command = Self::command_name(req.parsed().text("command")?);
+        let role = permission::required(&command).to_string();
+        reply_template!("builtin_perm_required", ("command", &command), ("role", &role))
+    }
+
+    // !perm set <command> <role> and !perm reset <command>
+    fn perm_set_command(&mut self, req: &Request) -> Option<Response> {
+        let command = Self::command_name(req.parsed().text("command")?);
+        let role = req.parsed().word("role").and_then(Role::parse);
+
+        // nobody can give away more than they have
+        let own = permission::role_of(req);
+        if permission::required(&command) > own || role.is_some_and(|role| role > own) {
+            return reply_template!("misc_requires_priv");
+        }
+
+        let conn = database::get_connection();
+        match role {
+            Some(role) => permission::Permissions::set_required(&conn, &command, role),
+            None => permission::Permissions::reset_required(&conn, &command),
+        }
+        let role = permission::required(&command).to_string();
+        reply_template!("builtin_perm_set", ("command", &command), ("role", &role))
+    }
+
+    // !perm grant <user> <role>
+    fn perm_grant_command(&mut self, req: &Request) -> Option<Response> {
+        let user = req.parsed().user("user")?;
+        let role = Role::parse(req.parsed().word("role")?)?;
+        if !permission::can_manage(req, user) {
+            return reply_template!("misc_requires_priv");
+        }
+        if role >= Role::Owner || role > permission::role_of(req) {
+            return reply_template!("misc_requires_priv");
+        }
+
+        let conn = database::get_connection();
+        permission::Permissions::grant_role(&conn, user.userid, role);
+        reply_template!(
+            "builtin_perm_granted",
+            ("user", &user.display),
+            ("role", &role.to_string())
+        )
+    }
+
+    // !perm allow <user> <command> and !perm deny <user> <command>
+    fn perm_allow_command(&mut self, req: &Request) -> Option<Response> {
+        let user = req.parsed().user("user")?;
+        let command = Self::command_name(req.parsed().text("command")?);
+        if !permission::can_manage(req, user) {
+            return reply_template!("misc_requires_priv");
+        }
+        if permission::required(&command) > permission::role_of(req) {
+            return reply_template!("misc_requires_priv");
+        }
+
+        let allowed = req.name()? == "!perm allow";
+        let conn = database::get_connection();
+        permission::Permissions::set_user_override(&conn, user.userid, &command, allowed);
+        let template = if allowed {
+            "builtin_perm_allowed"
+        } else {
+            "builtin_perm_denied"
+        };
+        reply!(template::lookup(
+            template,
+            &[("user", &user.display), ("command", &command)]
+        )
+        .unwrap())
+    }
+
+    // !perm clear <user>
+    fn perm_clear_command(&mut self, req: &Request) -> Option<Response> {
+        let user = req.parsed().user("user")?;
+        if !permission::can_manage(req, user) {
+            return reply_template!("misc_requires_priv");
+        }
+        permission::Permissions::clear_user(&database::get_connection(), user.userid);
+        reply_template!("builtin_perm_cleared", ("user", &user.display))
+    }
+
+    // !alias add <alias> <command>
+    fn alias_add_command(&mut self, req: &Request) -> Option<Response> {
+        let alias = Self::command_name(req.parsed().text("alias")?);
+        let command = Self::command_name(req.parsed().text("command")?);
+
+        let known = Registry::commands().iter().any(|cmd| cmd.name() == command)
+            || Registry::resolve(&command).is_some()
+            || Self::try_get_command(&command).is_some();
+        if !known {
+            return reply_template!("builtin_invalid_command", ("command", &command));
+        }
+
+        if Self::try_get_command(&alias).is_some()
+            || Registry::add_alias(&alias, &command, None).is_err()
+        {
+            return reply_template!("builtin_reserved_name", ("command", &alias));
+        }
+
+        let command = Registry::resolve(&alias)?;
+        reply_template!(
+            "builtin_alias_added",
+            ("alias", &alias),
+            ("command", &command)
+        )
+    }
+
+    // !alias remove <alias>
+    fn alias_remove_command(&mut self, req: &Request) -> Option<Response> {
+        let alias = Self::command_name(req.parsed().text("alias")?);
+        if !Registry::remove_alias(&alias) {
+            return reply_template!("builtin_alias_unknown", ("alias", &alias));
+        }
+        reply_template!("builtin_alias_removed", ("alias", &alias))
+    }
+
+    // !alias list
+    fn alias_list_command(&mut self, _req: &Request) -> Option<Response> {
+        let aliases = Registry::aliases()
+            .iter()
+            .map(|alias| format!("{} -> {}", alias.name(), alias.command()))
+            .collect::<Vec<_>>();
+        if aliases.is_empty() {
+            return reply_template!("builtin_alias_none");
+        }
+        reply_template!("builtin_alias_list", ("aliases", &aliases.join(", ")))
+    }
+
+    fn command_name(name: &str) -> String {
+        if name.starts_with('!') {
+            name.to_string()
+        } else {
+            format!("!{}", name)
+        }
+    }
+
     fn version_command(&mut self, _req: &Request) -> Option<Response> {
-        let rev = option_env!("SHAKEN_GIT_REV").expect("get rev");
-        let branch = option_env!("SHAKEN_GIT_BRANCH").expect("get branch");
+        let rev = option_env!("SHAKEN_GIT_REV").unwrap_or("unknown");
+        let branch = option_env!("SHAKEN_GIT_BRANCH").unwrap_or("unknown");
         reply_template!("builtin_github_repo", ("rev", &rev), ("branch", &branch))
     }
 
-    fn viewers_command(&mut self, _req: &Request) -> Option<Response> {
-        let streams = self.twitch.get_streams(&[self.channel.as_str()]);
+    fn viewers_command(&mut self, req: &Request) -> Option<Response> {
+        let streams = self.twitch.get_streams([config::channel_name(req.target())]);
         let stream = match streams {
             Ok(ref s) if !s.is_empty() => &s[0],
             _ => return reply_template!("builtin_stream_offline"),
         };
 
-        if stream.live.is_empty() || stream.live == "" {
+        if stream.live.is_empty() {
             return reply_template!("builtin_stream_offline");
         }
 
@@ -415,8 +689,8 @@ impl Builtin {
         )
     }
 
-    fn uptime_command(&mut self, _req: &Request) -> Option<Response> {
-        let streams = self.twitch.get_streams(&[self.channel.as_str()]);
+    fn uptime_command(&mut self, req: &Request) -> Option<Response> {
+        let streams = self.twitch.get_streams([config::channel_name(req.target())]);
 
         let stream = match streams {
             Ok(ref s) if !s.is_empty() => &s[0],
@@ -426,7 +700,7 @@ impl Builtin {
             }
         };
 
-        if stream.live.is_empty() || stream.live == "" {
+        if stream.live.is_empty() {
             return reply_template!("builtin_stream_offline");
         }
 
@@ -510,6 +784,40 @@ mod tests {
         assert_eq!(env.pop().unwrap(), "@test: \"!foo\" is a reserved name");
     }
 
+    #[test]
+    fn script_command() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        env.push_owner("!add !count script: `${req.args} is ${incr(req.args)}`");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: added \"!count\" as a command");
+
+        env.push("!count apples");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "apples is 1");
+        env.push("!count apples");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "apples is 2");
+
+        env.push_owner("!edit-body !count script: let x = ");
+        env.step();
+        assert!(env
+            .pop()
+            .unwrap()
+            .starts_with("@test: couldn't compile \"!count\": syntax error"));
+
+        env.push_owner("!edit-body !count script: throw \"nope\"");
+        env.step();
+        env.drain();
+        env.push("!count");
+        env.step();
+        let resp = env.pop().unwrap();
+        assert!(resp.starts_with("@test: \"!count\" failed:"), "{}", resp);
+        assert!(resp.contains("nope"), "{}", resp);
+    }
+
     #[test]
     fn edit_command() {
         let db = database::get_connection();
@@ -590,6 +898,178 @@ mod tests {
         );
     }
 
+    #[test]
+    fn perm_command() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        env.push("!add !secret hello");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        env.push_mod("!add !secret hello");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: added \"!secret\" as a command");
+
+        env.push_mod("!perm set secret moderator");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !secret now needs moderator");
+
+        env.push("!secret");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        env.push_mod("!secret");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "hello");
+
+        // can't set it higher than their own role
+        env.push_mod("!perm set !secret broadcaster");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you cannot do that");
+
+        env.push_owner("!perm set !secret broadcaster");
+        env.step();
+        env.drain();
+
+        env.push_mod("!perm !secret");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !secret needs broadcaster");
+
+        env.push_owner("!perm allow @test !secret");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: test can use !secret");
+
+        env.push("!secret");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "hello");
+
+        env.push_owner("!perm clear test");
+        env.step();
+        env.drain();
+
+        env.push("!secret");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        env.push_owner("!perm reset !secret");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !secret now needs everyone");
+    }
+
+    #[test]
+    fn perm_escalation() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        // a moderator can't change themselves
+        env.push_mod("!perm clear test");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you cannot do that");
+
+        env.push_mod("!perm grant test regular");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you cannot do that");
+
+        // or the broadcaster
+        env.push_user("hello", ("streamer", 2000));
+        env.push_raw(
+            "@badges=broadcaster/1;user-id=2000;display-name=streamer;color=#FFFFFF \
+             :streamer!user@irc.test PRIVMSG #test :hello",
+        );
+        env.push_user("hello", ("viewer", 2001));
+        env.step_wait(false);
+        env.step_wait(false);
+        env.step_wait(false);
+        env.drain();
+
+        env.push_mod("!perm deny streamer !version");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you cannot do that");
+
+        env.push_mod("!perm clear streamer");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you cannot do that");
+
+        // but can change someone below them
+        env.push_mod("!perm deny viewer !version");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: viewer cannot use !version");
+
+        env.push_mod("!perm clear viewer");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: viewer is back to their badges");
+
+        // and owners can change anyone
+        env.push_owner("!perm deny streamer !version");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: streamer cannot use !version");
+    }
+
+    #[test]
+    fn alias_command() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        env.push_mod("!add !hello hello there");
+        env.step();
+        env.drain();
+
+        env.push("!alias add !hi !hello");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+
+        env.push_mod("!alias add hi hello");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !hi is now an alias for !hello");
+
+        env.push("!hi");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "hello there");
+
+        // system commands can be aliased too
+        env.push_mod("!alias add !v !version");
+        env.step();
+        env.drain();
+        env.push("!v");
+        env.step();
+        assert!(env
+            .pop()
+            .unwrap()
+            .starts_with("@test: https://github.com/museun/shaken"));
+
+        env.push_mod("!alias add !hello !version");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: \"!hello\" is a reserved name");
+
+        env.push_mod("!alias add !nope !missing");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: \"!missing\" isn't a command");
+
+        env.push_mod("!add !hi another");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: \"!hi\" is a reserved name");
+
+        env.push("!alias list");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: aliases: !hi -> !hello, !v -> !version");
+
+        env.push_mod("!alias remove !hi");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !hi is no longer an alias");
+
+        env.push_mod("!alias remove !hi");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !hi isn't an alias");
+
+        env.push("!hi");
+        env.step_wait(false);
+        assert_eq!(env.pop(), None);
+    }
+
     #[test]
     fn help_command() {
         let db = database::get_connection();
@@ -614,7 +1094,7 @@ mod tests {
         }
         env.drain();
 
-        env.push_owner("!help !test");
+        env.push_owner("!help");
         env.step();
 
         let expected = Registry::commands().len() // system
@@ -627,4 +1107,64 @@ mod tests {
 
         assert_eq!(max, expected);
     }
+
+    #[test]
+    fn help_for_command() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        env.push("!help !alias add");
+        env.step();
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: !alias add <alias> <command...> -- makes another name for a command"
+        );
+
+        env.push("!help version");
+        env.step();
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: !version -- links the source code, and which version is running"
+        );
+
+        env.push_mod("!add !hello hello there");
+        env.step();
+        env.push_mod("!edit-desc !hello says hello");
+        env.step();
+        env.drain();
+
+        env.push("!help !hello");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: !hello -- says hello");
+
+        env.push("!help !missing");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: \"!missing\" isn't a command");
+    }
+
+    #[test]
+    fn help_by_namespace() {
+        let db = database::get_connection();
+        let mut builtin = Builtin::create().unwrap();
+        let mut env = Environment::new(&db, &mut builtin);
+
+        env.push("!help");
+        env.step();
+        let first = env.pop().unwrap();
+        assert!(first.starts_with("/w test Builtin: !add"), "{}", first);
+        env.drain();
+
+        env.push_mod("!add !hello hello there");
+        env.step();
+        env.drain();
+
+        env.push("!help");
+        env.step();
+        let mut last = None;
+        while let Some(line) = env.pop() {
+            last.replace(line);
+        }
+        assert_eq!(last.unwrap(), "/w test user commands: !hello");
+    }
 }
@@ -30,9 +30,11 @@ impl CurrentSong {
         Ok(Self {
             map: CommandMap::create(
                 "CurrentSong",
-                &[
-                    ("!song", Self::info_command),     //
-                    ("!prevsong", Self::prev_command), //
+                vec![
+                    Command::new("!song", Self::info_command)
+                        .help("shows the song that is playing"),
+                    Command::new("!prevsong", Self::prev_command)
+                        .help("shows the song that played before this one"),
                 ],
             )?,
         })
@@ -49,7 +51,7 @@ impl CurrentSong {
         use chrono::Duration as CDur;
         use std::time::Duration as SDur;
 
-        let start = Utc.timestamp(song.timestamp as i64, 0);
+        let start = Utc.timestamp(song.timestamp, 0);
         let dur = CDur::from_std(SDur::from_secs(song.duration as u64)).unwrap();
 
         let time = dur - (now - start);
@@ -76,7 +78,7 @@ impl CurrentSong {
             None => return reply_template!("currentsong_unknown_song"),
         };
 
-        let start = Utc.timestamp(song.timestamp as i64, 0);
+        let start = Utc.timestamp(song.timestamp, 0);
         let args = template::TemplateArgs::new()
             .with("start", &start)
             .with("title", &song.title.trim())
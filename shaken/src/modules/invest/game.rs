@@ -2,32 +2,50 @@ use crate::database::get_connection;
 
 use log::*;
 use rand::prelude::*;
-use rusqlite::{Connection, NO_PARAMS};
+use rusqlite::{types::ToSql, Connection, NO_PARAMS};
 
 const INVEST_TABLE: &str = r#"
-BEGIN;
-
 CREATE TABLE IF NOT EXISTS InvestStats (
-    ID INTEGER PRIMARY KEY NOT NULL UNIQUE,
-    Total INTEGER NOT NULL
+    Economy TEXT PRIMARY KEY NOT NULL UNIQUE,
+    Total   INTEGER NOT NULL
 );
 
-INSERT OR IGNORE INTO InvestStats (ID, Total) VALUES(0, 0);
+INSERT OR IGNORE INTO InvestStats (Economy, Total) VALUES('', 0);
 
 CREATE TABLE IF NOT EXISTS Invest (
-    ID      INTEGER PRIMARY KEY NOT NULL UNIQUE, -- twitch ID
-    Max     INTEGER NOT NULL,                    -- highest credits held
-    Current INTEGER NOT NULL,                    -- current credit balance
-    Total   INTEGER NOT NULL,                    -- total credits earned
-    Success INTEGER NOT NULL,                    -- number of successes
-    Failure INTEGER NOT NULL,                    -- number of failures
-    Active  INTEGER NOT NULL                     -- whether they'll get idle points
+    ID      INTEGER NOT NULL,             -- twitch ID
+    Max     INTEGER NOT NULL,             -- highest credits held
+    Current INTEGER NOT NULL,             -- current credit balance
+    Total   INTEGER NOT NULL,             -- total credits earned
+    Success INTEGER NOT NULL,             -- number of successes
+    Failure INTEGER NOT NULL,             -- number of failures
+    Active  INTEGER NOT NULL,             -- whether they'll get idle points
+    Economy TEXT NOT NULL DEFAULT '',     -- the channel, or empty for the shared economy
+    PRIMARY KEY (ID, Economy)
     -- FOREIGN KEY(ID) REFERENCES Users(ID) -- maybe add this constraint later
 );
+"#;
+
+// before economies, ID was the only key
+const MIGRATE_ECONOMY: &str = r#"
+ALTER TABLE Invest RENAME TO InvestOld;
+ALTER TABLE InvestStats RENAME TO InvestStatsOld;
+"#;
+
+const MIGRATE_ECONOMY_COPY: &str = r#"
+INSERT INTO Invest (ID, Max, Current, Total, Success, Failure, Active)
+    SELECT ID, Max, Current, Total, Success, Failure, Active FROM InvestOld;
+UPDATE InvestStats
+    SET Total = (SELECT Total FROM InvestStatsOld WHERE ID = 0)
+    WHERE Economy = '';
 
-COMMIT;
+DROP TABLE InvestOld;
+DROP TABLE InvestStatsOld;
 "#;
 
+/// The economy shared by every channel that doesn't have its own
+pub const SHARED: &str = "";
+
 pub type Credit = usize;
 pub type InvestResult<T> = std::result::Result<T, InvestError>;
 
@@ -52,71 +70,96 @@ pub struct InvestUser {
     pub total: Credit,
     pub invest: (Credit, Credit), // success, failure
     pub active: bool,
+    pub economy: String,
 }
 
 impl InvestUser {
-    pub fn new(id: i64) -> InvestUser {
-        let mut user = InvestUser::default();
-        user.id = id;
-        user
+    pub fn new(economy: &str, id: i64) -> InvestUser {
+        InvestUser {
+            id,
+            economy: economy.to_string(),
+            ..InvestUser::default()
+        }
     }
 }
 
+macro_rules! invest_user {
+    ($row:expr) => {{
+        macro_rules! un {
+            ($n:expr) => {
+                $row.get::<_, i64>($n)? as usize
+            };
+        }
+        Ok(InvestUser {
+            id: $row.get(0)?,
+            max: un!(1),
+            current: un!(2),
+            total: un!(3),
+            invest: (un!(4), un!(5)),
+            active: $row.get(6)?,
+            economy: $row.get(7)?,
+        })
+    }};
+}
+
 #[derive(Debug)]
 pub struct InvestGame;
 
 impl InvestGame {
     pub fn ensure_table(conn: &Connection) {
-        conn.execute_batch(INVEST_TABLE)
+        let columns = {
+            let mut stmt = conn
+                .prepare("PRAGMA table_info(Invest)")
+                .expect("valid sql");
+            stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))
+                .expect("get columns")
+                .filter_map(Result::ok)
+                .collect::<Vec<_>>()
+        };
+
+        if !columns.is_empty() && !columns.iter().any(|c| c == "Economy") {
+            info!("migrating Invest table to support economies");
+            let batch = ["BEGIN;", MIGRATE_ECONOMY, INVEST_TABLE, MIGRATE_ECONOMY_COPY, "COMMIT;"];
+            conn.execute_batch(&batch.concat())
+                .expect("migrate Invest table");
+            return;
+        }
+
+        conn.execute_batch(&["BEGIN;", INVEST_TABLE, "COMMIT;"].concat())
             .expect("create Invest table");
     }
 
-    pub fn get_top_n(conn: &Connection, bound: i16) -> Vec<InvestUser> {
-        macro_rules! un {
-            ($e:expr, $n:expr) => {
-                $e.get::<_, i64>($n)? as usize
-            };
-        }
-
+    pub fn get_top_n(conn: &Connection, economy: &str, bound: i16) -> Vec<InvestUser> {
         // TODO make this work for the other constraints
         let mut stmt = conn
-            .prepare("SELECT * FROM Invest ORDER BY Current DESC LIMIT ?")
+            .prepare("SELECT * FROM Invest WHERE Economy = ? ORDER BY Current DESC LIMIT ?")
             .expect("valid sql");
 
         let iter = stmt
-            .query_map(&[&bound], |row| {
-                Ok(InvestUser {
-                    id: row.get(0)?,
-                    max: un!(row, 1),
-                    current: un!(row, 2),
-                    total: un!(row, 3),
-                    invest: (un!(row, 4), un!(row, 5)),
-                    active: row.get(6)?,
-                })
-            })
+            .query_map(&[&economy as &dyn ToSql, &bound], |row| invest_user!(row))
             .map_err(|_e| { /* log this */ })
             .expect("get rows");
 
         iter.filter_map(Result::ok).collect()
     }
 
-    pub fn find(id: i64) -> Option<InvestUser> {
+    pub fn find(economy: &str, id: i64) -> Option<InvestUser> {
         trace!("looking up id: {}", id);
         let conn = get_connection();
-        Self::get_user_by_id(&conn, id).ok()
+        Self::get_user_by_id(&conn, economy, id).ok()
     }
 
-    pub fn stats_for(id: i64) -> (InvestUser, usize) {
+    pub fn stats_for(economy: &str, id: i64) -> (InvestUser, usize) {
         let conn = get_connection();
-        let user = Self::get_user_by_id(&conn, id).expect("get user");
-        let total = Self::get_collected(&conn);
+        let user = Self::get_user_by_id(&conn, economy, id).expect("get user");
+        let total = Self::get_collected(&conn, economy);
         (user, total)
     }
 
-    pub fn give(id: i64, credits: Credit) -> Option<Credit> {
+    pub fn give(economy: &str, id: i64, credits: Credit) -> Option<Credit> {
         trace!("trying to give {}: {} credits", id, credits);
         let conn = get_connection();
-        let mut user = Self::get_user_by_id(&conn, id).ok()?;
+        let mut user = Self::get_user_by_id(&conn, economy, id).ok()?;
         user.current += credits;
         user.total += credits;
 
@@ -124,11 +167,11 @@ impl InvestGame {
         Some(user.current)
     }
 
-    pub fn take(id: i64, credits: Credit) -> Option<Credit> {
+    pub fn take(economy: &str, id: i64, credits: Credit) -> Option<Credit> {
         trace!("trying to take {} credits from {}", credits, id);
 
         let conn = get_connection();
-        let mut user = Self::get_user_by_id(&conn, id).ok()?;
+        let mut user = Self::get_user_by_id(&conn, economy, id).ok()?;
         if credits > user.current {
             user.current = 0;
         } else {
@@ -139,22 +182,22 @@ impl InvestGame {
         Some(user.current)
     }
 
-    pub fn set_active(id: i64) {
+    pub fn set_active(economy: &str, id: i64) {
         const S: &str = r#"
             UPDATE Invest
             SET
                 Active = 1
-            WHERE ID = ?;
+            WHERE ID = ? AND Economy = ?;
         "#;
         let conn = get_connection();
-        let _ = conn.execute(S, &[&id]);
+        let _ = conn.execute(S, &[&id as &dyn ToSql, &economy]);
     }
 
-    pub fn invest(chance: f64, id: i64, want: Credit) -> InvestResult<Investment> {
+    pub fn invest(economy: &str, chance: f64, id: i64, want: Credit) -> InvestResult<Investment> {
         trace!("id {} trying to invest {} at {}", id, want, chance);
 
         let conn = get_connection();
-        let mut user = Self::get_user_by_id(&conn, id)
+        let mut user = Self::get_user_by_id(&conn, economy, id)
             .map_err(|_| InvestError::NotEnoughCredits { have: 0, want })?;
 
         if user.current < want {
@@ -177,7 +220,7 @@ impl InvestGame {
         user.total += want;
         user.invest.0 += 1;
 
-        let _ = Self::update_user(&conn, &user);
+        let _ = Self::update_user(conn, user);
 
         Ok(Investment::Success {
             old,
@@ -190,8 +233,8 @@ impl InvestGame {
         user.current -= want;
         user.invest.1 += 1;
 
-        Self::increment_collected(&conn, want);
-        let _ = Self::update_user(&conn, &user);
+        Self::increment_collected(conn, &user.economy, want);
+        let _ = Self::update_user(conn, user);
 
         Ok(Investment::Failure {
             old,
@@ -199,19 +242,20 @@ impl InvestGame {
         })
     }
 
-    pub fn increment_all_active(conn: &Connection, amount: Credit) {
+    pub fn increment_all_active(conn: &Connection, economy: &str, amount: Credit) {
         const S: &str = r#"
             UPDATE Invest 
             SET 
                 Current = Current + ?,
                 Total = Total + ?
-            WHERE Active = 1;
+            WHERE Active = 1 AND Economy = ?;
         "#;
 
-        let _ = conn.execute(S, &[&(amount as i64), &(amount as i64)]);
+        let amount = amount as i64;
+        let _ = conn.execute(S, &[&amount as &dyn ToSql, &amount, &economy]);
 
         // TODO: probably should batch these
-        Self::update_max(&conn);
+        Self::update_max(conn);
     }
 
     fn update_max(conn: &Connection) {
@@ -225,57 +269,46 @@ impl InvestGame {
         let _ = conn.execute(S, NO_PARAMS);
     }
 
-    pub fn get_collected(conn: &Connection) -> Credit {
+    pub fn get_collected(conn: &Connection, economy: &str) -> Credit {
         let mut stmt = conn
-            .prepare("SELECT Total FROM InvestStats WHERE ID = 0 LIMIT 1")
+            .prepare("SELECT Total FROM InvestStats WHERE Economy = ? LIMIT 1")
             .expect("valid sql");
         let mut iter = stmt
-            .query_map(NO_PARAMS, |row| Ok(row.get::<_, i64>(0)? as usize))
+            .query_map(&[&economy], |row| Ok(row.get::<_, i64>(0)? as usize))
             .expect("get total");
-        iter.next().expect("get total").expect("get total")
+        iter.next().and_then(Result::ok).unwrap_or_default()
     }
 
-    pub fn increment_collected(conn: &Connection, amount: Credit) {
-        const S: &str = "UPDATE InvestStats SET Total = Total + ? where ID = 0";
-        conn.execute(S, &[&(amount as i64)]).expect("update total");
+    pub fn increment_collected(conn: &Connection, economy: &str, amount: Credit) {
+        const S: &str = r#"
+            INSERT OR IGNORE INTO InvestStats (Economy, Total) VALUES(?, 0);
+        "#;
+        conn.execute(S, &[&economy]).expect("create total");
+
+        const U: &str = "UPDATE InvestStats SET Total = Total + ? where Economy = ?";
+        conn.execute(U, &[&(amount as i64) as &dyn ToSql, &economy])
+            .expect("update total");
     }
 
-    pub fn get_user_by_id(conn: &Connection, id: i64) -> InvestResult<InvestUser> {
+    pub fn get_user_by_id(conn: &Connection, economy: &str, id: i64) -> InvestResult<InvestUser> {
         let mut stmt = conn
-            .prepare("SELECT * FROM Invest WHERE ID = ? LIMIT 1")
+            .prepare("SELECT * FROM Invest WHERE ID = ? AND Economy = ? LIMIT 1")
             .expect("valid sql");
 
-        macro_rules! un {
-            ($e:expr, $n:expr) => {
-                $e.get::<_, i64>($n)? as usize
-            };
-        }
-
         let mut iter = stmt
-            .query_map(&[&id], |row| {
-                Ok(InvestUser {
-                    id: row.get(0)?,
-                    max: un!(row, 1),
-                    current: un!(row, 2),
-                    total: un!(row, 3),
-                    invest: (un!(row, 4), un!(row, 5)),
-                    active: row.get(6)?,
-                })
-            })
+            .query_map(&[&id as &dyn ToSql, &economy], |row| invest_user!(row))
             .map_err(|_err| InvestError::UserNotFound { id })?;
 
         if let Some(user) = iter.next() {
             return user.map_err(|_err| InvestError::UserNotFound { id });
         }
 
-        let user = InvestUser::new(id);
-        Self::create_user(&conn, &user)?;
+        let user = InvestUser::new(economy, id);
+        Self::create_user(conn, &user)?;
         Ok(user)
     }
 
     pub fn update_user(conn: &Connection, user: &InvestUser) -> InvestResult<()> {
-        use rusqlite::types::ToSql;
-
         const S: &str = r#"
             UPDATE Invest 
             SET 
@@ -285,7 +318,7 @@ impl InvestGame {
                 Success = ?,
                 Failure = ?,
                 Active = ?
-            WHERE ID = ?"#;
+            WHERE ID = ? AND Economy = ?"#;
 
         let map: &[&dyn ToSql] = &[
             &(user.max as i64),
@@ -295,26 +328,24 @@ impl InvestGame {
             &(user.invest.1 as i64),
             &user.active,
             &user.id,
+            &user.economy,
         ];
 
         let res = conn
             .execute(S, map)
-            .map_err(|_err| InvestError::CannotInsert { id: user.id })
-            .and_then(|_| Ok(()));
+            .map_err(|_err| InvestError::CannotInsert { id: user.id }).map(|_| ());
 
         if res.is_ok() {
-            Self::update_max(&conn);
+            Self::update_max(conn);
         }
         res
     }
 
     pub fn create_user(conn: &Connection, user: &InvestUser) -> InvestResult<()> {
-        use rusqlite::types::ToSql;
-
         const S: &str = r#"
             INSERT OR IGNORE INTO Invest 
-                (ID, Max, Current, Total, Success, Failure, Active) 
-            VALUES (?, ?, ?, ?, ?, ?, ?)"#;
+                (ID, Max, Current, Total, Success, Failure, Active, Economy) 
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#;
 
         let map: &[&dyn ToSql] = &[
             &user.id,
@@ -324,10 +355,63 @@ impl InvestGame {
             &(user.invest.0 as i64),
             &(user.invest.1 as i64),
             &user.active,
+            &user.economy,
         ];
 
         conn.execute(S, map)
-            .map_err(|_err| InvestError::CannotInsert { id: user.id })
-            .and_then(|_| Ok(()))
+            .map_err(|_err| InvestError::CannotInsert { id: user.id }).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_economy() {
+        let conn = get_connection();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE InvestStats (
+                ID INTEGER PRIMARY KEY NOT NULL UNIQUE,
+                Total INTEGER NOT NULL
+            );
+            INSERT INTO InvestStats (ID, Total) VALUES(0, 42);
+
+            CREATE TABLE Invest (
+                ID      INTEGER PRIMARY KEY NOT NULL UNIQUE,
+                Max     INTEGER NOT NULL,
+                Current INTEGER NOT NULL,
+                Total   INTEGER NOT NULL,
+                Success INTEGER NOT NULL,
+                Failure INTEGER NOT NULL,
+                Active  INTEGER NOT NULL
+            );
+            INSERT INTO Invest VALUES(1000, 50, 40, 60, 1, 2, 1);
+            "#,
+        )
+        .unwrap();
+
+        InvestGame::ensure_table(&conn);
+        InvestGame::ensure_table(&conn);
+
+        assert_eq!(
+            InvestGame::get_user_by_id(&conn, SHARED, 1000).unwrap(),
+            InvestUser {
+                id: 1000,
+                max: 50,
+                current: 40,
+                total: 60,
+                invest: (1, 2),
+                active: true,
+                economy: SHARED.into(),
+            }
+        );
+        assert_eq!(InvestGame::get_collected(&conn, SHARED), 42);
+
+        InvestGame::give("museun", 1000, 5);
+        assert_eq!(InvestGame::find("museun", 1000).unwrap().current, 5);
+        assert_eq!(InvestGame::find(SHARED, 1000).unwrap().current, 40);
+        assert_eq!(InvestGame::get_collected(&conn, "museun"), 0);
     }
 }
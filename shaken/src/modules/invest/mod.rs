@@ -8,9 +8,12 @@ use std::time::{Duration, Instant};
 
 use hashbrown::HashMap;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub const NAME: &str = "Invest";
 
+const AMOUNT: Arg = args::int_or("amount", &["all", "half", "random"]);
+
 submit! {
     template::Response("invest_no_credits", "you don't have any credits.");
     template::Response("invest_zero_number", "zero what?");
@@ -18,10 +21,8 @@ submit! {
     template::Response("invest_success_delta", "success! you went from ${old} to ${new} (+${delta})");
     template::Response("invest_failure", "failure! you went from ${old} to ${new} (-${delta}). try again in a minute");
     template::Response("invest_requires_credits", "you don't have enough. you have ${have} but you want to invest ${want}.");
-    template::Response("invest_no_target", "who do you want to give credits to?");
     template::Response("invest_send_to_self", "what are you doing?");
     template::Response("invest_send_to_bot", "I don't want any credits.");
-    template::Response("invest_unknown_target", "I don't know who that is.");
     template::Response("invest_not_enough_credits", "you only have ${current} credits.");
     template::Response("invest_send_success", "they now have ${them} credits and you're down to ${you} credits.");
     template::Response("invest_check_credits", "you have ${credits} credits.");
@@ -30,9 +31,9 @@ submit! {
 }
 
 pub struct Invest {
-    config: config::Invest,
+    config: Config,
     limit: HashMap<i64, Instant>,
-    last: Instant,
+    last: HashMap<String, Instant>, // per economy
     map: CommandMap<Invest>,
 }
 
@@ -50,12 +51,65 @@ impl Module for Invest {
     }
 
     fn tick(&mut self, dt: Instant) -> Option<Response> {
-        if dt - self.last >= Duration::from_secs(self.config.interval as u64) {
-            InvestGame::increment_all_active(&get_connection(), 1);
-            self.last = dt
+        let mut economies = vec![(SHARED.to_string(), self.config.invest.interval)];
+        for channel in &self.config.twitch.channels {
+            let config = self.config.invest_for(channel);
+            if config.separate {
+                economies.push((config::channel_name(channel), config.interval))
+            }
+        }
+
+        let conn = get_connection();
+        for (economy, interval) in economies {
+            let last = self.last.entry(economy.clone()).or_insert(dt);
+            if dt - *last >= Duration::from_secs(interval as u64) {
+                InvestGame::increment_all_active(&conn, &economy, 1);
+                *last = dt
+            }
         }
         None
     }
+
+    // keep people from getting around the rate limit by waiting for a restart
+    fn save_state(&self) -> Option<serde_json::Value> {
+        let limit = self
+            .limit
+            .iter()
+            .map(|(id, at)| (*id, util::instant_to_timestamp(*at)))
+            .collect::<HashMap<_, _>>();
+        let last = self
+            .last
+            .iter()
+            .map(|(economy, at)| (economy.clone(), util::instant_to_timestamp(*at)))
+            .collect::<HashMap<_, _>>();
+        serde_json::to_value(SavedState { limit, last }).ok()
+    }
+
+    fn restore_state(&mut self, state: serde_json::Value) {
+        let state: SavedState = match serde_json::from_value(state) {
+            Ok(state) => state,
+            Err(err) => return log::warn!("cannot restore invest state: {}", err),
+        };
+        self.limit.extend(
+            state
+                .limit
+                .into_iter()
+                .map(|(id, ts)| (id, util::timestamp_to_instant(ts))),
+        );
+        self.last.extend(
+            state
+                .last
+                .into_iter()
+                .map(|(economy, ts)| (economy, util::timestamp_to_instant(ts))),
+        );
+    }
+}
+
+// timestamps are milliseconds since the unix epoch
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    limit: HashMap<i64, u64>,
+    last: HashMap<String, u64>,
 }
 
 impl Invest {
@@ -64,25 +118,41 @@ impl Invest {
 
         let map = CommandMap::create(
             NAME,
-            &[
-                ("!invest", Self::invest_command),
-                ("!give", Self::give_command),
-                ("!check", Self::check_command),
-                ("!top5", Self::top5_command),
-                ("!top", Self::top5_command),
-                ("!stats", Self::stats_command),
+            vec![
+                Command::new("!invest", Self::invest_command)
+                    .help("invests some of your credits, for a chance at more")
+                    .args(&[AMOUNT]),
+                Command::new("!give", Self::give_command)
+                    .help("gives some of your credits to someone")
+                    .args(&[args::user("user"), AMOUNT]),
+                Command::new("!check", Self::check_command)
+                    .help("shows how many credits you have"),
+                Command::new("!top", Self::top_command)
+                    .help("shows the people with the most credits")
+                    .args(&[args::int("n").optional()]),
+                Command::new("!stats", Self::stats_command)
+                    .help("shows your investment history"),
             ],
-        )?;
+        )?
+        .aliases(&[("!top5", "!top")])?;
 
-        let config = Config::load();
         Ok(Self {
-            config: config.invest.clone(),
+            config: Config::load(),
             limit: HashMap::new(),
-            last: Instant::now(),
+            last: HashMap::new(),
             map,
         })
     }
 
+    /// Channels with `separate` set get their own economy, the rest share one
+    fn economy(&self, channel: &str) -> String {
+        if self.config.invest_for(channel).separate {
+            config::channel_name(channel)
+        } else {
+            SHARED.to_string()
+        }
+    }
+
     fn invest_command(&mut self, req: &Request) -> Option<Response> {
         let id = req.sender();
         if self.check_rate_limit(id) {
@@ -90,18 +160,20 @@ impl Invest {
             return None;
         }
 
-        let user = InvestGame::find(id);
+        let economy = self.economy(req.target());
+        let user = InvestGame::find(&economy, id);
         let user = match user.as_ref() {
             Some(user) if user.current > 0 => user,
-            None | _ => return reply_template!("invest_no_credits"),
+            _ => return reply_template!("invest_no_credits"),
         };
-        let (ty, num) = match Self::get_credits_from_args(user.current, req.args_iter()) {
-            Some((_, num)) if num == 0 => return reply_template!("invest_zero_number"),
+        let (ty, num) = match Self::get_credits_from_args(user.current, req) {
+            Some((_, 0)) => return reply_template!("invest_zero_number"),
             Some((ty, num)) => (ty, num),
             None => return reply_template!("misc_invalid_number"),
         };
 
-        match InvestGame::invest(self.config.chance, id, num) {
+        let chance = self.config.invest_for(req.target()).chance;
+        match InvestGame::invest(&economy, chance, id, num) {
             Ok(Investment::Success { old, new }) => match ty {
                 NumType::Random => reply_template!(
                     "invest_success_delta",
@@ -140,38 +212,25 @@ impl Invest {
         let conn = get_connection();
 
         let id = req.sender();
+        let economy = self.economy(req.target());
         let sender = UserStore::get_user_by_id(&conn, id)?;
-        let user = InvestGame::find(id)?;
+        let user = InvestGame::find(&economy, id)?;
         if user.current == 0 {
             return reply_template!("invest_no_credits");
         }
 
-        let mut args = req.args_iter();
-        let mut target = match args.next() {
-            Some(target) => target,
-            None => return reply_template!("invest_no_target"),
-        };
-
-        if target.starts_with('@') {
-            target = &target[1..]
-        }
-
-        if target.eq_ignore_ascii_case(&sender.display) {
+        let target = req.parsed().user("user")?;
+        if target.userid == sender.userid {
             return reply_template!("invest_send_to_self");
         }
 
         let me = UserStore::get_bot(&conn).expect("get bot user info");
-        if target.eq_ignore_ascii_case(&me.display) {
+        if target.userid == me.userid {
             return reply_template!("invest_send_to_bot");
         }
 
-        let tid = match UserStore::get_user_by_name(&conn, &target) {
-            Some(user) => user,
-            None => return reply_template!("invest_unknown_target"),
-        };
-
-        let (_ty, num) = match Self::get_credits_from_args(user.current, args) {
-            Some((_, num)) if num == 0 => return reply_template!("invest_zero_number"),
+        let (_ty, num) = match Self::get_credits_from_args(user.current, req) {
+            Some((_, 0)) => return reply_template!("invest_zero_number"),
             Some((ty, num)) => (ty, num),
             None => return reply_template!("misc_invalid_number"),
         };
@@ -184,8 +243,8 @@ impl Invest {
         }
 
         let (them, you) = {
-            let c = InvestGame::give(tid.userid, num).expect("give credits");
-            let d = InvestGame::take(user.id, num).expect("take credits");
+            let c = InvestGame::give(&economy, target.userid, num).expect("give credits");
+            let d = InvestGame::take(&economy, user.id, num).expect("take credits");
             (c, d)
         };
 
@@ -197,7 +256,8 @@ impl Invest {
     }
 
     fn check_command(&mut self, req: &Request) -> Option<Response> {
-        match InvestGame::find(req.sender()).unwrap().current {
+        let economy = self.economy(req.target());
+        match InvestGame::find(&economy, req.sender()).unwrap().current {
             credits if credits > 0 => {
                 reply_template!("invest_check_credits", ("credits", &credits.commas()))
             }
@@ -205,23 +265,15 @@ impl Invest {
         }
     }
 
-    fn top5_command(&mut self, req: &Request) -> Option<Response> {
-        let n = req
-            .args_iter()
-            .next()
-            .and_then(|s| s.parse::<u16>().ok())
-            .unwrap_or_else(|| 5);
+    fn top_command(&mut self, req: &Request) -> Option<Response> {
+        let n = req.parsed().int("n").unwrap_or(5);
 
         // sanity checks because I'm sure someone will do it
         // clamp it between 5 and 10
-        let n = match n {
-            n if n > 10 => 10,
-            n if n < 5 => 5,
-            n => n,
-        };
+        let n = n.clamp(5, 10);
 
         let conn = get_connection();
-        let list = InvestGame::get_top_n(&conn, n as i16)
+        let list = InvestGame::get_top_n(&conn, &self.economy(req.target()), n as i16)
             .into_iter()
             .enumerate()
             .map(|(i, iu)| {
@@ -239,7 +291,7 @@ impl Invest {
 
     fn stats_command(&mut self, req: &Request) -> Option<Response> {
         let id = req.sender();
-        let (user, total) = InvestGame::stats_for(id);
+        let (user, total) = InvestGame::stats_for(&self.economy(req.target()), id);
 
         reply_template!(
             "invest_stats",
@@ -252,13 +304,16 @@ impl Invest {
     }
 
     fn on_message(&self, msg: &irc::Message) -> Option<Response> {
-        if msg.expect_data().starts_with('!') || msg.expect_data().starts_with('@') {
+        let data = msg.data()?;
+        if data.starts_with('!') || data.starts_with('@') {
             return None;
         }
 
         let id = msg.tags.get_userid()?;
-        InvestGame::give(id, self.config.line_value);
-        InvestGame::set_active(id);
+        let target = msg.target()?;
+        let (economy, config) = (self.economy(target), self.config.invest_for(target));
+        InvestGame::give(&economy, id, config.line_value);
+        InvestGame::set_active(&economy, id);
 
         fn parse_decay(s: &str) -> Vec<(usize, usize)> {
             s.split(',').fold(vec![], |mut list, s| {
@@ -270,23 +325,30 @@ impl Invest {
             })
         }
 
-        let kappas = msg.tags.get_kappas()?;
-        let len = kappas.len();
-        for (points, decay) in parse_decay(&self.config.kappas) {
+        // only the different emotes count
+        let mut ids = msg.emotes().into_iter().map(|emote| emote.id).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        let len = ids.len();
+        if len == 0 {
+            return None;
+        }
+        for (points, decay) in parse_decay(&config.kappas) {
             if len <= decay {
-                InvestGame::give(id, points);
+                InvestGame::give(&economy, id, points);
                 return None;
             }
         }
         None
     }
 
-    fn get_credits_from_args<'a>(
-        credits: Credit,
-        mut parts: impl Iterator<Item = &'a str>,
-    ) -> Option<(NumType, Credit)> {
-        let data = parts.next()?.trim();
-        let ty = parse_number_or_context(&data)?;
+    fn get_credits_from_args(credits: Credit, req: &Request) -> Option<(NumType, Credit)> {
+        let args = req.parsed();
+        let ty = match args.int("amount") {
+            Some(n) if n >= 0 => NumType::Num(n as Credit),
+            Some(..) => return None,
+            None => args.word("amount")?.into(),
+        };
         Some((
             ty,
             match ty {
@@ -320,29 +382,6 @@ enum NumType {
     Random,
 }
 
-fn parse_number_or_context(data: &str) -> Option<NumType> {
-    const CONTEXTS: [&str; 3] = ["all", "half", "random"];
-
-    let num = data
-        .chars()
-        .take_while(char::is_ascii_digit)
-        .collect::<String>();
-
-    if let Ok(num) = num.parse::<usize>() {
-        return Some(NumType::Num(num));
-    }
-
-    if let Some(part) = data.split_whitespace().take(1).next() {
-        for ctx in &CONTEXTS {
-            if &part == ctx {
-                return Some(part.into());
-            }
-        }
-    }
-
-    None
-}
-
 impl From<&str> for NumType {
     fn from(s: &str) -> Self {
         match s {
@@ -364,14 +403,14 @@ mod tests {
         let db = database::get_connection();
         {
             let mut invest = Invest::create().unwrap();
-            invest.config.chance = 0.0;
+            invest.config.invest.chance = 0.0;
             let mut env = Environment::new(&db, &mut invest);
 
             env.push("!invest 10");
             env.step();
             assert_eq!(env.pop().unwrap(), "@test: you don't have any credits.");
 
-            InvestGame::give(env.get_user_id(), 100);
+            InvestGame::give(SHARED, env.get_user_id(), 100);
             env.push("!invest 10");
             env.step();
             assert_eq!(
@@ -381,7 +420,7 @@ mod tests {
         };
 
         let mut invest = Invest::create().unwrap();
-        invest.config.chance = 1.0;
+        invest.config.invest.chance = 1.0;
         let mut env = Environment::new(&db, &mut invest);
 
         env.push("!invest 10");
@@ -401,33 +440,44 @@ mod tests {
         let mut invest = Invest::create().unwrap();
         let mut env = Environment::new(&db, &mut invest);
 
-        env.push("!give foo 10");
+        env.push("!give shaken_bot 10");
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: you don't have any credits.");
 
-        InvestGame::give(env.get_user_id(), 100);
+        InvestGame::give(SHARED, env.get_user_id(), 100);
         env.push("!give");
         env.step();
         assert_eq!(
             env.pop().unwrap(),
-            "@test: who do you want to give credits to?"
+            "@test: <user> is missing. usage: !give <user> <amount|all|half|random>"
         );
 
-        env.push("!give test");
+        env.push("!give @test 10");
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: what are you doing?");
 
-        env.push("!give shaken_bot");
+        env.push("!give shaken_bot 10");
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: I don't want any credits.");
 
-        env.push("!give foo");
+        env.push("!give foo 10");
         env.step();
-        assert_eq!(env.pop().unwrap(), "@test: I don't know who that is.");
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: I don't know who foo is. usage: !give <user> <amount|all|half|random>"
+        );
 
         let _user = make_test_user(&db, "foo", 1001);
 
-        env.push("!give foo");
+        env.push("!give foo lots");
+        env.step();
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: 'lots' isn't a number or one of: all, half, random. usage: !give <user> \
+             <amount|all|half|random>"
+        );
+
+        env.push("!give foo -5");
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: thats not a number I understand");
 
@@ -453,7 +503,7 @@ mod tests {
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: you don't have any credits.");
 
-        InvestGame::give(env.get_user_id(), 100);
+        InvestGame::give(SHARED, env.get_user_id(), 100);
 
         env.push("!check");
         env.step();
@@ -473,9 +523,9 @@ mod tests {
                 .sample_iter(&Alphanumeric)
                 .take(10)
                 .collect::<String>();
-            let _u = make_test_user(&db, &name, n as i64);
+            let _u = make_test_user(&db, &name, n);
             let r = thread_rng().gen::<u16>();
-            InvestGame::give(n, r as usize);
+            InvestGame::give(SHARED, n, r as usize);
         }
 
         env.push("!top5");
@@ -498,4 +548,70 @@ mod tests {
         env.step();
         assert!(env.pop().is_some());
     }
+
+    #[test]
+    fn emote_lines() {
+        let db = database::get_connection();
+        let mut invest = Invest::create().unwrap();
+        let mut env = Environment::new(&db, &mut invest);
+        let id = env.get_user_id();
+
+        let mut push = |emotes: &str, data: &str| {
+            env.push_raw(&format!(
+                "@emotes={};user-id={};display-name=test;color=#FFFFFF :test!user@irc.test \
+                 PRIVMSG #test :{}",
+                emotes, id, data
+            ));
+            env.step_wait(false);
+        };
+
+        // kappas = "5:1,3:3,1:1", so a single emote is worth the most
+        push("", "hello");
+        assert_eq!(InvestGame::find(SHARED, id).unwrap().current, 5);
+        push("25:6-10", "héllo Kappa");
+        assert_eq!(InvestGame::find(SHARED, id).unwrap().current, 5 + 5 + 5);
+        // the same emote twice is still a single emote
+        push("25:0-4,6-10", "Kappa Kappa");
+        assert_eq!(InvestGame::find(SHARED, id).unwrap().current, 15 + 5 + 5);
+        push("25:0-4/354:6-10", "Kappa 4Head");
+        assert_eq!(InvestGame::find(SHARED, id).unwrap().current, 25 + 5 + 3);
+    }
+
+    #[test]
+    fn separate_economy() {
+        let db = database::get_connection();
+        let mut invest = Invest::create().unwrap();
+        invest.config.channels.insert(
+            "other".into(),
+            config::Channel {
+                invest: Some(config::Invest {
+                    separate: true,
+                    ..invest.config.invest.clone()
+                }),
+                ..Default::default()
+            },
+        );
+        let mut env = Environment::new(&db, &mut invest);
+
+        InvestGame::give(SHARED, env.get_user_id(), 100);
+        env.push("!check");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you have 100 credits.");
+
+        let other = "@user-id=1000;display-name=test :test!user@irc.test PRIVMSG #other :";
+        env.push_raw(&format!("{}!check", other));
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you don't have any credits.");
+
+        // chatting gives credits in that channel's economy
+        env.push_raw(&format!("{}hello", other));
+        env.step_wait(false);
+        env.push_raw(&format!("{}!check", other));
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you have 5 credits.");
+
+        env.push("!check");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: you have 100 credits.");
+    }
 }
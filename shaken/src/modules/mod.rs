@@ -2,6 +2,7 @@ macro_rules! export {
     ($($m:tt),*) => {
         $(
             mod $m;
+            #[allow(ambiguous_glob_reexports)]
             pub use self::$m::*;
         )*
 
@@ -32,19 +32,22 @@ impl RustStuff {
         Ok(Self {
             map: CommandMap::create(
                 "RustStuff",
-                &[
-                    ("!crate", Self::crates_command),  //
-                    ("!crates", Self::crates_command), //
+                vec![
+                    Command::new("!crate", Self::crates_command)
+                        .help("looks up a crate on crates.io")
+                        .args(&[args::word("name")]),
                 ],
-            )?,
+            )?
+            .aliases(&[("!crates", "!crate")])?
+            .cooldowns(&[("!crate", Cooldown::user(15))]),
         })
     }
 }
 
 impl RustStuff {
     pub fn crates_command(&mut self, req: &Request) -> Option<Response> {
-        let query = req.args();
-        let c = match Self::lookup_crate(&query) {
+        let query = req.parsed().text("name")?;
+        let c = match Self::lookup_crate(query) {
             Ok(c) => c,
             Err(Error::NoMatches) => {
                 return reply_template!("rust_no_crate", ("crate", &query));
@@ -76,7 +79,7 @@ impl RustStuff {
 
         util::http_get_json::<Resp>(&url)
             .map_err(|_e| Error::CratesGet)
-            .and_then(|mut s| s.crates.pop().ok_or_else(|| Error::NoMatches))
+            .and_then(|mut s| s.crates.pop().ok_or(Error::NoMatches))
     }
 }
 #[allow(dead_code)]
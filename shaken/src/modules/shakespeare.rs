@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use hashbrown::HashMap;
 use log::*;
 use rand::prelude::*;
 use std::borrow::Cow;
@@ -27,21 +28,34 @@ pub const NAME: &str = "Shakespeare";
 submit! {
     template::Response("shakespeare_config", "what do you want to configure: ${options}");
     template::Response("shakespeare_chance", "chance has to be ${min} <= chance <= ${max}");
-    template::Response("shakespeare_unknown_config", "I don't know how to configure that");
     template::Response("shakespeare_required_value", "provide a value, please");
 }
 
 pub struct Shakespeare {
     map: CommandMap<Shakespeare>,
     markovs: Vec<Box<dyn Markov>>,
+    config: Config,
+    channels: HashMap<String, Channel>,
+}
 
+struct Channel {
     previous: Option<Instant>,
-    limit: Duration,
     interval: f64,
     chance: f64,
     bypass: usize, // is this even needed?
 }
 
+impl Channel {
+    fn new(config: &config::Shakespeare) -> Self {
+        Self {
+            previous: None,
+            interval: config.interval as f64,
+            chance: config.chance,
+            bypass: config.bypass,
+        }
+    }
+}
+
 impl Module for Shakespeare {
     fn command(&mut self, req: &Request) -> Option<Response> {
         let map = self.map.clone();
@@ -49,9 +63,10 @@ impl Module for Shakespeare {
     }
 
     fn passive(&mut self, msg: &irc::Message) -> Option<Response> {
-        self.check_mentions(&msg).or_else(|| {
-            if !msg.expect_data().starts_with('!') {
-                self.auto_speak()
+        let channel = msg.target()?;
+        self.check_mentions(channel, msg).or_else(|| {
+            if !msg.data()?.starts_with('!') {
+                self.auto_speak(channel)
             } else {
                 None
             }
@@ -63,107 +78,162 @@ impl Shakespeare {
     pub fn create(markovs: Vec<Box<dyn Markov>>) -> Result<Self, ModuleError> {
         let map = CommandMap::create(
             "Shakespeare",
-            &[
-                ("!speak configure", Self::configure_command),
-                ("!speak", Self::speak_command),
+            vec![
+                Command::new("!speak configure", Self::configure_command)
+                    .help("changes how often the bot speaks on its own")
+                    .args(&[
+                        args::choice("option", &["interval", "chance", "bypass"]).optional(),
+                        args::word("value").optional(),
+                    ]),
+                Command::new("!speak", Self::speak_command)
+                    .help("makes the bot say something"),
             ],
-        )?;
-        let config = Config::load();
-
+        )?
+        .permissions(&[("!speak configure", Role::Moderator)]);
         Ok(Self {
             map,
             markovs,
-
-            previous: None,
-            limit: Duration::from_secs(config.shakespeare.interval as u64),
-            interval: config.shakespeare.interval as f64,
-            chance: config.shakespeare.chance,
-            bypass: config.shakespeare.bypass,
+            config: Config::load(),
+            channels: HashMap::new(),
         })
     }
 
-    fn speak_command(&mut self, _: &Request) -> Option<Response> {
-        say!(self.generate()?)
+    fn channel(&mut self, channel: &str) -> &mut Channel {
+        let config = &self.config;
+        self.channels
+            .entry(config::channel_name(channel))
+            .or_insert_with(|| Channel::new(config.shakespeare_for(channel)))
+    }
+
+    fn speak_command(&mut self, req: &Request) -> Option<Response> {
+        say!(self.generate(req.target())?)
     }
 
     fn configure_command(&mut self, req: &Request) -> Option<Response> {
-        require_privileges!(&req);
-        let args = req.args_iter().collect::<Vec<_>>();
-        if args.is_empty() {
-            return reply_template!(
-                "shakespeare_config",
-                ("options", &["interval, chance", "bypass"].join(", "))
-            );
-        }
+        let args = req.parsed();
+        let option = match args.word("option") {
+            Some(option) => option,
+            None => {
+                return reply_template!(
+                    "shakespeare_config",
+                    ("options", &["interval, chance", "bypass"].join(", "))
+                )
+            }
+        };
 
-        let res = match args.as_slice() {
-            ["interval", n] => {
+        let channel = self.channel(req.target());
+        let res = match (option, args.text("value")) {
+            ("interval", Some(n)) => {
                 if let Ok(n) = n.parse::<f64>() {
-                    self.interval = n;
+                    channel.interval = n;
                     reply_template!("misc_done")
                 } else {
                     reply_template!("misc_invalid_number")
                 }
             }
-            ["chance", n] => {
+            ("chance", Some(n)) => {
                 if let Ok(n) = n.parse::<f64>() {
-                    if n > 1.0 || n < 0.0 {
+                    if !(0.0..=1.0).contains(&n) {
                         return reply_template!(
                             "shakespeare_chance",
                             ("min", &"0.0"),
                             ("max", &"1.0")
                         );
                     }
-                    self.chance = n;
+                    channel.chance = n;
                     reply_template!("misc_done")
                 } else {
                     reply_template!("misc_invalid_number")
                 }
             }
-            ["bypass", n] => {
+            ("bypass", Some(n)) => {
                 if let Ok(n) = n.parse::<usize>() {
-                    self.bypass = n;
+                    channel.bypass = n;
                     reply_template!("misc_done")
                 } else {
                     reply_template!("misc_invalid_number")
                 }
             }
-            ["interval"] | ["chance"] | ["bypass"] => reply_template!("shakespeare_required_value"),
-            _ => reply_template!("shakespeare_unknown_config"),
+            _ => reply_template!("shakespeare_required_value"),
         };
 
+        let (interval, chance, bypass) = (channel.interval, channel.chance, channel.bypass);
+        let name = config::channel_name(req.target());
+
+        // channels with an override only change that override,
+        // otherwise it changes the section shared by the rest of them
+        let shared = Self::update_config(&mut self.config, &name, interval, chance, bypass);
+        if shared {
+            let config = &self.config;
+            for (_, ch) in self
+                .channels
+                .iter_mut()
+                .filter(|(k, _)| {
+                    config
+                        .channels
+                        .get(k.as_str())
+                        .is_none_or(|ch| ch.shakespeare.is_none())
+                })
+            {
+                ch.interval = interval;
+                ch.chance = chance;
+                ch.bypass = bypass;
+            }
+        }
+
         let mut config = Config::load();
-        config.shakespeare.chance = self.chance;
-        config.shakespeare.interval = self.interval as usize; // what
-        config.shakespeare.bypass = self.bypass;
+        Self::update_config(&mut config, &name, interval, chance, bypass);
         config.save();
 
         res
     }
 
-    fn auto_speak(&mut self) -> Option<Response> {
-        let bypass = if let Some(prev) = self.previous {
+    /// returns false if the channel had its own section
+    fn update_config(
+        config: &mut Config,
+        name: &str,
+        interval: f64,
+        chance: f64,
+        bypass: usize,
+    ) -> bool {
+        let (section, shared) = match config
+            .channels
+            .get_mut(name)
+            .and_then(|ch| ch.shakespeare.as_mut())
+        {
+            Some(section) => (section, false),
+            None => (&mut config.shakespeare, true),
+        };
+        section.chance = chance;
+        section.interval = interval as usize; // what
+        section.bypass = bypass;
+        shared
+    }
+
+    fn auto_speak(&mut self, target: &str) -> Option<Response> {
+        let channel = self.channel(target);
+        let bypass = if let Some(prev) = channel.previous {
             let left = Instant::now().duration_since(prev);
-            let right = Duration::from_secs(self.bypass as u64);
+            let right = Duration::from_secs(channel.bypass as u64);
             left > right
         } else {
-            self.bypass == 0
+            channel.bypass == 0
         };
 
         if bypass {
             trace!("bypassing the roll");
         }
 
-        if bypass || thread_rng().gen_bool(self.chance) {
+        if bypass || thread_rng().gen_bool(channel.chance) {
             trace!("automatically trying to speak");
-            let resp = self.generate()?;
+            let resp = self.generate(target)?;
             std::thread::sleep(Duration::from_millis(thread_rng().gen_range(150, 750)));
             return say!(resp);
         }
         None
     }
 
-    fn check_mentions(&mut self, msg: &irc::Message) -> Option<Response> {
+    fn check_mentions(&mut self, target: &str, msg: &irc::Message) -> Option<Response> {
         let user = UserStore::get_bot(&get_connection())?;
 
         fn trim_then_check(s: &str, nick: &str) -> bool {
@@ -171,34 +241,35 @@ impl Shakespeare {
             !s.is_empty() && s[1..].eq_ignore_ascii_case(nick)
         }
 
-        for part in msg.expect_data().split_whitespace() {
-            if part.starts_with('@') && trim_then_check(&part, &user.display) {
+        for part in msg.data()?.split_whitespace() {
+            if part.starts_with('@') && trim_then_check(part, &user.display) {
                 trace!("got a mention, trying to speak");
-                return say!(self.generate()?);
+                return say!(self.generate(target)?);
             }
         }
         None
     }
 
-    fn generate(&mut self) -> Option<String> {
-        self.markovs.shuffle(&mut thread_rng());
-        let markov = self.markovs.get(0)?;
-
+    fn generate(&mut self, target: &str) -> Option<String> {
         let now = Instant::now();
-        if let Some(prev) = self.previous {
-            if now.duration_since(prev) < self.limit {
+        let channel = self.channel(target);
+        if let Some(prev) = channel.previous {
+            if now.duration_since(prev) < Duration::from_secs(channel.interval as u64) {
                 let (secs, nanos) = {
                     let dur = now.duration_since(prev);
                     (dur.as_secs() as f64, f64::from(dur.subsec_nanos()) * 1e-9)
                 };
 
-                let rem = self.interval - secs + nanos;
+                let rem = channel.interval - secs + nanos;
                 debug!("already spoke: {:.3}s remaining", rem);
                 return None;
             }
         }
 
-        loop {
+        self.markovs.shuffle(&mut thread_rng());
+        let markov = self.markovs.first()?;
+
+        let data = loop {
             let data = match markov.get_next() {
                 Some(data) => data,
                 None => {
@@ -208,16 +279,17 @@ impl Shakespeare {
             };
 
             trace!("generated a message");
-            self.previous = Some(now);
-
             let data = prune(&data);
             if data.chars().filter(char::is_ascii_whitespace).count() < 3 {
                 trace!("trying for a better sentence");
                 continue;
             }
 
-            return Some([data, "."].concat());
-        }
+            break [data, "."].concat();
+        };
+
+        self.channel(target).previous = Some(now);
+        Some(data)
     }
 }
 
@@ -306,13 +378,15 @@ mod tests {
             env.step();
             assert_eq!(
                 env.pop().unwrap(),
-                "@test: I don't know how to configure that"
+                "@test: 'foobar' isn't one of: interval, chance, bypass. usage: !speak configure \
+                 [interval|chance|bypass] [value]"
             );
         }
 
-        assert_eq!(shakespeare.interval, 1.0);
-        assert_eq!(shakespeare.chance, 0.2);
-        assert_eq!(shakespeare.bypass, 1);
+        let channel = shakespeare.channel("#test");
+        assert_eq!(channel.interval, 1.0);
+        assert_eq!(channel.chance, 0.2);
+        assert_eq!(channel.bypass, 1);
 
         // env.drain_and_log();
     }
@@ -321,7 +395,7 @@ mod tests {
     fn auto_speak() {
         let db = database::get_connection();
         let mut shakespeare = Shakespeare::create(vec![Box::new(TestMarkov {})]).unwrap();
-        shakespeare.bypass = 0;
+        shakespeare.channel("#test").bypass = 0;
         let mut env = Environment::new(&db, &mut shakespeare);
 
         env.push("testing this out");
@@ -349,4 +423,38 @@ mod tests {
         env.step_wait(false);
         assert_eq!(env.pop(), None);
     }
+
+    #[test]
+    fn per_channel() {
+        let db = database::get_connection();
+        let mut shakespeare = Shakespeare::create(vec![Box::new(TestMarkov {})]).unwrap();
+        shakespeare.config.channels.insert(
+            "other".into(),
+            config::Channel {
+                shakespeare: Some(config::Shakespeare {
+                    chance: 0.0,
+                    bypass: 600,
+                    interval: 30,
+                    brains: vec![],
+                }),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(shakespeare.channel("#another").chance, 0.15);
+        assert_eq!(shakespeare.channel("#other").chance, 0.0);
+
+        {
+            let mut env = Environment::new(&db, &mut shakespeare);
+            env.push_broadcaster("!speak configure chance 0.5");
+            env.step();
+            assert_eq!(env.pop().unwrap(), "@test: done");
+        }
+
+        // channels without an override share the change
+        assert_eq!(shakespeare.channel("#test").chance, 0.5);
+        assert_eq!(shakespeare.channel("#another").chance, 0.5);
+        assert_eq!(shakespeare.channel("#other").chance, 0.0);
+        assert_eq!(shakespeare.channel("#other").interval, 30.0);
+    }
 }
@@ -3,8 +3,9 @@ use crate::prelude::*;
 use std::str;
 use std::time::{Duration, Instant};
 
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use log::*;
+use serde::{Deserialize, Serialize};
 
 pub const NAME: &str = "TwitchPoll";
 
@@ -23,11 +24,16 @@ submit! {
 }
 
 pub struct TwitchPoll {
+    channels: HashMap<String, Channel>, // each channel has its own poll
+    map: CommandMap<TwitchPoll>,
+}
+
+#[derive(Default)]
+struct Channel {
     poll: Option<Poll>,
     start: Option<Instant>,
     duration: usize,
     running: bool,
-    map: CommandMap<TwitchPoll>,
 }
 
 impl Module for TwitchPoll {
@@ -39,39 +45,92 @@ impl Module for TwitchPoll {
     fn tick(&mut self, dt: Instant) -> Option<Response> {
         self.handle_tick(dt)
     }
+
+    // polls keep running across restarts
+    fn save_state(&self) -> Option<serde_json::Value> {
+        let channels = self
+            .channels
+            .iter()
+            .filter(|(_, ch)| ch.poll.is_some())
+            .map(|(name, ch)| {
+                let saved = SavedChannel {
+                    poll: ch.poll.clone(),
+                    started_at: ch.start.map(util::instant_to_timestamp),
+                    duration: ch.duration,
+                    running: ch.running,
+                };
+                (name.clone(), saved)
+            })
+            .collect::<HashMap<_, _>>();
+        serde_json::to_value(channels).ok()
+    }
+
+    fn restore_state(&mut self, state: serde_json::Value) {
+        let channels: HashMap<String, SavedChannel> = match serde_json::from_value(state) {
+            Ok(channels) => channels,
+            Err(err) => return warn!("cannot restore polls: {}", err),
+        };
+        for (name, saved) in channels {
+            let channel = Channel {
+                poll: saved.poll,
+                start: saved.started_at.map(util::timestamp_to_instant),
+                duration: saved.duration,
+                running: saved.running,
+            };
+            self.channels.insert(name, channel);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedChannel {
+    poll: Option<Poll>,
+    started_at: Option<u64>, // milliseconds since the unix epoch
+    duration: usize,
+    running: bool,
 }
 
 impl TwitchPoll {
     pub fn create() -> Result<Self, ModuleError> {
         let map = CommandMap::create(
             NAME,
-            &[
-                ("!poll", Self::poll_command),
-                ("!poll start", Self::poll_start_command),
-                ("!poll stop", Self::poll_stop_command),
-                ("!vote", Self::poll_vote_command),
+            vec![
+                Command::new("!poll", Self::poll_command)
+                    .help("makes a poll, like !poll title | option | option"),
+                Command::new("!poll start", Self::poll_start_command)
+                    .help("starts the poll, for this long")
+                    .args(&[args::duration("duration").optional()]),
+                Command::new("!poll stop", Self::poll_stop_command)
+                    .help("stops the running poll"),
+                Command::new("!vote", Self::poll_vote_command)
+                    .help("votes for an option in the running poll"),
             ],
-        )?;
+        )?
+        .permissions(&[
+            ("!poll", Role::Broadcaster),
+            ("!poll start", Role::Broadcaster),
+            ("!poll stop", Role::Broadcaster),
+        ]);
 
         Ok(Self {
-            poll: None,
-            start: None,
-            duration: 0,
-            running: false,
+            channels: HashMap::new(),
             map,
         })
     }
 
-    fn poll_command(&mut self, req: &Request) -> Option<Response> {
-        require_broadcaster!(&req, "You cannot do that.");
+    fn channel(&mut self, req: &Request) -> &mut Channel {
+        self.channels.entry(req.target().to_string()).or_default()
+    }
 
+    fn poll_command(&mut self, req: &Request) -> Option<Response> {
         let poll = match Self::parse_poll(req.target(), req.args()) {
             Ok(poll) => poll,
             Err(ParseError::Title) => return reply_template!("twitchpoll_parse_error_title"),
             Err(ParseError::Options) => return reply_template!("twitchpoll_parse_error_options"),
         };
 
-        if self.running {
+        let channel = self.channel(req);
+        if channel.running {
             return reply_template!("twitchpoll_already_running");
         }
 
@@ -88,63 +147,53 @@ impl TwitchPoll {
             }))
         );
 
-        std::mem::replace(&mut self.poll, Some(poll));
+        channel.poll.replace(poll);
         res
     }
 
     fn poll_start_command(&mut self, req: &Request) -> Option<Response> {
-        require_broadcaster!(&req);
-
-        if self.poll.is_none() {
+        let channel = self.channel(req);
+        if channel.poll.is_none() {
             warn!("no poll");
             return reply_template!("twitchpoll_not_configured");
         }
 
-        let dur = match req.args_iter().next().and_then(|a| {
-            a.chars()
-                .take_while(char::is_ascii_digit)
-                .collect::<String>()
-                .parse::<usize>()
-                .ok()
-        }) {
-            Some(n) => n,
-            None => return reply_template!("twitchpoll_unknown_duration"),
+        let dur = match req.parsed().duration("duration") {
+            Some(dur) if dur.as_secs() > 0 => dur.as_secs() as usize,
+            _ => return reply_template!("twitchpoll_unknown_duration"),
         };
 
-        self.running = true;
-        self.duration = dur;
-        std::mem::replace(&mut self.start, Some(Instant::now()));
+        channel.running = true;
+        channel.duration = dur;
+        channel.start.replace(Instant::now());
         say_template!("twitchpoll_start", ("dur", &dur))
     }
 
     fn poll_stop_command(&mut self, req: &Request) -> Option<Response> {
-        require_broadcaster!(&req);
-
-        if !self.running {
+        let channel = self.channel(req);
+        if !channel.running {
             return reply_template!("twitchpoll_poll_not_running");
         }
 
-        info!("stopping poll");
-        self.running = false;
-        self.duration = 0;
-        self.start.take();
-        self.poll.take();
-
+        info!("stopping poll in {}", req.target());
+        *channel = Channel::default();
         None
     }
 
     fn poll_vote_command(&mut self, req: &Request) -> Option<Response> {
-        if !self.running {
+        let channel = self.channel(req);
+        if !channel.running {
             debug!("poll not running");
             return None;
         }
 
-        if self.poll.is_none() {
-            warn!("tried to vote on an inactive poll. this shouldn't be reachable");
-            return None;
-        }
-
-        let poll = self.poll.as_mut().unwrap();
+        let poll = match channel.poll.as_mut() {
+            Some(poll) => poll,
+            None => {
+                warn!("tried to vote on an inactive poll. this shouldn't be reachable");
+                return None;
+            }
+        };
         let max = poll.choices.len();
 
         let n = match req.args_iter().next().and_then(|a| {
@@ -166,46 +215,51 @@ impl TwitchPoll {
         None
     }
 
-    fn handle_tick(&mut self, _dt: Instant) -> Option<Response> {
-        if !self.running || self.start.is_none() {
-            return None;
-        }
+    fn handle_tick(&mut self, now: Instant) -> Option<Response> {
 
-        let dt = Instant::now(); // don't trust the delta
+        let mut finished = vec![];
+        for channel in self.channels.values_mut() {
+            let start = match channel.start {
+                Some(start) if channel.running => start,
+                _ => continue,
+            };
 
-        let deadline = Duration::from_secs(self.duration as u64);
-        if let Some(start) = self.start {
-            warn!("{:?} - {:?} < {:?}", dt, start, deadline);
-            if dt - start < deadline {
-                return None;
+            let deadline = Duration::from_secs(channel.duration as u64);
+            if now.saturating_duration_since(start) < deadline {
+                continue;
             }
-        }
 
-        info!("tallying the poll");
-        self.running = false;
+            info!("tallying the poll");
+            let poll = channel.poll.take().expect("poll should have been running");
+            *channel = Channel::default();
+            finished.push(poll);
+        }
 
-        let mut poll = {
-            self.start.take();
-            self.poll.take().expect("poll should have been running")
-        };
+        if finished.is_empty() {
+            return None;
+        }
 
-        let target = poll.target.clone(); // this is dumb
-        let res = poll.tally().iter().take(3).map(|opt| {
-            let args = template::TemplateArgs::new()
-                .with("count", &opt.count)
-                .with("pos", &(opt.pos + 1))
-                .with("option", &opt.option)
-                .build();
-            let out = template::lookup("twitchpoll_result", &args).unwrap();
-            privmsg!(&target, out)
-        });
-
-        multi(res)
+        multi(finished.into_iter().flat_map(|mut poll| {
+            let target = poll.target.clone(); // this is dumb
+            poll.tally()
+                .iter()
+                .take(3)
+                .map(|opt| {
+                    let args = template::TemplateArgs::new()
+                        .with("count", &opt.count)
+                        .with("pos", &(opt.pos + 1))
+                        .with("option", &opt.option)
+                        .build();
+                    let out = template::lookup("twitchpoll_result", &args).unwrap();
+                    privmsg!(&target, out)
+                })
+                .collect::<Vec<_>>()
+        }))
     }
 
     fn parse_poll(target: &str, data: &str) -> Result<Poll, ParseError> {
         let mut iter = data.split('|').map(str::trim).filter(|s| !s.is_empty());
-        let title = iter.next().ok_or_else(|| ParseError::Title)?;
+        let title = iter.next().ok_or(ParseError::Title)?;
         Poll::new(target, title, iter)
     }
 }
@@ -216,14 +270,14 @@ enum ParseError {
     Options,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Choice {
     pos: usize,
     count: usize,
     option: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Poll {
     target: String,
     title: String,
@@ -274,7 +328,7 @@ impl Poll {
     }
 
     pub fn tally(&mut self) -> &[Choice] {
-        self.choices.sort_by(|l, r| r.count.cmp(&l.count));
+        self.choices.sort_by_key(|c| std::cmp::Reverse(c.count));
         &self.choices
     }
 }
@@ -309,7 +363,7 @@ mod tests {
 
         env.push("!poll");
         env.step_wait(false);
-        assert_eq!(env.pop(), Some("@test: You cannot do that.".into()));
+        assert_eq!(env.pop(), None);
 
         env.push_broadcaster("!poll");
         env.step();
@@ -358,6 +412,10 @@ mod tests {
         env.step();
         assert_eq!(env.pop().unwrap(), "@test: I don't know how long that is");
 
+        env.push_broadcaster("!poll start 0s");
+        env.step();
+        assert_eq!(env.pop().unwrap(), "@test: I don't know how long that is");
+
         env.push_broadcaster("!poll start 160");
         env.step();
         assert_eq!(
@@ -397,10 +455,11 @@ mod tests {
             assert_eq!(env.pop().unwrap(), "@test: no poll is running");
         }
 
-        assert!(poll.poll.is_none());
-        assert!(poll.start.is_none());
-        assert_eq!(poll.duration, 0);
-        assert_eq!(poll.running, false);
+        let channel = &poll.channels["#test"];
+        assert!(channel.poll.is_none());
+        assert!(channel.start.is_none());
+        assert_eq!(channel.duration, 0);
+        assert!(!channel.running);
     }
 
     #[test]
@@ -411,7 +470,7 @@ mod tests {
 
         env.push("!poll vote");
         env.step_wait(false);
-        assert_eq!(env.pop(), Some("@test: You cannot do that.".into()));
+        assert_eq!(env.pop(), None);
 
         env.push_broadcaster("!poll test poll | option a | option b");
         env.step_wait(false);
@@ -442,11 +501,96 @@ mod tests {
 
         env.drain();
 
-        // TODO don't do this
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        env.tick();
+        env.tick_at(Instant::now() + Duration::from_secs(1));
 
         assert_eq!(env.pop().unwrap(), "(3 votes) #1 option a");
         assert_eq!(env.pop().unwrap(), "(1 votes) #2 option b");
     }
+
+    #[test]
+    fn per_channel() {
+        let db = database::get_connection();
+        let mut poll = TwitchPoll::create().unwrap();
+        let mut env = Environment::new(&db, &mut poll);
+
+        let other = |data: &str| {
+            format!(
+                "@badges=broadcaster/1;user-id=1000;display-name=test :test!user@irc.test \
+                 PRIVMSG #other :{}",
+                data
+            )
+        };
+
+        env.push_broadcaster("!poll test poll | option a | option b");
+        env.step();
+        env.drain();
+
+        env.push_raw(&other("!poll start 1"));
+        env.step();
+        assert_eq!(
+            env.pop().unwrap(),
+            "@test: no poll has been configured. use !poll title | options | ..."
+        );
+
+        env.push_raw(&other("!poll other poll | option c | option d"));
+        env.step();
+        env.drain();
+
+        env.push_broadcaster("!poll start 1");
+        env.step();
+        env.drain();
+
+        env.push_raw(&other("!poll start 1"));
+        env.step();
+        env.drain();
+
+        env.push_user("!vote 2", ("test", 1001));
+        env.step_wait(false);
+
+        env.tick_at(Instant::now() + Duration::from_secs(1));
+
+        let mut results = vec![];
+        while let Some(line) = env.pop_raw() {
+            results.push(line);
+        }
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                "PRIVMSG #other :(0 votes) #1 option c",
+                "PRIVMSG #other :(0 votes) #2 option d",
+                "PRIVMSG #test :(0 votes) #1 option a",
+                "PRIVMSG #test :(1 votes) #2 option b",
+            ]
+        );
+    }
+
+    #[test]
+    fn poll_survives_restart() {
+        let db = database::get_connection();
+        let mut poll = TwitchPoll::create().unwrap();
+        let state = {
+            let mut env = Environment::new(&db, &mut poll);
+            env.push_broadcaster("!poll test poll | option a | option b");
+            env.step();
+            env.push_broadcaster("!poll start 160");
+            env.step();
+            env.drain();
+            env.push("!vote 2");
+            env.step_wait(false);
+            env.module().save_state().unwrap()
+        };
+
+        let mut poll = TwitchPoll::create().unwrap();
+        poll.restore_state(state);
+        let channel = &poll.channels["#test"];
+        assert!(channel.running);
+        assert_eq!(channel.duration, 160);
+        assert!(channel.start.unwrap().elapsed() < Duration::from_secs(5));
+
+        let poll = channel.poll.as_ref().unwrap();
+        assert_eq!(poll.title, "test poll");
+        assert_eq!(poll.choices[1].count, 1);
+        assert!(poll.seen.contains(&1000));
+    }
 }
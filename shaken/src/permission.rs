@@ -0,0 +1,367 @@
+use crate::prelude::*;
+
+use std::sync::RwLock;
+
+use hashbrown::HashMap;
+use log::*;
+use once_cell::{sync::Lazy, sync_lazy};
+use rusqlite::{types::ToSql, Connection, OptionalExtension};
+
+static OWNERS: Lazy<RwLock<Vec<i64>>> = sync_lazy! {
+    RwLock::new(Config::load().twitch.owners)
+};
+
+// the roles users had from their badges, by channel, when we last saw them
+static BADGES: Lazy<RwLock<HashMap<i64, HashMap<String, Role>>>> = sync_lazy! {
+    RwLock::new(HashMap::new())
+};
+
+// the roles commands were declared with
+static DECLARED: Lazy<RwLock<HashMap<String, Role>>> = sync_lazy! {
+    RwLock::new(HashMap::new())
+};
+
+/// Who someone is, from the least to the most trusted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Everyone,
+    Regular, // granted with `!perm grant`
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+    Owner, // from the config
+}
+
+impl Role {
+    /// The names of the roles, in order
+    pub const NAMES: &'static [&'static str] = &[
+        "everyone",
+        "regular",
+        "subscriber",
+        "vip",
+        "moderator",
+        "broadcaster",
+        "owner",
+    ];
+
+    const ALL: [Role; 7] = [
+        Role::Everyone,
+        Role::Regular,
+        Role::Subscriber,
+        Role::Vip,
+        Role::Moderator,
+        Role::Broadcaster,
+        Role::Owner,
+    ];
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|s| s.eq_ignore_ascii_case(name))
+            .map(|i| Self::ALL[i])
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Uses `owners` instead of the ones from the config
+pub fn set_owners(owners: &[i64]) {
+    *OWNERS.write().unwrap() = owners.to_vec();
+}
+
+pub fn is_owner(id: i64) -> bool {
+    OWNERS.read().unwrap().contains(&id)
+}
+
+fn badge_role(badges: &irc::Badges) -> Role {
+    if badges.has(irc::Badge::Broadcaster) {
+        Role::Broadcaster
+    } else if badges.has(irc::Badge::Moderator) {
+        Role::Moderator
+    } else if badges.is_vip() {
+        Role::Vip
+    } else if badges.is_subscriber() {
+        Role::Subscriber
+    } else {
+        Role::Everyone
+    }
+}
+
+/// Remembers the role the badges of the sender of `msg` give them, for `role_of_user`
+pub fn observe(msg: &irc::Message) {
+    if msg.command() != "PRIVMSG" {
+        return;
+    }
+    let (sender, channel) = match (msg.tags.get_userid(), msg.args.first()) {
+        (Some(id), Some(channel)) => (id, config::channel_name(channel)),
+        _ => return,
+    };
+
+    let role = badge_role(&msg.tags.badges());
+    let seen = BADGES
+        .read()
+        .unwrap()
+        .get(&sender)
+        .and_then(|channels| channels.get(&channel).copied())
+        .unwrap_or(Role::Everyone);
+    if seen == role {
+        return;
+    }
+
+    let mut badges = BADGES.write().unwrap();
+    let channels = badges.entry(sender).or_default();
+    if role == Role::Everyone {
+        channels.remove(&channel);
+    } else {
+        channels.insert(channel, role);
+    }
+}
+
+/// The highest role the sender of `req` has, from their badges or a grant
+pub fn role_of(req: &Request) -> Role {
+    if is_owner(req.sender()) {
+        return Role::Owner;
+    }
+    let badges = badge_role(req.badges());
+    let granted = Permissions::granted_role(&get_connection(), req.sender());
+    granted.map_or(badges, |granted| granted.max(badges))
+}
+
+/// The highest role `user` has in `channel`, from the badges they were last seen
+/// with or a grant
+pub fn role_of_user(user: &User, channel: &str) -> Role {
+    if is_owner(user.userid) {
+        return Role::Owner;
+    }
+    let channel = config::channel_name(channel);
+    let badges = if user.display.eq_ignore_ascii_case(&channel) {
+        Role::Broadcaster
+    } else {
+        BADGES
+            .read()
+            .unwrap()
+            .get(&user.userid)
+            .and_then(|channels| channels.get(&channel).copied())
+            .unwrap_or(Role::Everyone)
+    };
+    let granted = Permissions::granted_role(&get_connection(), user.userid);
+    granted.map_or(badges, |granted| granted.max(badges))
+}
+
+/// Whether the sender of `req` can change the roles and commands of `user`
+///
+/// Only owners can change someone with the same role as them, or a higher one
+pub fn can_manage(req: &Request, user: &User) -> bool {
+    let own = role_of(req);
+    own == Role::Owner || role_of_user(user, req.target()) < own
+}
+
+/// Sets the role `command` needs, unless it is changed with `!perm set`
+pub fn declare(command: &str, role: Role) {
+    DECLARED.write().unwrap().insert(command.to_string(), role);
+}
+
+/// The role `command` needs. Commands that weren't declared can be used by everyone
+pub fn required(command: &str) -> Role {
+    Permissions::required(&get_connection(), command)
+        .or_else(|| DECLARED.read().unwrap().get(command).copied())
+        .unwrap_or(Role::Everyone)
+}
+
+/// Whether `req` can use `command`
+///
+/// A user can be allowed or denied a command, regardless of their role. Owners can
+/// use everything
+pub fn check(command: &str, req: &Request) -> bool {
+    if is_owner(req.sender()) {
+        return true;
+    }
+    let conn = get_connection();
+    if let Some(allowed) = Permissions::user_override(&conn, req.sender(), command) {
+        debug!(
+            "{} is {} {}",
+            req.sender(),
+            if allowed { "allowed" } else { "denied" },
+            command
+        );
+        return allowed;
+    }
+    role_of(req) >= required(command)
+}
+
+/// The roles and grants stored in the database
+pub struct Permissions;
+
+impl Permissions {
+    fn ensure_table(conn: &Connection) {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS CommandRoles(
+                command     TEXT PRIMARY KEY NOT NULL,
+                role        TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS UserRoles(
+                userid      INTEGER PRIMARY KEY NOT NULL,
+                role        TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS UserCommandGrants(
+                userid      INTEGER NOT NULL,
+                command     TEXT NOT NULL,
+                allowed     INTEGER NOT NULL,
+                UNIQUE(userid, command)
+            );"#,
+        )
+        .expect("create permission tables");
+    }
+
+    /// The role `command` was changed to need, if it was
+    pub fn required(conn: &Connection, command: &str) -> Option<Role> {
+        Self::ensure_table(conn);
+        let role: String = conn
+            .query_row(
+                "SELECT role FROM CommandRoles WHERE command = ?",
+                &[command],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("valid sql")?;
+        Role::parse(&role)
+    }
+
+    pub fn set_required(conn: &Connection, command: &str, role: Role) {
+        Self::ensure_table(conn);
+        conn.execute(
+            "INSERT OR REPLACE INTO CommandRoles (command, role) VALUES (?, ?)",
+            &[command, role.name()],
+        )
+        .expect("valid sql");
+    }
+
+    /// Goes back to the role `command` was declared with
+    pub fn reset_required(conn: &Connection, command: &str) {
+        Self::ensure_table(conn);
+        conn.execute("DELETE FROM CommandRoles WHERE command = ?", &[command])
+            .expect("valid sql");
+    }
+
+    pub fn granted_role(conn: &Connection, userid: i64) -> Option<Role> {
+        Self::ensure_table(conn);
+        let role: String = conn
+            .query_row(
+                "SELECT role FROM UserRoles WHERE userid = ?",
+                &[&userid],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("valid sql")?;
+        Role::parse(&role)
+    }
+
+    pub fn grant_role(conn: &Connection, userid: i64, role: Role) {
+        Self::ensure_table(conn);
+        conn.execute(
+            "INSERT OR REPLACE INTO UserRoles (userid, role) VALUES (?, ?)",
+            &[&userid as &dyn ToSql, &role.name()],
+        )
+        .expect("valid sql");
+    }
+
+    /// Whether the user was explicitly allowed (or denied) `command`
+    pub fn user_override(conn: &Connection, userid: i64, command: &str) -> Option<bool> {
+        Self::ensure_table(conn);
+        conn.query_row(
+            "SELECT allowed FROM UserCommandGrants WHERE userid = ? AND command = ?",
+            &[&userid as &dyn ToSql, &command],
+            |row| row.get(0),
+        )
+        .optional()
+        .expect("valid sql")
+    }
+
+    pub fn set_user_override(conn: &Connection, userid: i64, command: &str, allowed: bool) {
+        Self::ensure_table(conn);
+        conn.execute(
+            "INSERT OR REPLACE INTO UserCommandGrants (userid, command, allowed) VALUES (?, ?, ?)",
+            &[&userid as &dyn ToSql, &command, &allowed],
+        )
+        .expect("valid sql");
+    }
+
+    /// Removes every role and override the user was given
+    pub fn clear_user(conn: &Connection, userid: i64) {
+        Self::ensure_table(conn);
+        conn.execute("DELETE FROM UserRoles WHERE userid = ?", &[&userid])
+            .expect("valid sql");
+        conn.execute("DELETE FROM UserCommandGrants WHERE userid = ?", &[&userid])
+            .expect("valid sql");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn request(userid: i64, badges: &str) -> Request {
+        testing::request("#test", userid, badges, "!cmd")
+    }
+
+    #[test]
+    fn roles() {
+        assert!(Role::Owner > Role::Broadcaster);
+        assert!(Role::Regular > Role::Everyone);
+        for name in Role::NAMES {
+            assert_eq!(Role::parse(name).unwrap().name(), *name);
+        }
+        assert_eq!(Role::parse("VIP"), Some(Role::Vip));
+        assert_eq!(Role::parse("admin"), None);
+
+        let db = get_connection();
+        assert_eq!(role_of(&request(1000, "")), Role::Everyone);
+        assert_eq!(role_of(&request(1000, "subscriber/12")), Role::Subscriber);
+        assert_eq!(role_of(&request(1000, "vip/1,subscriber/12")), Role::Vip);
+        assert_eq!(role_of(&request(1000, "moderator/1")), Role::Moderator);
+        assert_eq!(role_of(&request(1000, "broadcaster/1")), Role::Broadcaster);
+        assert_eq!(role_of(&request(23_196_011, "")), Role::Owner);
+
+        // a grant doesn't take away what their badges give them
+        Permissions::grant_role(&db, 1000, Role::Regular);
+        assert_eq!(role_of(&request(1000, "")), Role::Regular);
+        assert_eq!(role_of(&request(1000, "moderator/1")), Role::Moderator);
+    }
+
+    #[test]
+    fn checks() {
+        let db = get_connection();
+        let (user, moderator) = (request(1000, ""), request(1001, "moderator/1"));
+
+        assert!(check("!undeclared", &user));
+        declare("!checked", Role::Moderator);
+        assert!(!check("!checked", &user));
+        assert!(check("!checked", &moderator));
+        assert!(check("!checked", &request(23_196_011, "")));
+
+        Permissions::set_required(&db, "!checked", Role::Broadcaster);
+        assert_eq!(required("!checked"), Role::Broadcaster);
+        assert!(!check("!checked", &moderator));
+        Permissions::reset_required(&db, "!checked");
+        assert!(check("!checked", &moderator));
+
+        Permissions::set_user_override(&db, 1000, "!checked", true);
+        Permissions::set_user_override(&db, 1001, "!checked", false);
+        assert!(check("!checked", &user));
+        assert!(!check("!checked", &moderator));
+
+        Permissions::clear_user(&db, 1000);
+        assert!(!check("!checked", &user));
+    }
+}
@@ -0,0 +1,455 @@
+use crate::prelude::*;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use log::*;
+
+// https://dev.twitch.tv/docs/irc/guide/#command--message-limits
+const PERIOD: Duration = Duration::from_secs(30);
+const USER_LIMIT: u32 = 20;
+const MODERATOR_LIMIT: u32 = 100;
+const JOIN_PERIOD: Duration = Duration::from_secs(10);
+const JOIN_LIMIT: u32 = 20;
+
+// chat commands that should jump ahead of normal messages
+const MODERATION: &[&str] = &[
+    "ban",
+    "unban",
+    "timeout",
+    "untimeout",
+    "delete",
+    "clear",
+    "slow",
+    "slowoff",
+    "followers",
+    "followersoff",
+    "subscribers",
+    "subscribersoff",
+    "emoteonly",
+    "emoteonlyoff",
+    "r9kbeta",
+    "r9kbetaoff",
+];
+
+/// A bucket of `capacity` tokens, one for each line sent
+///
+/// The tokens come back one at a time, so a full bucket is refilled in `period`
+pub struct TokenBucket {
+    capacity: u32,
+    tokens: u32,
+    period: Duration,
+    refilled: Option<Instant>, // when the last token came back, if any were taken
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            period,
+            refilled: None,
+        }
+    }
+
+    /// Changes the capacity, keeping the tokens that have already been used
+    pub fn set_capacity(&mut self, capacity: u32) {
+        let used = self.capacity.saturating_sub(self.tokens);
+        self.capacity = capacity;
+        self.tokens = capacity.saturating_sub(used);
+    }
+
+    /// How many tokens there are at `now`
+    pub fn tokens(&mut self, now: Instant) -> u32 {
+        self.refill(now);
+        self.tokens
+    }
+
+    pub fn available(&mut self, now: Instant) -> bool {
+        self.tokens(now) > 0
+    }
+
+    /// Takes `n` tokens at `now`, or as many as there are
+    pub fn take(&mut self, n: u32, now: Instant) {
+        self.refill(now);
+        self.tokens = self.tokens.saturating_sub(n);
+        self.refilled.get_or_insert(now);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let last = match self.refilled {
+            Some(last) if self.capacity > 0 => last,
+            _ => return,
+        };
+        let every = self.period / self.capacity;
+        let elapsed = now.saturating_duration_since(last);
+        let n =
+            (elapsed.as_nanos() / every.as_nanos().max(1)).min(u128::from(self.capacity)) as u32;
+        self.tokens = self.tokens.saturating_add(n).min(self.capacity);
+        // the time towards the next token is kept, unless the bucket is full
+        self.refilled = if self.tokens == self.capacity {
+            None
+        } else {
+            Some(last + every * n)
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Normal,
+    Moderation,
+    Immediate,
+}
+
+struct Line {
+    priority: Priority,
+    limit: Limit,
+    data: String,
+}
+
+enum Limit {
+    None,
+    Message(String), // to a channel
+    Join(u32),       // this many channels
+}
+
+impl Line {
+    fn classify(data: String) -> Self {
+        let msg = match irc::Message::parse(&data) {
+            Ok(msg) => msg,
+            Err(..) => {
+                return Self {
+                    priority: Priority::Normal,
+                    limit: Limit::None,
+                    data,
+                }
+            }
+        };
+        let (priority, limit) = match msg.command() {
+            "PING" | "PONG" | "CAP" | "PASS" | "NICK" | "USER" | "QUIT" => {
+                (Priority::Immediate, Limit::None)
+            }
+            "PRIVMSG" => {
+                let channel = msg.args.first().map(|s| s.to_ascii_lowercase());
+                let moderation = msg
+                    .data
+                    .as_ref()
+                    .filter(|s| s.starts_with('/') || s.starts_with('.'))
+                    .and_then(|s| s[1..].split_whitespace().next())
+                    .map(|cmd| MODERATION.contains(&cmd))
+                    .unwrap_or(false);
+                let priority = if moderation {
+                    Priority::Moderation
+                } else {
+                    Priority::Normal
+                };
+                (priority, channel.map_or(Limit::None, Limit::Message))
+            }
+            "JOIN" => {
+                let channels = msg.args.first().map_or(1, |s| s.split(',').count() as u32);
+                (Priority::Normal, Limit::Join(channels))
+            }
+            _ => (Priority::Normal, Limit::None),
+        };
+
+        Self {
+            priority,
+            limit,
+            data,
+        }
+    }
+}
+
+/// The outbound queue for the Bot
+///
+/// Lines are written highest priority first. Messages need a token from the bucket
+/// for their channel and from the one for the whole account, and JOINs from their own
+pub struct Outbound {
+    queue: VecDeque<Line>,
+    channels: HashMap<String, TokenBucket>,
+    account: TokenBucket, // every message
+    user: TokenBucket,    // also every message, but only needed where we aren't a moderator
+    joins: TokenBucket,
+    moderator: HashMap<String, bool>,
+}
+
+impl Default for Outbound {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            channels: HashMap::new(),
+            account: TokenBucket::new(MODERATOR_LIMIT, PERIOD),
+            user: TokenBucket::new(USER_LIMIT, PERIOD),
+            joins: TokenBucket::new(JOIN_LIMIT, JOIN_PERIOD),
+            moderator: HashMap::new(),
+        }
+    }
+}
+
+impl Outbound {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `data`, split into the lines that will be written, so each of
+    /// them counts towards the limits
+    pub fn push(&mut self, data: String) {
+        for line in irc::split(&data) {
+            self.queue.push_back(Line::classify(line.into_owned()))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Updates our status in `channel`, using the badges from a USERSTATE
+    pub fn set_moderator(&mut self, channel: &str, moderator: bool) {
+        let channel = channel.to_ascii_lowercase();
+        if self.moderator.insert(channel.clone(), moderator) != Some(moderator) {
+            debug!("moderator in {}: {}", channel, moderator);
+        }
+        if let Some(bucket) = self.channels.get_mut(&channel) {
+            bucket.set_capacity(Self::limit(moderator));
+        }
+    }
+
+    /// Pops the next line that can be written at `now`
+    pub fn pop(&mut self, now: Instant) -> Option<String> {
+        let Self {
+            queue,
+            channels,
+            account,
+            user,
+            joins,
+            moderator,
+        } = self;
+
+        let (mut best, mut priority) = (None, None);
+        for (i, line) in queue.iter().enumerate() {
+            if priority.map(|p| line.priority <= p).unwrap_or(false) {
+                continue;
+            }
+            let available = match &line.limit {
+                Limit::Message(channel) => {
+                    let moderated = moderator.get(channel).cloned().unwrap_or_default();
+                    (moderated || user.available(now))
+                        && account.available(now)
+                        && Self::bucket(channels, moderator, channel).available(now)
+                }
+                Limit::Join(n) => joins.tokens(now) >= (*n).min(JOIN_LIMIT),
+                Limit::None => true,
+            };
+            if available {
+                best.replace(i);
+                priority.replace(line.priority);
+            }
+        }
+
+        let line = queue.remove(best?)?;
+        match &line.limit {
+            Limit::Message(channel) => {
+                Self::bucket(channels, moderator, channel).take(1, now);
+                account.take(1, now);
+                user.take(1, now);
+            }
+            Limit::Join(n) => joins.take(*n, now),
+            Limit::None => {}
+        }
+        Some(line.data)
+    }
+
+    fn bucket<'a>(
+        channels: &'a mut HashMap<String, TokenBucket>,
+        moderator: &HashMap<String, bool>,
+        channel: &str,
+    ) -> &'a mut TokenBucket {
+        let limit = Self::limit(moderator.get(channel).cloned().unwrap_or_default());
+        channels
+            .entry(channel.to_string())
+            .or_insert_with(|| TokenBucket::new(limit, PERIOD))
+    }
+
+    fn limit(moderator: bool) -> u32 {
+        if moderator {
+            MODERATOR_LIMIT
+        } else {
+            USER_LIMIT
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(out: &mut Outbound, now: Instant) -> Vec<String> {
+        std::iter::from_fn(|| out.pop(now)).collect()
+    }
+
+    fn try_take(bucket: &mut TokenBucket, now: Instant) -> bool {
+        let available = bucket.available(now);
+        if available {
+            bucket.take(1, now);
+        }
+        available
+    }
+
+    #[test]
+    fn token_bucket() {
+        let now = Instant::now();
+        let secs = |n| now + Duration::from_secs(n);
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(10));
+        assert!(try_take(&mut bucket, now));
+        assert!(try_take(&mut bucket, now));
+        assert!(!try_take(&mut bucket, now));
+
+        // a token comes back every 5 seconds
+        assert!(!try_take(&mut bucket, secs(4)));
+        assert!(try_take(&mut bucket, secs(5)));
+        assert!(!try_take(&mut bucket, secs(9)));
+        assert!(try_take(&mut bucket, secs(10)));
+        assert_eq!(bucket.tokens(secs(17)), 1);
+
+        // it never holds more than its capacity
+        assert_eq!(bucket.tokens(secs(60)), 2);
+
+        // the tokens already used count towards the new capacity
+        assert!(try_take(&mut bucket, secs(60)));
+        bucket.set_capacity(5);
+        assert_eq!(
+            (0..5).filter(|_| try_take(&mut bucket, secs(60))).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn priority() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        out.push("PRIVMSG #test :hello".into());
+        out.push("PRIVMSG #test :/timeout someone 10".into());
+        out.push("PONG :tmi.twitch.tv".into());
+        out.push("PRIVMSG #test :world".into());
+        out.push("PRIVMSG #test :.ban someone".into());
+
+        assert_eq!(
+            drain(&mut out, now),
+            vec![
+                "PONG :tmi.twitch.tv",
+                "PRIVMSG #test :/timeout someone 10",
+                "PRIVMSG #test :.ban someone",
+                "PRIVMSG #test :hello",
+                "PRIVMSG #test :world",
+            ]
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn per_channel() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        out.set_moderator("#foo", true);
+        for i in 0..150 {
+            out.push(format!("PRIVMSG #foo :{}", i));
+        }
+        out.push("PRIVMSG #bar :hello".into());
+        out.push("JOIN #baz".into());
+
+        let lines = drain(&mut out, now);
+        assert_eq!(lines.len(), MODERATOR_LIMIT as usize + 1);
+        assert!(lines.contains(&"JOIN #baz".to_string()));
+        assert_eq!(out.len(), 51);
+
+        // a token comes back every 300ms, so half of them are back in half a period
+        assert!(drain(&mut out, now + Duration::from_millis(299)).is_empty());
+        assert_eq!(drain(&mut out, now + PERIOD / 2).len(), 50);
+
+        // #bar is over the lower limit for the account
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    fn account() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        for i in 0..15 {
+            out.push(format!("PRIVMSG #foo :{}", i));
+            out.push(format!("PRIVMSG #bar :{}", i));
+        }
+
+        // the limit is for every channel together
+        assert_eq!(drain(&mut out, now).len(), USER_LIMIT as usize);
+        assert_eq!(drain(&mut out, now + PERIOD).len(), 10);
+
+        // messages to channels we moderate can use what's left of the higher limit
+        let later = now + PERIOD * 2;
+        out.set_moderator("#foo", true);
+        for i in 0..150 {
+            out.push(format!("PRIVMSG #foo :{}", i));
+            out.push(format!("PRIVMSG #bar :{}", i));
+        }
+        let lines = drain(&mut out, later);
+        let count = |ch| lines.iter().filter(|s| s.contains(ch)).count();
+        assert_eq!(lines.len(), MODERATOR_LIMIT as usize);
+        assert_eq!(count("#bar"), USER_LIMIT as usize / 2);
+    }
+
+    #[test]
+    fn long_lines() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        for _ in 0..USER_LIMIT / 2 {
+            out.push(format!("PRIVMSG #test :{}", "hello world ".repeat(50)));
+        }
+        out.push("PRIVMSG #test :one more".into());
+
+        let lines = drain(&mut out, now);
+        assert_eq!(lines.len(), USER_LIMIT as usize);
+        assert!(lines.iter().all(|s| s.len() <= 510));
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    fn joins() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        out.push("JOIN #a,#b".into());
+        for i in 0..20 {
+            out.push(format!("JOIN #c{}", i));
+        }
+        out.push("PRIVMSG #a :hello".into());
+
+        let lines = drain(&mut out, now);
+        // one line joins two channels
+        assert_eq!(lines.len(), JOIN_LIMIT as usize);
+        assert!(lines.contains(&"PRIVMSG #a :hello".to_string()));
+        assert_eq!(drain(&mut out, now + JOIN_PERIOD / JOIN_LIMIT).len(), 1);
+        assert_eq!(drain(&mut out, now + JOIN_PERIOD).len(), 1);
+    }
+
+    #[test]
+    fn moderator() {
+        let now = Instant::now();
+        let mut out = Outbound::new();
+        out.set_moderator("#foo", true);
+        for i in 0..150 {
+            out.push(format!("PRIVMSG #foo :{}", i));
+        }
+        assert_eq!(drain(&mut out, now).len(), MODERATOR_LIMIT as usize);
+
+        // losing mod keeps the tokens that were used, they come back more slowly
+        out.set_moderator("#foo", false);
+        assert!(drain(&mut out, now).is_empty());
+        let half = USER_LIMIT as usize / 2;
+        assert_eq!(drain(&mut out, now + PERIOD / 2).len(), half);
+        assert_eq!(drain(&mut out, now + PERIOD).len(), half);
+    }
+}
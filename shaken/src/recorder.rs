@@ -0,0 +1,267 @@
+use crate::prelude::*;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::Path;
+
+use crossbeam_channel as channel;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(usize, serde_json::Error), // line number, starting at 1
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "cannot read recording: {}", err),
+            Error::Parse(n, err) => write!(f, "invalid entry on line {}: {}", n, err),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// A single line in a recording
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    pub ts: u64, // milliseconds since the unix epoch
+    pub dir: Direction,
+    pub line: String,
+}
+
+/// Writes every line the Bot reads and writes to a file, as JSON lines
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Opens `path` for appending, creating it if needed
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, dir: Direction, line: &str) {
+        // don't write the password to disk
+        if line.starts_with("PASS ") {
+            return;
+        }
+
+        let entry = Entry {
+            ts: util::get_timestamp(),
+            dir,
+            line: line.to_string(),
+        };
+        let res = serde_json::to_writer(&mut self.writer, &entry)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+        if let Err(err) = res {
+            warn!("cannot record line: {}", err)
+        }
+    }
+}
+
+/// Reads a recording made by a `Recorder`
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Entry>, Error> {
+    let file = File::open(path).map_err(Error::Io)?;
+    let mut entries = vec![];
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(Error::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|err| Error::Parse(n + 1, err))?);
+    }
+    Ok(entries)
+}
+
+/// A recorded line that the modules responded to differently
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub line: String,
+    pub missing: Vec<String>, // recorded, but not produced
+    pub extra: Vec<String>,   // produced, but not recorded
+}
+
+/// Feeds the inbound lines of a recording through the `modules`, and compares
+/// what they produce against the outbound lines that were recorded after each one
+///
+/// Ticks aren't replayed, so anything a module sent on a timer shows up as missing.
+/// The modules change the database, so create them after `database::use_copy`
+pub fn replay(entries: &[Entry], config: &Config, modules: &[LoadedModule]) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+
+    // everything before the first inbound line is registration
+    let mut iter = entries
+        .iter()
+        .skip_while(|entry| entry.dir == Direction::Out)
+        .peekable();
+
+    while let Some(entry) = iter.next() {
+        let mut expected = vec![];
+        while let Some(next) = iter.peek().filter(|e| e.dir == Direction::Out) {
+            // our keepalive pings aren't a response to anything
+            if !next.line.starts_with("PING ") {
+                expected.push(next.line.clone());
+            }
+            iter.next();
+        }
+
+        let mut extra = dispatch(&entry.line, config, modules);
+        let mut missing = vec![];
+        for line in expected {
+            match extra.iter().position(|s| *s == line) {
+                Some(pos) => {
+                    extra.remove(pos);
+                }
+                None => missing.push(line),
+            }
+        }
+
+        if !missing.is_empty() || !extra.is_empty() {
+            mismatches.push(Mismatch {
+                line: entry.line.clone(),
+                missing,
+                extra,
+            })
+        }
+    }
+
+    mismatches
+}
+
+// this does what the Bot does for a single line, but on this thread
+fn dispatch(line: &str, config: &Config, modules: &[LoadedModule]) -> Vec<String> {
+    let msg = match irc::Message::parse(line) {
+        Ok(msg) => msg,
+        Err(err) => {
+            warn!("skipping a bad line ({}): {:?}", err, line);
+            return vec![];
+        }
+    };
+    if let "GLOBALUSERSTATE" = msg.command() {
+        let _ = User::from_msg(&msg);
+    }
+    let req = Request::try_from(&msg).map(Box::new);
+    let ev = irc::TwitchEvent::from_msg(&msg).map(Box::new);
+    let target = msg.args.first().filter(|s| s.starts_with('#'));
+
+    let (tx, rx) = channel::unbounded();
+    for loaded in modules {
+        match target {
+            Some(ch) if !config.is_enabled(loaded.name, Some(ch)) => continue,
+            _ => {}
+        }
+        let (events_tx, events) = channel::unbounded();
+        let _ = events_tx.send(Event::Message(msg.clone(), req.clone(), ev.clone()));
+        drop(events_tx);
+        loaded.lock().handle(events, tx.clone());
+    }
+    drop(tx);
+
+    let mut lines = vec![];
+    for (context, resp) in rx {
+        if let Some(context) = context.as_ref() {
+            for loaded in modules {
+                loaded.lock().inspect(context, &resp);
+            }
+        }
+        if let Some(resp) = resp.build(context.as_ref(), &config.messages) {
+            lines.extend(resp)
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ping;
+    impl Module for Ping {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            req.search("!ping")?;
+            say!("pong")
+        }
+    }
+
+    fn entry(dir: Direction, line: &str) -> Entry {
+        Entry {
+            ts: 0,
+            dir,
+            line: line.into(),
+        }
+    }
+
+    #[test]
+    fn record_and_load() {
+        let path = std::env::temp_dir().join(format!("shaken-{}.jsonl", util::get_timestamp()));
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            recorder.record(Direction::Out, "PASS oauth:hunter2");
+            recorder.record(Direction::Out, "NICK shaken_bot");
+            recorder.record(Direction::In, "PING :tmi.twitch.tv");
+        }
+
+        let entries = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = entries
+            .iter()
+            .map(|e| (e.dir, e.line.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (Direction::Out, "NICK shaken_bot"),
+                (Direction::In, "PING :tmi.twitch.tv"),
+            ]
+        );
+        assert!(entries[0].ts > 0);
+    }
+
+    #[test]
+    fn replay_recording() {
+        let privmsg = "@user-id=1000;display-name=test;color=#FFFFFF :test!user@irc.test \
+                       PRIVMSG #test :!ping";
+
+        let entries = vec![
+            entry(Direction::Out, "NICK shaken_bot"),
+            entry(
+                Direction::In,
+                "@badges=;color=#FF0000;display-name=shaken_bot;user-id=42 :tmi.twitch.tv \
+                 GLOBALUSERSTATE",
+            ),
+            entry(Direction::In, privmsg),
+            entry(Direction::Out, "PRIVMSG #test :pong"),
+            entry(Direction::Out, "PING :shaken"),
+            entry(Direction::In, ":tmi.twitch.tv PONG tmi.twitch.tv :shaken"),
+            entry(Direction::In, privmsg),
+            entry(Direction::Out, "PRIVMSG #test :pang"),
+        ];
+
+        let mut config = Config::default();
+        config.enabled.push("Ping".into());
+        let modules = vec![LoadedModule::new("Ping", Ping)];
+        assert_eq!(
+            replay(&entries, &config, &modules),
+            vec![Mismatch {
+                line: privmsg.into(),
+                missing: vec!["PRIVMSG #test :pang".into()],
+                extra: vec!["PRIVMSG #test :pong".into()],
+            }]
+        );
+    }
+}
@@ -1,6 +1,39 @@
 use crate::prelude::*;
+use crate::trie::Trie;
 
-use rusqlite::{Connection, NO_PARAMS};
+use std::sync::{Arc, RwLock};
+
+use hashbrown::HashMap;
+use log::*;
+use once_cell::{sync::Lazy, sync_lazy};
+use rusqlite::{types::ToSql, Connection, NO_PARAMS};
+
+// what is kept from the database, so it isn't read for every line
+//
+// they're shared out as an `Arc`, so the lock isn't held while they're used
+#[derive(Default)]
+struct Cache {
+    ready: bool,                                   // the tables were created, and migrated
+    aliases: Option<Arc<HashMap<String, String>>>, // alias -> command, loaded when first needed
+    commands: Option<Arc<Trie<Command>>>,          // every module's commands, same as `aliases`
+}
+
+// keyed by `database::id`, each test has its own database
+static CACHE: Lazy<RwLock<HashMap<String, Cache>>> = sync_lazy! {
+    RwLock::new(HashMap::new())
+};
+
+fn read_cache<R>(f: impl FnOnce(&Cache) -> R) -> R {
+    let id = database::id();
+    match CACHE.read().unwrap().get(&id) {
+        Some(cache) => f(cache),
+        None => f(&Cache::default()),
+    }
+}
+
+fn with_cache<R>(f: impl FnOnce(&mut Cache) -> R) -> R {
+    f(CACHE.write().unwrap().entry(database::id()).or_default())
+}
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Error {
@@ -11,6 +44,7 @@ pub enum Error {
 pub struct Command {
     name: String,
     help: String,
+    usage: String,
     namespace: String,
 }
 
@@ -27,6 +61,14 @@ impl Command {
         self.help.as_str()
     }
 
+    /// How to use the command, e.g. `!give <user> <amount>`
+    pub fn usage(&self) -> &str {
+        if self.usage.is_empty() {
+            return self.name.as_str();
+        }
+        self.usage.as_str()
+    }
+
     pub fn has_help(&self) -> bool {
         self.help != "no help provided" // TODO make this some sigil value (or an Option)
     }
@@ -36,11 +78,34 @@ impl Command {
     }
 }
 
+/// Another name for a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    name: String,
+    command: String,
+    namespace: Option<String>, // None if it was added with `!alias add`
+}
+
+impl Alias {
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn command(&self) -> &str {
+        self.command.as_str()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+}
+
 #[derive(Default)]
 pub struct CommandBuilder {
     name: String,
     help: Option<String>,
-    namespace: Option<String>, // for future use
+    usage: Option<String>,
+    namespace: Option<String>,
 }
 
 impl CommandBuilder {
@@ -58,7 +123,15 @@ impl CommandBuilder {
     where
         S: ToString,
     {
-        std::mem::replace(&mut self.help, Some(help.to_string()));
+        self.help.replace(help.to_string());
+        self
+    }
+
+    pub fn usage<S>(mut self, usage: S) -> Self
+    where
+        S: ToString,
+    {
+        self.usage.replace(usage.to_string());
         self
     }
 
@@ -66,7 +139,7 @@ impl CommandBuilder {
     where
         S: ToString,
     {
-        std::mem::replace(&mut self.namespace, Some(namespace.to_string()));
+        self.namespace.replace(namespace.to_string());
         self
     }
 
@@ -74,6 +147,7 @@ impl CommandBuilder {
         Command {
             name: self.name,
             help: self.help.unwrap_or_else(|| "no help provided".into()),
+            usage: self.usage.unwrap_or_default(),
             namespace: self.namespace.expect("namespace is required"),
         }
     }
@@ -82,17 +156,50 @@ impl CommandBuilder {
 pub struct Registry;
 
 impl Registry {
+    /// Creates the tables, and migrates older ones, the first time it is called
     fn ensure_table(conn: &Connection) {
-        conn.execute(
-            r#"CREATE TABLE IF NOT EXISTS CommandRegistry(
+        if read_cache(|cache| cache.ready)
+            || with_cache(|cache| std::mem::replace(&mut cache.ready, true))
+        {
+            return;
+        }
+
+        let columns = {
+            let mut stmt = conn
+                .prepare("PRAGMA table_info(CommandRegistry)")
+                .expect("valid sql");
+            stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))
+                .expect("get columns")
+                .filter_map(Result::ok)
+                .collect::<Vec<_>>()
+        };
+
+        // before usages were stored
+        if !columns.is_empty() && !columns.iter().any(|c| c == "usage") {
+            info!("migrating CommandRegistry table to store usages");
+            conn.execute(
+                "ALTER TABLE CommandRegistry ADD COLUMN usage TEXT NOT NULL DEFAULT ''",
+                NO_PARAMS,
+            )
+            .expect("migrate CommandRegistry table");
+        }
+
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS CommandRegistry(
                 id              INTEGER PRIMARY KEY AUTOINCREMENT,
                 command         TEXT NOT NULL,
                 description     TEXT NOT NULL,
+                usage           TEXT NOT NULL DEFAULT '',
                 namespace       TEXT NOT NULL
-            )"#,
-            NO_PARAMS,
+            );
+            CREATE TABLE IF NOT EXISTS CommandAliases(
+                alias           TEXT PRIMARY KEY NOT NULL,
+                command         TEXT NOT NULL,
+                namespace       TEXT
+            );"#,
         )
-        .expect("create CommandRegistry table");
+        .expect("create CommandRegistry tables");
     }
 
     pub fn commands() -> Vec<Command> {
@@ -100,14 +207,15 @@ impl Registry {
         Self::ensure_table(&conn);
 
         let mut s = conn
-            .prepare("SELECT command, description, namespace FROM CommandRegistry")
+            .prepare("SELECT command, description, usage, namespace FROM CommandRegistry")
             .expect("valid sql");
 
         s.query_map(NO_PARAMS, |row| {
             Ok(Command {
                 name: row.get(0)?,
                 help: row.get(1)?,
-                namespace: row.get(2)?,
+                usage: row.get(2)?,
+                namespace: row.get(3)?,
             })
         })
         .expect("valid sql")
@@ -115,12 +223,12 @@ impl Registry {
         .collect()
     }
 
+    /// Whether `name` isn't a command or an alias
     pub fn is_available(name: impl AsRef<str>) -> bool {
         let name = name.as_ref();
-        !Self::commands().iter().any(|cmd| cmd.name == name)
+        !Self::commands().iter().any(|cmd| cmd.name == name) && Self::resolve(name).is_none()
     }
 
-    #[must_use]
     pub fn register(cmd: &Command) -> Result<(), Error> {
         let conn = database::get_connection();
         Self::ensure_table(&conn);
@@ -148,20 +256,178 @@ impl Registry {
             if command.name == cmd.name {
                 if command.namespace != cmd.namespace {
                     return Err(Error::AlreadyExists);
-                } else {
-                    return Ok(());
                 }
+                // the module was created again, its help could have changed
+                conn.execute(
+                    "UPDATE CommandRegistry SET description = ?1, usage = ?2 WHERE command = ?3",
+                    &[&cmd.help, &cmd.usage, &cmd.name],
+                )
+                .expect("valid sql");
+                return Ok(());
             }
         }
 
+        // commands take precedence over aliases
+        if let Some(alias) = Self::aliases()
+            .into_iter()
+            .find(|alias| alias.name == cmd.name)
+        {
+            warn!("{} replaces the alias for {}", cmd.name, alias.command);
+            Self::remove_alias(&alias.name);
+        }
+
         conn.execute(
-            "INSERT INTO CommandRegistry (command, description, namespace) VALUES (?1, ?2, ?3)",
-            &[&cmd.name, &cmd.help, &cmd.namespace],
+            r#"INSERT INTO CommandRegistry (command, description, usage, namespace)
+                VALUES (?1, ?2, ?3, ?4)"#,
+            &[&cmd.name, &cmd.help, &cmd.usage, &cmd.namespace],
         )
         .expect("valid sql");
+        Self::forget();
+
+        Ok(())
+    }
+
+    /// Makes `alias` another name for `command`
+    ///
+    /// Aliases added by a module belong to its `namespace`, and are removed with it.
+    /// An alias of an alias points at the command it was for
+    pub fn add_alias(alias: &str, command: &str, namespace: Option<&str>) -> Result<(), Error> {
+        let conn = database::get_connection();
+        Self::ensure_table(&conn);
+
+        if let Some(namespace) = namespace {
+            // the module used to register it as a command
+            let removed = conn
+                .execute(
+                    "DELETE FROM CommandRegistry WHERE command = ?1 AND namespace = ?2",
+                    &[alias, namespace],
+                )
+                .expect("valid sql");
+            if removed > 0 {
+                Self::forget();
+            }
+        }
+
+        if Self::commands().iter().any(|cmd| cmd.name == alias) {
+            return Err(Error::AlreadyExists);
+        }
+        if let Some(existing) = Self::aliases().into_iter().find(|a| a.name == alias) {
+            if namespace.is_none() || existing.namespace() != namespace {
+                return Err(Error::AlreadyExists);
+            }
+        }
 
+        let command = Self::resolve(command).unwrap_or_else(|| command.to_string());
+        if command == alias {
+            return Err(Error::AlreadyExists);
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO CommandAliases (alias, command, namespace) VALUES (?1, ?2, ?3)",
+            &[&alias as &dyn ToSql, &command, &namespace],
+        )
+        .expect("valid sql");
+        Self::forget();
         Ok(())
     }
+
+    /// Removes `alias`, returning whether it was one
+    pub fn remove_alias(alias: &str) -> bool {
+        let conn = database::get_connection();
+        Self::ensure_table(&conn);
+        let removed = conn
+            .execute("DELETE FROM CommandAliases WHERE alias = ?1", &[alias])
+            .expect("valid sql")
+            > 0;
+        if removed {
+            Self::forget();
+        }
+        removed
+    }
+
+    pub fn aliases() -> Vec<Alias> {
+        let conn = database::get_connection();
+        Self::ensure_table(&conn);
+
+        let mut s = conn
+            .prepare("SELECT alias, command, namespace FROM CommandAliases ORDER BY alias")
+            .expect("valid sql");
+
+        s.query_map(NO_PARAMS, |row| {
+            Ok(Alias {
+                name: row.get(0)?,
+                command: row.get(1)?,
+                namespace: row.get(2)?,
+            })
+        })
+        .expect("valid sql")
+        .filter_map(Result::ok)
+        .collect()
+    }
+
+    /// The command `name` is an alias for, if it is one
+    ///
+    /// The aliases are read once, and again after they are changed
+    pub fn resolve(name: &str) -> Option<String> {
+        let aliases = read_cache(|cache| cache.aliases.clone()).unwrap_or_else(|| {
+            let aliases = Self::aliases()
+                .into_iter()
+                .map(|alias| (alias.name, alias.command))
+                .collect::<HashMap<_, _>>();
+            let aliases = Arc::new(aliases);
+            with_cache(|cache| cache.aliases.replace(Arc::clone(&aliases)));
+            aliases
+        });
+        aliases.get(name).cloned()
+    }
+
+    /// Finds the longest command, of any module, that `line` starts with
+    ///
+    /// `f` is given the command and the rest of the line. The commands are read once,
+    /// and again after they are changed
+    pub fn find_prefix<'a, R>(line: &'a str, f: impl FnOnce(&Command, &'a str) -> R) -> Option<R> {
+        let commands = read_cache(|cache| cache.commands.clone()).unwrap_or_else(|| {
+            let mut commands = Trie::new();
+            for cmd in Self::commands() {
+                let name = cmd.name.clone();
+                commands.insert(&name, cmd);
+            }
+            let commands = Arc::new(commands);
+            with_cache(|cache| cache.commands.replace(Arc::clone(&commands)));
+            commands
+        });
+
+        let (cmd, rest) = commands.find(line)?;
+        Some(f(cmd, rest))
+    }
+
+    // they're read again the next time they're needed
+    fn forget() {
+        with_cache(|cache| {
+            cache.aliases.take();
+            cache.commands.take();
+        });
+    }
+
+    /// Finds the command registered as `name`, or the one it is an alias for
+    pub fn find(name: &str) -> Option<Command> {
+        let name = Self::resolve(name).unwrap_or_else(|| name.to_string());
+        Self::commands().into_iter().find(|cmd| cmd.name == name)
+    }
+
+    /// Removes every command registered by `namespace`
+    pub fn unregister_namespace(namespace: impl AsRef<str>) {
+        let conn = database::get_connection();
+        Self::ensure_table(&conn);
+
+        for table in &["CommandRegistry", "CommandAliases"] {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE namespace = ?1", table),
+                &[namespace.as_ref()],
+            )
+            .expect("valid sql");
+        }
+        Self::forget();
+    }
 }
 
 #[cfg(test)]
@@ -191,4 +457,135 @@ mod tests {
         assert_eq!(commands.len(), 2);
         assert_eq!(commands[1], cmd);
     }
+
+    #[test]
+    fn help_and_usage() {
+        let _conn = database::get_connection();
+
+        let cmd = CommandBuilder::command("!test").namespace("test").build();
+        Registry::register(&cmd).unwrap();
+        let found = Registry::find("!test").unwrap();
+        assert!(!found.has_help());
+        assert_eq!(found.usage(), "!test");
+
+        // registering it again updates it
+        let cmd = CommandBuilder::command("!test")
+            .namespace("test")
+            .help("tests things")
+            .usage("!test <thing>")
+            .build();
+        Registry::register(&cmd).unwrap();
+        assert_eq!(Registry::find("!test").unwrap(), cmd);
+
+        Registry::add_alias("!t", "!test", None).unwrap();
+        assert_eq!(Registry::find("!t").unwrap().help(), "tests things");
+        assert_eq!(Registry::find("!missing"), None);
+    }
+
+    #[test]
+    fn find_prefix() {
+        let _conn = database::get_connection();
+
+        for name in &["!poll", "!poll start"] {
+            let cmd = CommandBuilder::command(name).namespace("test").build();
+            Registry::register(&cmd).unwrap();
+        }
+
+        let found = Registry::find_prefix("!poll start 30s", |cmd, rest| {
+            // the registry can be used from here, nothing is held
+            let other = CommandBuilder::command("!pollster")
+                .namespace("test")
+                .build();
+            Registry::register(&other).unwrap();
+            (cmd.name().to_string(), rest.to_string())
+        });
+        assert_eq!(found, Some(("!poll start".into(), "30s".into())));
+
+        let found = Registry::find_prefix("!pollster", |cmd, _| cmd.name().to_string());
+        assert_eq!(found, Some("!pollster".into()));
+        assert_eq!(Registry::find_prefix("!polls", |_, _| ()), None);
+    }
+
+    #[test]
+    fn migrate_usage() {
+        let conn = database::get_connection();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE CommandRegistry(
+                id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                command         TEXT NOT NULL,
+                description     TEXT NOT NULL,
+                namespace       TEXT NOT NULL
+            );
+            INSERT INTO CommandRegistry (command, description, namespace)
+                VALUES ('!old', 'no help provided', 'test');"#,
+        )
+        .unwrap();
+
+        let commands = Registry::commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].usage(), "!old");
+    }
+
+    #[test]
+    fn unregister_namespace() {
+        let _conn = database::get_connection();
+
+        for (name, namespace) in &[("!a", "foo"), ("!b", "foo"), ("!c", "bar")] {
+            let cmd = CommandBuilder::command(name).namespace(namespace).build();
+            Registry::register(&cmd).unwrap();
+        }
+
+        Registry::unregister_namespace("foo");
+        let commands = Registry::commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].name(), "!c");
+        assert!(Registry::is_available("!a"));
+    }
+
+    #[test]
+    fn aliases() {
+        let _conn = database::get_connection();
+
+        let cmd = CommandBuilder::command("!top").namespace("foo").build();
+        Registry::register(&cmd).unwrap();
+
+        Registry::add_alias("!top5", "!top", Some("foo")).unwrap();
+        // an alias of an alias is for the same command
+        Registry::add_alias("!best", "!top5", None).unwrap();
+        assert_eq!(Registry::resolve("!top5"), Some("!top".into()));
+        assert_eq!(Registry::resolve("!best"), Some("!top".into()));
+        assert_eq!(Registry::resolve("!top"), None);
+        assert!(!Registry::is_available("!best"));
+
+        // the module can add its own alias again
+        Registry::add_alias("!top5", "!top", Some("foo")).unwrap();
+        for (alias, namespace) in &[("!top5", None), ("!best", Some("bar")), ("!top", None)] {
+            let err = Registry::add_alias(alias, "!top", *namespace).unwrap_err();
+            assert_eq!(err, Error::AlreadyExists);
+        }
+
+        let aliases = Registry::aliases();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases[0].name(), "!best");
+        assert_eq!(aliases[0].namespace(), None);
+
+        // they're only read again after they change
+        assert_eq!(Registry::resolve("!best"), Some("!top".into()));
+        let conn = database::get_connection();
+        conn.execute(
+            "DELETE FROM CommandAliases WHERE alias = '!best'",
+            NO_PARAMS,
+        )
+        .unwrap();
+        assert_eq!(Registry::resolve("!best"), Some("!top".into()));
+        Registry::add_alias("!best", "!top", None).unwrap();
+        assert_eq!(Registry::resolve("!best"), Some("!top".into()));
+
+        // aliases from the namespace go away with it
+        Registry::unregister_namespace("foo");
+        assert_eq!(Registry::resolve("!top5"), None);
+        assert!(Registry::remove_alias("!best"));
+        assert!(!Registry::remove_alias("!best"));
+    }
 }
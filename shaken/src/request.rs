@@ -6,9 +6,10 @@ pub struct Request {
     args: String,         // tail
     sender: i64,
     target: String,
-    broadcaster: bool,
-    moderator: bool,
+    badges: irc::Badges,
     color: RGB,
+    id: Option<String>, // the `id` tag of the message, for replying to it
+    parsed: args::Args, // filled in by the CommandMap
 }
 
 // TODO: I don't like this. rework this whole thing
@@ -20,20 +21,16 @@ impl Request {
             ("PRIVMSG", Some(data)) | ("WHISPER", Some(data))
                 if data.starts_with('!') && data.len() > 1 =>
             {
-                let sender = User::from_msg(&msg)?;
-                let (broadcaster, moderator) = (
-                    msg.tags.has_badge(irc::Badge::Broadcaster),
-                    msg.tags.has_badge(irc::Badge::Moderator),
-                );
-
+                let sender = User::from_msg(msg)?;
                 Some(Request {
                     name: None,
                     args: data.to_string(),
                     sender,
-                    target: msg.target().to_string(),
-                    broadcaster,
-                    moderator,
+                    target: msg.target()?.to_string(),
+                    badges: msg.tags.badges(),
                     color: msg.tags.get_color(),
+                    id: msg.tags.get("id").map(ToString::to_string),
+                    parsed: args::Args::default(),
                 })
             }
             _ => None,
@@ -48,6 +45,16 @@ impl Request {
         &self.args
     }
 
+    /// The arguments declared for the command, once they've been parsed
+    pub fn parsed(&self) -> &args::Args {
+        &self.parsed
+    }
+
+    pub(crate) fn with_parsed(mut self, parsed: args::Args) -> Self {
+        self.parsed = parsed;
+        self
+    }
+
     pub fn args_iter(&self) -> impl Iterator<Item = &str> {
         self.args.split_whitespace().map(str::trim)
     }
@@ -56,12 +63,17 @@ impl Request {
         &self.target
     }
 
+    /// The id of the message this came from, if Twitch sent one
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
     pub fn sender(&self) -> i64 {
         self.sender
     }
 
     pub fn is_from_owner(&self) -> bool {
-        Config::load().twitch.owners.contains(&self.sender)
+        permission::is_owner(self.sender)
     }
 
     pub fn color(&self) -> RGB {
@@ -69,11 +81,33 @@ impl Request {
     }
 
     pub fn is_from_moderator(&self) -> bool {
-        self.moderator
+        self.badges.has(irc::Badge::Moderator)
     }
 
     pub fn is_from_broadcaster(&self) -> bool {
-        self.broadcaster
+        self.badges.has(irc::Badge::Broadcaster)
+    }
+
+    pub fn is_vip(&self) -> bool {
+        self.badges.is_vip()
+    }
+
+    pub fn is_subscriber(&self) -> bool {
+        self.badges.is_subscriber()
+    }
+
+    /// How many months the sender has been subscribed for, from `badge-info`
+    pub fn sub_months(&self) -> Option<u32> {
+        self.badges.sub_months()
+    }
+
+    /// The bits badge the sender has, e.g. 1000
+    pub fn bits_tier(&self) -> Option<u32> {
+        self.badges.bits_tier()
+    }
+
+    pub fn badges(&self) -> &irc::Badges {
+        &self.badges
     }
 
     pub fn search(&self, query: &str) -> Option<Request> {
@@ -87,19 +121,39 @@ impl Request {
             }
         }
 
-        if self.args.starts_with(&query) {
-            return Some(Request {
-                name: Some(query.to_string()),
-                args: self.args[query.len()..].trim().to_string(),
-                sender: self.sender,
-                target: self.target.clone(),
-                moderator: self.moderator,
-                broadcaster: self.broadcaster,
-                color: self.color,
-            });
+        // only whole words, so !pollster isn't !poll
+        let rest = self.args.strip_prefix(query)?;
+        if rest.starts_with(|c: char| !c.is_whitespace()) {
+            return None;
         }
+        Some(self.with_command(query, rest))
+    }
 
-        None
+    /// Replaces the alias the line starts with by the command it is for
+    ///
+    /// Returns `None` if it doesn't start with one, see `Registry::resolve`
+    pub(crate) fn resolve_alias(&self) -> Option<Request> {
+        let line = self.args.trim_start();
+        let head = line.split_whitespace().next()?;
+        let command = Registry::resolve(head)?;
+        Some(Request {
+            args: format!("{}{}", command, &line[head.len()..]),
+            ..self.clone()
+        })
+    }
+
+    /// Makes the request for the command `name`, with `rest` of the line as its args
+    pub(crate) fn with_command(&self, name: &str, rest: &str) -> Request {
+        Request {
+            name: Some(name.to_string()),
+            args: rest.trim().to_string(),
+            sender: self.sender,
+            target: self.target.clone(),
+            badges: self.badges.clone(),
+            color: self.color,
+            id: self.id.clone(),
+            parsed: args::Args::default(),
+        }
     }
 }
 #[cfg(test)]
@@ -191,4 +245,41 @@ mod tests {
         assert_eq!(req, None);
     }
 
+    #[test]
+    fn search_whole_words() {
+        let req = Request {
+            name: None,
+            args: "!pollster start".into(),
+            target: "#test".into(),
+            ..Request::default()
+        };
+        assert_eq!(req.search("!poll"), None);
+        assert_eq!(req.search("!pollster start").unwrap().args(), "");
+        assert_eq!(req.search("!pollster").unwrap().args(), "start");
+    }
+
+    #[test]
+    fn badges() {
+        // parsing the message needs to know who we are
+        let db = database::get_connection();
+        let bot = User {
+            display: "shaken_bot".into(),
+            userid: 42,
+            color: RGB::from("#ffffff"),
+        };
+        UserStore::create_user(&db, &bot, true);
+
+        let msg = irc::Message::parse(
+            "@badge-info=subscriber/7;badges=vip/1,subscriber/6,bits/100;id=abc-123;\
+             user-id=1000;display-name=test;color=#FFFFFF :test!user@irc.test PRIVMSG #test :!hello world",
+        )
+        .unwrap();
+        let req = Request::try_from(&msg).unwrap().search("!hello").unwrap();
+        assert!(req.is_vip());
+        assert!(req.is_subscriber());
+        assert!(!req.is_from_moderator());
+        assert_eq!(req.sub_months(), Some(7));
+        assert_eq!(req.bits_tier(), Some(100));
+        assert_eq!(req.id(), Some("abc-123"));
+    }
 }
@@ -41,13 +41,20 @@ impl IntoIterator for FormattedResponse {
 }
 
 impl Response {
-    pub(crate) fn build(&self, context: Option<&irc::Message>) -> Option<FormattedResponse> {
+    /// Formats the response into lines for the wire
+    ///
+    /// Anything too long for a single message is split, truncated or whispered
+    /// depending on `messages`
+    pub(crate) fn build(
+        &self,
+        context: Option<&irc::Message>,
+        messages: &config::Messages,
+    ) -> Option<FormattedResponse> {
         match self {
             Response::Multi { data } => {
                 return Some(FormattedResponse::List(
                     data.iter()
-                        .map(|s| s.build(context))
-                        .flat_map(|s| s)
+                        .filter_map(|s| s.build(context, messages))
                         .flat_map(IntoIterator::into_iter)
                         .collect(),
                 ));
@@ -57,7 +64,9 @@ impl Response {
                 IrcCommand::Join { channel } => return Some(format!("JOIN {}", channel).into()),
                 IrcCommand::Raw { data } => return Some(data.clone().into()),
                 IrcCommand::Privmsg { target, data } => {
-                    return Some(format!("PRIVMSG {} :{}", target, data).into());
+                    return Some(lines(fit(data, messages), |s| {
+                        format!("PRIVMSG {} :{}", target, s)
+                    }));
                 }
             },
 
@@ -69,37 +78,101 @@ impl Response {
             None
         })?;
 
+        let target = context.target()?;
         if let Response::Action { data } = self {
-            return Some(format!("PRIVMSG {} :\x01ACTION {}\x01", context.target(), data).into());
+            return Some(lines(fit(data, messages), |s| {
+                format!("PRIVMSG {} :\x01ACTION {}\x01", target, s)
+            }));
         }
 
-        let nick = match context.prefix {
-            Some(irc::Prefix::User { ref nick, .. }) => nick,
-            _ => unreachable!(),
+        let nick = context.nick().or_else(|| {
+            warn!("cannot reply to a message that wasn't from a user");
+            None
+        })?;
+        let user = UserStore::get_user_by_name(&get_connection(), nick)?;
+        let whisper = |data: &str| {
+            lines(fit(data, messages), |s| {
+                format!("PRIVMSG jtv :/w {} {}", user.display, s)
+            })
         };
-        let user = UserStore::get_user_by_name(&get_connection(), &nick)?;
-        match (self, context.command.as_str()) {
-            (Response::Reply { data }, "PRIVMSG") => {
-                Some(format!("PRIVMSG {} :@{}: {}", context.target(), user.display, data).into())
+        let say = |data: &str, original: &str, parent: Option<&str>| {
+            if messages.overflow == config::Overflow::Whisper
+                && data.chars().count() > irc::MAX_MESSAGE_LENGTH
+            {
+                debug!("response is too long for {}, whispering", target);
+                return whisper(original);
             }
+            lines(fit(data, messages), |s| privmsg(target, s, parent))
+        };
 
-            (Response::Say { data }, "PRIVMSG") => {
-                Some(format!("PRIVMSG {} :{}", context.target(), data).into())
+        match (self, context.command()) {
+            (Response::Reply { data }, "PRIVMSG") => {
+                // without an id (e.g. an old recording) fall back to mentioning them
+                let req = Request::try_from(context);
+                match req
+                    .as_ref()
+                    .and_then(Request::id)
+                    .filter(|_| messages.replies == config::Replies::Thread)
+                {
+                    Some(id) => Some(say(data, data, Some(id))),
+                    None => Some(say(&format!("@{}: {}", user.display, data), data, None)),
+                }
             }
 
+            (Response::Say { data }, "PRIVMSG") => Some(say(data, data, None)),
+
             (Response::Reply { data }, "WHISPER")
             | (Response::Say { data }, "WHISPER")
-            | (Response::Whisper { data }, ..) => {
-                Some(format!("PRIVMSG jtv :/w {} {}", user.display, data).into())
+            | (Response::Whisper { data }, ..) => Some(whisper(data)),
+            (resp, command) => {
+                warn!("cannot respond to a {} with {:?}, ignoring", command, resp);
+                None
             }
-            _ => unreachable!(),
         }
     }
 }
 
+// splits or truncates `data` so each part fits in a single message
+fn fit(data: &str, messages: &config::Messages) -> Vec<String> {
+    match messages.overflow {
+        config::Overflow::Truncate => {
+            vec![irc::truncate_message(data, irc::MAX_MESSAGE_LENGTH)]
+        }
+        // whispering is decided by the caller, the whisper itself still gets split
+        config::Overflow::Split | config::Overflow::Whisper => irc::split_message(
+            data,
+            irc::MAX_MESSAGE_LENGTH,
+            messages.continuation.as_deref(),
+        ),
+    }
+}
+
+// a PRIVMSG, threaded under the message with the `parent` id if there is one
+fn privmsg(target: &str, data: &str, parent: Option<&str>) -> String {
+    let mut tags = irc::Tags::default();
+    if let Some(id) = parent {
+        tags.insert("reply-parent-msg-id", id);
+    }
+    irc::Message {
+        tags,
+        prefix: None,
+        command: "PRIVMSG".into(),
+        args: vec![target.into()],
+        data: Some(data.into()),
+    }
+    .to_wire()
+}
+
+fn lines(parts: Vec<String>, f: impl Fn(&str) -> String) -> FormattedResponse {
+    match parts.as_slice() {
+        [part] => f(part).into(),
+        parts => parts.iter().map(|s| f(s)).collect::<Vec<_>>().into(),
+    }
+}
+
 pub fn multi(iter: impl Iterator<Item = Option<Response>>) -> Option<Response> {
     Some(Response::Multi {
-        data: iter.filter_map(|s| s).collect(),
+        data: iter.flatten().collect(),
     })
 }
 
@@ -116,3 +189,98 @@ pub fn join(ch: &str) -> Option<Response> {
         cmd: IrcCommand::Join { channel: ch.into() },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    struct Echo;
+    impl Module for Echo {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            if let Some(req) = req.search("!say") {
+                return say!("{}", req.args());
+            }
+            let req = req.search("!reply")?;
+            reply!("{}", req.args())
+        }
+    }
+
+    #[test]
+    fn long_responses() {
+        let db = database::get_connection();
+        let mut echo = Echo;
+        let mut env = Environment::new(&db, &mut echo);
+        let long = "ünïcödé ".repeat(100);
+
+        env.push(&format!("!say {}", long));
+        env.step();
+        let (first, second) = (env.pop().unwrap(), env.pop().unwrap());
+        assert_eq!(first.chars().count(), 495);
+        assert_eq!(format!("{} {} ", first, second), long);
+
+        env.messages_mut().continuation = Some("(cont)".into());
+        env.push(&format!("!reply {}", long));
+        env.step();
+        let first = env.pop().unwrap();
+        assert!(first.starts_with("@test: ünïcödé"));
+        assert!(first.ends_with("ünïcödé (cont)"));
+        assert!(first.chars().count() <= irc::MAX_MESSAGE_LENGTH);
+        assert!(!env.pop().unwrap().ends_with("(cont)"));
+
+        env.messages_mut().overflow = config::Overflow::Truncate;
+        env.push(&format!("!say {}", long));
+        env.step();
+        assert!(env.pop().unwrap().ends_with("ünïcödé\u{2026}"));
+        assert_eq!(env.pop(), None);
+
+        env.messages_mut().overflow = config::Overflow::Whisper;
+        env.push(&format!("!say {}", long));
+        env.step();
+        assert!(env
+            .pop_raw()
+            .unwrap()
+            .starts_with("PRIVMSG jtv :/w test ünïcödé"));
+        assert!(env
+            .pop_raw()
+            .unwrap()
+            .starts_with("PRIVMSG jtv :/w test ünïcödé"));
+
+        // short responses are never whispered
+        env.push("!say hello");
+        env.step();
+        assert_eq!(env.pop_raw(), Some("PRIVMSG #test :hello".into()));
+    }
+
+    #[test]
+    fn threaded_replies() {
+        let db = database::get_connection();
+        let mut echo = Echo;
+        let mut env = Environment::new(&db, &mut echo);
+        let line = "@id=abc-123;user-id=1000;display-name=test;color=#FFFFFF \
+                    :test!user@irc.test PRIVMSG #test :!reply hello";
+
+        env.push_raw(line);
+        env.step();
+        assert_eq!(
+            env.pop_raw(),
+            Some("@reply-parent-msg-id=abc-123 PRIVMSG #test :hello".into())
+        );
+
+        // only replies are threaded
+        env.push_raw(&line.replace("!reply", "!say"));
+        env.step();
+        assert_eq!(env.pop_raw(), Some("PRIVMSG #test :hello".into()));
+
+        env.messages_mut().replies = config::Replies::Mention;
+        env.push_raw(line);
+        env.step();
+        assert_eq!(env.pop_raw(), Some("PRIVMSG #test :@test: hello".into()));
+
+        // messages without an id are always mentioned
+        env.messages_mut().replies = config::Replies::Thread;
+        env.push("!reply hello");
+        env.step();
+        assert_eq!(env.pop(), Some("@test: hello".into()));
+    }
+}
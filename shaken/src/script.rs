@@ -0,0 +1,418 @@
+use crate::prelude::*;
+
+use heck::SnekCase;
+use log::*;
+use rand::{distributions::Uniform, prelude::*};
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, Dynamic, Engine, EvalAltResult, ImmutableString,
+    Map, Scope,
+};
+use rusqlite::{Connection, OptionalExtension};
+
+use std::sync::{Arc, Mutex};
+
+/// User commands whose body starts with this are scripts
+pub const PREFIX: &str = "script:";
+
+// keeps a runaway script from tying up the module
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_STRING_SIZE: usize = 4096;
+const MAX_COLLECTION_SIZE: usize = 1024;
+const MAX_STORE_CALLS: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Compile(String),
+    Runtime(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Compile(err) => write!(f, "syntax error: {}", err),
+            Error::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The script in a command `body`, if it is one
+pub fn source(body: &str) -> Option<&str> {
+    body.strip_prefix(PREFIX).map(str::trim)
+}
+
+/// Checks that `source` is a valid script, without running it
+pub fn compile(source: &str) -> Result<(), Error> {
+    engine("")
+        .compile(source)
+        .map(|_| ())
+        .map_err(|err| Error::Compile(err.to_string()))
+}
+
+/// Runs the script for `command`, returning the lines to say
+///
+/// The script gets the request as the `req` constant, and the value of its last
+/// expression is said. An array is said one element per line, and `()` says nothing
+pub fn run(command: &str, source: &str, req: &Request) -> Result<Vec<String>, Error> {
+    let engine = engine(command);
+    let ast = engine
+        .compile(source)
+        .map_err(|err| Error::Compile(err.to_string()))?;
+
+    let mut scope = Scope::new();
+    scope.push_constant("req", request(req));
+
+    let value = engine
+        .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+        .map_err(|err| Error::Runtime(err.to_string()))?;
+
+    let lines = if value.is_unit() {
+        vec![]
+    } else if value.is_array() {
+        value
+            .into_array()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect()
+    } else {
+        vec![value.to_string()]
+    };
+    Ok(lines.into_iter().filter(|s| !s.trim().is_empty()).collect())
+}
+
+fn request(req: &Request) -> Map {
+    let conn = get_connection();
+    let sender = UserStore::get_user_by_id(&conn, req.sender())
+        .map(|user| user.display)
+        .unwrap_or_default();
+    let badges = req
+        .badges()
+        .iter()
+        .map(|badge| Dynamic::from(format!("{:?}", badge).to_snek_case()))
+        .collect::<Array>();
+    let argv = req
+        .args_iter()
+        .map(|s| Dynamic::from(s.to_string()))
+        .collect::<Array>();
+
+    let mut map = Map::new();
+    map.insert("sender".into(), sender.into());
+    map.insert("sender_id".into(), req.sender().into());
+    map.insert("channel".into(), req.target().to_string().into());
+    map.insert("args".into(), req.args().to_string().into());
+    map.insert("argv".into(), argv.into());
+    map.insert("badges".into(), badges.into());
+    map.insert("is_moderator".into(), req.is_from_moderator().into());
+    map.insert("is_broadcaster".into(), req.is_from_broadcaster().into());
+    map.insert("is_vip".into(), req.is_vip().into());
+    map.insert("is_subscriber".into(), req.is_subscriber().into());
+    map
+}
+
+fn engine(command: &str) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(16)
+        .set_max_expr_depths(32, 32)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_COLLECTION_SIZE)
+        .set_max_map_size(MAX_COLLECTION_SIZE)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+    engine.disable_symbol("eval");
+    // scripts can't load other scripts from the filesystem
+    engine.set_module_resolver(DummyModuleResolver::new());
+
+    engine
+        .register_fn("random", |lo: i64, hi: i64| {
+            let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+            thread_rng().sample(Uniform::new_inclusive(lo, hi))
+        })
+        .register_fn("pick", |list: Array| {
+            list.choose(&mut thread_rng())
+                .cloned()
+                .unwrap_or(Dynamic::UNIT)
+        })
+        .register_fn("uptime", || {
+            connection::stats()
+                .uptime()
+                .map(|dur| dur.as_readable_time())
+                .unwrap_or_default()
+        })
+        .register_fn(
+            "template",
+            |key: &str| -> Result<String, Box<EvalAltResult>> {
+                template::finder()
+                    .get_no_apply(key)
+                    .map_err(|err| err.to_string().into())
+            },
+        )
+        .register_fn(
+            "template",
+            |key: &str, args: Map| -> Result<String, Box<EvalAltResult>> {
+                let args = args
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.to_string()))
+                    .collect::<Vec<_>>();
+                template::lookup(key, &args).map_err(|err| err.to_string().into())
+            },
+        );
+
+    // the store is separate for each command
+    let store = Store::new(command);
+    let (get, get_or, set, incr, remove) = (
+        store.clone(),
+        store.clone(),
+        store.clone(),
+        store.clone(),
+        store,
+    );
+    engine
+        .register_fn(
+            "get",
+            move |key: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+                Ok(get
+                    .with(|conn, cmd| Store::get(conn, cmd, key))?
+                    .unwrap_or(Dynamic::UNIT))
+            },
+        )
+        .register_fn(
+            "get",
+            move |key: &str, default: Dynamic| -> Result<Dynamic, Box<EvalAltResult>> {
+                Ok(get_or
+                    .with(|conn, cmd| Store::get(conn, cmd, key))?
+                    .unwrap_or(default))
+            },
+        )
+        .register_fn(
+            "set",
+            move |key: &str, val: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                set.with(|conn, cmd| Store::set(conn, cmd, key, &val))
+            },
+        )
+        .register_fn(
+            "incr",
+            move |key: &str| -> Result<i64, Box<EvalAltResult>> {
+                incr.with(|conn, cmd| {
+                    let n = Store::get(conn, cmd, key)
+                        .and_then(|val| val.as_int().ok())
+                        .unwrap_or_default()
+                        .saturating_add(1);
+                    Store::set(conn, cmd, key, &n.into());
+                    n
+                })
+            },
+        )
+        .register_fn(
+            "remove",
+            move |key: &str| -> Result<(), Box<EvalAltResult>> {
+                remove.with(|conn, cmd| Store::remove(conn, cmd, key))
+            },
+        );
+
+    engine
+}
+
+// a small key/value store for a command's script
+//
+// one is made for each run, so the table is only created once, and a script can
+// only use it so many times
+#[derive(Clone)]
+struct Store(Arc<Mutex<StoreState>>);
+
+struct StoreState {
+    command: String,
+    calls: usize,
+    conn: Option<Connection>,
+}
+
+impl Store {
+    fn new(command: &str) -> Self {
+        Store(Arc::new(Mutex::new(StoreState {
+            command: command.to_string(),
+            calls: 0,
+            conn: None,
+        })))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Connection, &str) -> T) -> Result<T, Box<EvalAltResult>> {
+        let mut state = self.0.lock().unwrap();
+        let StoreState {
+            command,
+            calls,
+            conn,
+        } = &mut *state;
+
+        *calls += 1;
+        if *calls > MAX_STORE_CALLS {
+            let err = format!("the store can only be used {} times", MAX_STORE_CALLS);
+            return Err(err.into());
+        }
+
+        let conn = conn.get_or_insert_with(|| {
+            let conn = get_connection();
+            Self::ensure_table(&conn);
+            conn
+        });
+        Ok(f(conn, command))
+    }
+
+    fn ensure_table(conn: &Connection) {
+        conn.execute(
+            r#"CREATE TABLE IF NOT EXISTS ScriptStore(
+                command     TEXT NOT NULL,
+                key         TEXT NOT NULL,
+                value       TEXT NOT NULL,
+                UNIQUE(command, key)
+            )"#,
+            rusqlite::NO_PARAMS,
+        )
+        .expect("create ScriptStore table");
+    }
+
+    // numbers come back as numbers, everything else as a string
+    fn get(conn: &Connection, command: &str, key: &str) -> Option<Dynamic> {
+        let val: String = conn
+            .query_row(
+                "SELECT value FROM ScriptStore WHERE command = ? AND key = ?",
+                &[command, key],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("valid sql")?;
+        Some(match val.parse::<i64>() {
+            Ok(n) => n.into(),
+            Err(..) => ImmutableString::from(val).into(),
+        })
+    }
+
+    fn set(conn: &Connection, command: &str, key: &str, val: &Dynamic) {
+        if let Err(err) = conn.execute(
+            "INSERT OR REPLACE INTO ScriptStore (command, key, value) VALUES (?, ?, ?)",
+            &[command, key, &val.to_string()],
+        ) {
+            warn!("cannot store {} for {}: {}", key, command, err)
+        }
+    }
+
+    fn remove(conn: &Connection, command: &str, key: &str) {
+        conn.execute(
+            "DELETE FROM ScriptStore WHERE command = ? AND key = ?",
+            &[command, key],
+        )
+        .expect("valid sql");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn request(args: &str) -> Request {
+        let data = format!("!cmd {}", args);
+        testing::request("#test", 1000, "vip/1", &data)
+            .search("!cmd")
+            .unwrap()
+    }
+
+    #[test]
+    fn request_fields() {
+        let _db = get_connection();
+        let req = request("hello world");
+        let run = |source| run("!cmd", source, &req).unwrap();
+
+        assert_eq!(run("req.sender"), vec!["test"]);
+        assert_eq!(run("`${req.argv[1]} ${req.channel}`"), vec!["world #test"]);
+        assert_eq!(run("req.args.len()"), vec!["11"]);
+        assert_eq!(
+            run("if req.is_vip { \"vip\" } else { \"pleb\" }"),
+            vec!["vip"]
+        );
+        assert_eq!(run("\"vip\" in req.badges"), vec!["true"]);
+        assert_eq!(run("[\"a\", \"\", \"b\"]"), vec!["a", "b"]);
+        assert!(run("()").is_empty());
+    }
+
+    #[test]
+    fn store() {
+        let _db = get_connection();
+        let req = request("");
+        let counter = "let n = incr(\"count\"); `count is ${n}`";
+
+        assert_eq!(run("!a", counter, &req).unwrap(), vec!["count is 1"]);
+        assert_eq!(run("!a", counter, &req).unwrap(), vec!["count is 2"]);
+        // each command has its own store
+        assert_eq!(run("!b", counter, &req).unwrap(), vec!["count is 1"]);
+
+        let source = "set(\"name\", req.sender); get(\"name\") + get(\"missing\", \"!\")";
+        assert_eq!(run("!a", source, &req).unwrap(), vec!["test!"]);
+        assert_eq!(
+            run("!a", "remove(\"name\"); get(\"name\")", &req).unwrap(),
+            Vec::<String>::new()
+        );
+
+        // but it can only be used so many times in a run
+        let source = "for i in 0..100 { incr(\"spam\") }";
+        match run("!c", source, &req) {
+            Err(Error::Runtime(err)) => assert!(err.contains("the store can only be used")),
+            res => panic!("{:?}", res),
+        }
+        let source = "get(\"spam\")";
+        assert_eq!(
+            run("!c", source, &req).unwrap(),
+            vec![MAX_STORE_CALLS.to_string()]
+        );
+    }
+
+    #[test]
+    fn helpers() {
+        let _db = get_connection();
+        let req = request("");
+        for _ in 0..100 {
+            let n = run("!cmd", "random(1, 3)", &req).unwrap()[0]
+                .parse::<i64>()
+                .unwrap();
+            assert!((1..=3).contains(&n));
+        }
+        let max = i64::MAX.to_string();
+        let source = format!("random({}, {})", max, max);
+        assert_eq!(run("!cmd", &source, &req).unwrap(), vec![max]);
+        assert_eq!(run("!cmd", "random(5, 5)", &req).unwrap(), vec!["5"]);
+        let picked = run("!cmd", "pick([\"a\", \"b\"])", &req).unwrap();
+        assert!(picked == vec!["a"] || picked == vec!["b"]);
+        assert_eq!(
+            run("!cmd", "template(\"misc_requires_priv\")", &req).unwrap(),
+            vec!["you cannot do that"]
+        );
+    }
+
+    #[test]
+    fn sandboxed() {
+        let _db = get_connection();
+        let req = request("");
+        assert!(compile("let x = ").is_err());
+        assert!(compile("eval(\"1\")").is_err());
+        match run("!cmd", "loop {}", &req) {
+            Err(Error::Runtime(..)) => {}
+            res => panic!("{:?}", res),
+        }
+        match run("!cmd", "let s = \"a\"; loop { s += s }", &req) {
+            Err(Error::Runtime(..)) => {}
+            res => panic!("{:?}", res),
+        }
+
+        let module = std::env::temp_dir().join(format!("shaken-import-{}", std::process::id()));
+        std::fs::write(module.with_extension("rhai"), "export const secret = 42;").unwrap();
+        let script = format!("import {:?} as m; m::secret", module.to_string_lossy());
+        let res = run("!cmd", &script, &req);
+        std::fs::remove_file(module.with_extension("rhai")).unwrap();
+        match res {
+            Err(Error::Runtime(..)) => {}
+            res => panic!("{:?}", res),
+        }
+    }
+}
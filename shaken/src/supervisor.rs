@@ -0,0 +1,286 @@
+use crate::prelude::*;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as channel;
+use hashbrown::HashMap;
+use log::*;
+use once_cell::{sync::Lazy, sync_lazy};
+
+static STATUS: Lazy<RwLock<HashMap<&'static str, Status>>> = sync_lazy! {
+    RwLock::new(HashMap::new())
+};
+
+/// A module that hasn't panicked for this long starts over with the shortest delay
+const STABLE_MODULE: Duration = Duration::from_secs(5 * 60);
+
+/// How a module has been doing since the process started
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub panics: usize,
+    pub restarts: usize, // successful rebuilds after a panic
+    pub last_error: Option<String>,
+    pub last_panic: Option<Instant>,
+    pub down: bool, // waiting to be rebuilt
+}
+
+/// Gets a snapshot of the status of the module enabled as `name`
+pub fn status(name: &str) -> Option<Status> {
+    STATUS.read().unwrap().get(name).cloned()
+}
+
+/// Gets a snapshot of the status of every module that has been supervised
+pub fn statuses() -> Vec<(&'static str, Status)> {
+    let mut list = STATUS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(k, v)| (*k, v.clone()))
+        .collect::<Vec<_>>();
+    list.sort_unstable_by_key(|(k, _)| *k);
+    list
+}
+
+fn update(name: &'static str, f: impl FnOnce(&mut Status)) {
+    f(STATUS.write().unwrap().entry(name).or_default())
+}
+
+/// Runs a module one event at a time, so a panic only loses that event
+///
+/// A module that panics is taken down, and rebuilt after a backoff. Events that
+/// arrive while it is down are dropped. The `owners` are whispered about each panic
+pub(crate) struct Supervisor<'a> {
+    loaded: &'a LoadedModule,
+    owners: &'a [i64],
+    backoff: connection::Backoff,
+    down_until: Option<Instant>,
+}
+
+impl<'a> Supervisor<'a> {
+    pub fn new(loaded: &'a LoadedModule, owners: &'a [i64], backoff: connection::Backoff) -> Self {
+        update(loaded.name, |_| {});
+        Self {
+            loaded,
+            owners,
+            backoff,
+            down_until: None,
+        }
+    }
+
+    pub fn run(mut self, rx: Receiver, tx: Sender) {
+        for event in rx {
+            self.dispatch(event, &tx)
+        }
+    }
+
+    pub fn dispatch(&mut self, event: Event, tx: &Sender) {
+        if let Some(until) = self.down_until {
+            if Instant::now() < until {
+                trace!("{} is down, dropping an event", self.loaded.name);
+                return;
+            }
+            if !self.restart() {
+                return;
+            }
+        }
+
+        let (events_tx, events) = channel::bounded(1);
+        let _ = events_tx.send(event);
+        drop(events_tx);
+
+        let loaded = self.loaded;
+        let sender = tx.clone();
+        if let Err(err) = panic::catch_unwind(AssertUnwindSafe(move || {
+            loaded.lock().handle(events, sender)
+        })) {
+            self.crashed(describe(&*err), tx)
+        }
+    }
+
+    fn crashed(&mut self, error: String, tx: &Sender) {
+        let name = self.loaded.name;
+        let now = Instant::now();
+
+        let mut stable = false;
+        update(name, |status| {
+            stable = status
+                .last_panic
+                .is_none_or(|at| now.duration_since(at) >= STABLE_MODULE);
+            status.panics += 1;
+            status.last_error.replace(error.clone());
+            status.last_panic.replace(now);
+            status.down = true;
+        });
+        if stable {
+            self.backoff.reset()
+        }
+
+        let delay = self.backoff.next_delay();
+        self.down_until.replace(now + delay);
+        error!(
+            "module {} panicked: {}. restarting in {:.1} seconds",
+            name,
+            error,
+            delay.as_secs_f64()
+        );
+
+        let conn = get_connection();
+        for owner in self.owners {
+            if let Some(user) = UserStore::get_user_by_id(&conn, *owner) {
+                let data = format!(
+                    "/w {} module {} panicked: {} (restarting in {}s)",
+                    user.display,
+                    name,
+                    error,
+                    delay.as_secs()
+                );
+                let cmd = IrcCommand::Privmsg {
+                    target: "jtv".into(),
+                    data,
+                };
+                let _ = tx.send((None, Response::Command { cmd }));
+            }
+        }
+    }
+
+    // returns whether the module is back up
+    fn restart(&mut self) -> bool {
+        let name = self.loaded.name;
+        match self.loaded.rebuild() {
+            Ok(..) => {
+                info!("restarted module: {}", name);
+                self.down_until.take();
+                update(name, |status| {
+                    status.restarts += 1;
+                    status.down = false;
+                });
+                true
+            }
+            Err(err) => {
+                let delay = self.backoff.next_delay();
+                warn!(
+                    "cannot restart module {} ({:?}), trying again in {:.1} seconds",
+                    name,
+                    err,
+                    delay.as_secs_f64()
+                );
+                self.down_until.replace(Instant::now() + delay);
+                false
+            }
+        }
+    }
+}
+
+fn describe(err: &(dyn std::any::Any + Send)) -> String {
+    err.downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| err.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Boom;
+    impl Boom {
+        fn create() -> Result<Self, ModuleError> {
+            CREATED.fetch_add(1, Ordering::SeqCst);
+            Ok(Boom)
+        }
+    }
+    impl Module for Boom {
+        fn command(&mut self, req: &Request) -> Option<Response> {
+            if req.search("!boom").is_some() {
+                panic!("boom")
+            }
+            req.search("!ping")?;
+            privmsg!(req.target(), "pong")
+        }
+    }
+
+    fn event(data: &str) -> Event {
+        let msg = irc::Message::parse(&format!(
+            "@user-id=1000;display-name=test;color=#FFFFFF :test!user@irc.test PRIVMSG #test \
+             :{}",
+            data
+        ))
+        .unwrap();
+        let req = Request::try_from(&msg).map(Box::new);
+        Event::Message(msg, req, None)
+    }
+
+    fn responses(rx: &channel::Receiver<(Option<irc::Message>, Response)>) -> Vec<String> {
+        rx.try_iter()
+            .filter_map(|(msg, resp)| resp.build(msg.as_ref(), &Default::default()))
+            .flat_map(IntoIterator::into_iter)
+            .collect()
+    }
+
+    fn setup() -> rusqlite::Connection {
+        let db = database::get_connection();
+        for (display, userid, bot) in &[("shaken_bot", 42, true), ("owner", 1, false)] {
+            let user = User {
+                display: display.to_string(),
+                userid: *userid,
+                color: RGB::from("#ffffff"),
+            };
+            UserStore::create_user(&db, &user, *bot);
+        }
+        db
+    }
+
+    #[test]
+    fn restart_after_panic() {
+        let _db = setup();
+        let loaded = LoadedModule::supervised("Boom", Boom::create).unwrap();
+        let backoff = connection::Backoff::new(Duration::from_secs(0), Duration::from_secs(0));
+        let mut supervisor = Supervisor::new(&loaded, &[1], backoff);
+        let (tx, rx) = channel::unbounded();
+
+        supervisor.dispatch(event("!ping"), &tx);
+        assert_eq!(responses(&rx), vec!["PRIVMSG #test :pong"]);
+
+        let created = CREATED.load(Ordering::SeqCst);
+        supervisor.dispatch(event("!boom"), &tx);
+        assert_eq!(
+            responses(&rx),
+            vec!["PRIVMSG jtv :/w owner module Boom panicked: boom (restarting in 0s)"]
+        );
+        let status = super::status("Boom").unwrap();
+        assert_eq!(status.panics, 1);
+        assert_eq!(status.last_error, Some("boom".into()));
+        assert!(status.down);
+
+        // the next event rebuilds it
+        supervisor.dispatch(event("!ping"), &tx);
+        assert_eq!(responses(&rx), vec!["PRIVMSG #test :pong"]);
+        assert!(CREATED.load(Ordering::SeqCst) > created);
+        let status = super::status("Boom").unwrap();
+        assert_eq!(status.restarts, 1);
+        assert!(!status.down);
+        assert!(!loaded.module.is_poisoned());
+    }
+
+    #[test]
+    fn down_during_backoff() {
+        let _db = setup();
+        let loaded = LoadedModule::new("BoomBackoff", Boom);
+        let backoff = connection::Backoff::new(Duration::from_secs(60), Duration::from_secs(60));
+        let mut supervisor = Supervisor::new(&loaded, &[], backoff);
+        let (tx, rx) = channel::unbounded();
+
+        supervisor.dispatch(event("!boom"), &tx);
+        supervisor.dispatch(event("!ping"), &tx);
+        assert!(responses(&rx).is_empty());
+
+        let status = super::status("BoomBackoff").unwrap();
+        assert_eq!((status.panics, status.restarts), (1, 0));
+        assert!(status.down);
+    }
+}
@@ -92,7 +92,7 @@ impl Template {
                 Some(part) => part,
                 None => return Err(Error::Missing(self.left)),
             };
-            self.data.replace_range(self.index.clone(), &part);
+            self.data.replace_range(self.index.clone(), part);
             if seen == parts.len() - 1 {
                 break;
             }
@@ -102,7 +102,7 @@ impl Template {
                 Err(err) => return Err(err),
                 Ok(this) => this,
             };
-            std::mem::replace(&mut self, this);
+            self = this;
             seen += 1;
         }
 
@@ -177,6 +177,8 @@ submit!(
     Response("misc_done", "done");
     Response("misc_invalid_args", "invalid arguments");
     Response("misc_invalid_number", "thats not a number I understand");
+    Response("misc_usage", "${error}. usage: ${usage}");
+    Response("misc_cooldown", "${command} can be used again in ${remaining}");
     Response("misc_requires_priv", "you cannot do that");
 );
 
@@ -275,9 +277,9 @@ fn get_data_file() -> Option<PathBuf> {
     use directories::ProjectDirs;
     ProjectDirs::from("com.github", "museun", "shaken").and_then(|dir| {
         let dir = dir.config_dir();
-        std::fs::create_dir_all(&dir)
+        std::fs::create_dir_all(dir)
             .ok()
-            .and_then(|_| Some(dir.join("responses.json")))
+            .map(|_| dir.join("responses.json"))
     })
 }
 
@@ -326,7 +328,7 @@ mod tests {
                         'collected' ${overall_total} credits from all of \
                         the failures.";
 
-        let t = Template::parse(&template).unwrap();
+        let t = Template::parse(template).unwrap();
         let out = t
             .apply(&[
                 ("max", &"218,731"),
@@ -353,7 +355,7 @@ mod tests {
                         'collected' ${overall_total} credits from all of \
                         the failures.";
 
-        let t = Template::parse(&template).unwrap();
+        let t = Template::parse(template).unwrap();
         eprintln!("{:#?}", t);
         let parts = t
             .args()
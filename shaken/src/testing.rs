@@ -37,10 +37,29 @@ pub fn make_test_user(conn: &Connection, name: &str, id: i64) -> User {
         userid: id,
         color: crate::color::RGB::from("#ffffff"),
     };
-    let _ = UserStore::create_user(&conn, &user, false);
+    let _ = UserStore::create_user(conn, &user, false);
     user
 }
 
+/// A request for `data`, from `userid` (named "test") with `badges` in `channel`
+pub fn request(channel: &str, userid: i64, badges: &str, data: &str) -> Request {
+    let conn = get_connection();
+    let bot = User {
+        display: "shaken_bot".into(),
+        userid: 42,
+        color: crate::color::RGB::from("#ffffff"),
+    };
+    UserStore::create_user(&conn, &bot, true);
+    make_test_user(&conn, USER_NAME, userid);
+
+    let msg = irc::Message::parse(&format!(
+        "@badges={};user-id={};display-name={};color=#FFFFFF :{}!user@irc.test PRIVMSG {} :{}",
+        badges, userid, USER_NAME, USER_NAME, channel, data
+    ))
+    .expect("valid test input");
+    Request::try_from(&msg).expect("a request")
+}
+
 const USER_ID: i64 = 1000;
 const USER_NAME: &str = "test";
 
@@ -53,6 +72,8 @@ pub struct Environment<'a> {
 
     in_tx: channel::Sender<(Option<irc::Message>, Response)>,
     in_rx: channel::Receiver<(Option<irc::Message>, Response)>,
+
+    messages: config::Messages,
 }
 
 impl<'a> Environment<'a> {
@@ -61,7 +82,7 @@ impl<'a> Environment<'a> {
         M: Module + 'a,
     {
         UserStore::create_user(
-            &db,
+            db,
             &User {
                 display: USER_NAME.into(),
                 color: RGB::from("#f0f0f0"),
@@ -71,7 +92,7 @@ impl<'a> Environment<'a> {
         );
 
         UserStore::create_user(
-            &db,
+            db,
             &User {
                 display: "shaken_bot".into(),
                 color: RGB::from("#f0f0f0"),
@@ -88,12 +109,13 @@ impl<'a> Environment<'a> {
             write: VecDeque::new(),
             in_tx,
             in_rx,
+            messages: Config::load().messages,
         }
     }
 
     #[allow(dead_code)]
     pub fn get_db_conn(&self) -> &Connection {
-        &self.db
+        self.db
     }
 
     #[allow(dead_code)]
@@ -106,6 +128,11 @@ impl<'a> Environment<'a> {
         self.module
     }
 
+    #[allow(dead_code)]
+    pub fn messages_mut(&mut self) -> &mut config::Messages {
+        &mut self.messages
+    }
+
     pub fn step_wait(&mut self, wait: bool) {
         let input = match self.read.pop_front() {
             Some(data) => data,
@@ -114,12 +141,15 @@ impl<'a> Environment<'a> {
 
         let (out_tx, out_rx) = channel::unbounded();
 
-        let msg = irc::Message::parse(&input);
+        let msg = irc::Message::parse(&input).expect("valid test input");
+        permission::observe(&msg);
         let req = Request::try_from(&msg);
+        let ev = irc::TwitchEvent::from_msg(&msg);
         trace!("(msg) -> {:?}", msg);
         trace!("(req) -> {:?}", req);
+        trace!("(ev) -> {:?}", ev);
 
-        let _ = out_tx.send(Event::Message(msg, req.map(Box::new)));
+        let _ = out_tx.send(Event::Message(msg, req.map(Box::new), ev.map(Box::new)));
 
         drop(out_tx);
         self.module.handle(out_rx, self.in_tx.clone());
@@ -138,10 +168,11 @@ impl<'a> Environment<'a> {
             if let Some(msg) = msg.as_ref() {
                 self.module.inspect(&msg.clone(), &resp.clone());
             }
-            if let Some(resp) = resp.build(msg.as_ref()) {
-                resp.into_iter()
-                    .inspect(|s| trace!("writing response: {}", s))
-                    .for_each(|m| self.write.push_back(m));
+            if let Some(resp) = resp.build(msg.as_ref(), &self.messages) {
+                for m in resp {
+                    trace!("writing response: {}", m);
+                    self.write.push_back(m)
+                }
             }
         }
     }
@@ -150,11 +181,17 @@ impl<'a> Environment<'a> {
         self.step_wait(true)
     }
 
+    #[allow(dead_code)]
     pub fn tick(&mut self) {
-        use std::time::{Duration, Instant};
+        self.tick_at(std::time::Instant::now())
+    }
+
+    /// Ticks the module as if it were `now`, so tests don't have to sleep
+    pub fn tick_at(&mut self, now: std::time::Instant) {
+        use std::time::Duration;
 
         let (out_tx, out_rx) = channel::unbounded();
-        let _ = out_tx.send(Event::Tick(Instant::now()));
+        let _ = out_tx.send(Event::Tick(now));
 
         drop(out_tx);
         self.module.handle(out_rx, self.in_tx.clone());
@@ -168,10 +205,11 @@ impl<'a> Environment<'a> {
             if let Some(msg) = msg.as_ref() {
                 self.module.inspect(&msg.clone(), &resp.clone());
             }
-            if let Some(resp) = resp.build(msg.as_ref()) {
-                resp.into_iter()
-                    .inspect(|s| trace!("writing response: {}", s))
-                    .for_each(|m| self.write.push_back(m));
+            if let Some(resp) = resp.build(msg.as_ref(), &self.messages) {
+                for m in resp {
+                    trace!("writing response: {}", m);
+                    self.write.push_back(m)
+                }
             }
         }
     }
@@ -185,7 +223,7 @@ impl<'a> Environment<'a> {
 
     pub fn push_user(&mut self, data: &str, user: (&str, i64)) {
         UserStore::create_user(
-            &self.db,
+            self.db,
             &User {
                 display: user.0.into(),
                 color: crate::color::RGB::from("#f0f0f0"),
@@ -200,7 +238,6 @@ impl<'a> Environment<'a> {
         ))
     }
 
-    #[allow(dead_code)]
     pub fn push_mod(&mut self, data: &str) {
         self.push_raw(&format!(
             "@badges=moderator/1;user-id={};display-name={};color=#FFFFFF :{}!user@irc.test \
@@ -235,10 +272,8 @@ impl<'a> Environment<'a> {
     }
 
     pub fn pop(&mut self) -> Option<String> {
-        let mut data = self.write.pop_front()?;
-        data.insert_str(0, ":test!user@irc.test ");
-        let msg = irc::Message::parse(&data);
-        msg.data
+        let data = self.write.pop_front()?;
+        irc::Message::parse(&data).ok()?.data
     }
 
     pub fn get_user_id(&self) -> i64 {
@@ -251,7 +286,7 @@ impl<'a> Environment<'a> {
     }
 
     pub fn drain(&mut self) {
-        while let Some(_) = self.pop() {}
+        while self.pop().is_some() {}
     }
 
     /// this logs to the warn level
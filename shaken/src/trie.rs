@@ -0,0 +1,125 @@
+use hashbrown::HashMap;
+
+/// A prefix trie of whole words, for finding the command a line starts with
+///
+/// `!poll start` is stored as `!poll` -> `start`, so `!pollster` doesn't match
+/// `!poll`. Looking something up doesn't allocate
+#[derive(Debug, Clone)]
+pub struct Trie<V> {
+    nodes: Vec<Node<V>>, // the root is the first one
+}
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    children: HashMap<String, usize>,
+    value: Option<V>,
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            children: HashMap::new(),
+            value: None,
+        }
+    }
+}
+
+impl<V> Default for Trie<V> {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+impl<V> Trie<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value` for the words in `key`, returning the value it replaced
+    pub fn insert(&mut self, key: &str, value: V) -> Option<V> {
+        let mut node = 0;
+        for word in key.split_whitespace() {
+            node = match self.nodes[node].children.get(word) {
+                Some(&next) => next,
+                None => {
+                    let next = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children.insert(word.to_string(), next);
+                    next
+                }
+            };
+        }
+
+        self.nodes[node].value.replace(value)
+    }
+
+    /// Finds the longest key that `input` starts with
+    ///
+    /// Returns its value, and the rest of the line after it
+    pub fn find<'a>(&self, input: &'a str) -> Option<(&V, &'a str)> {
+        let (mut node, mut found) = (0, None);
+        let mut rest = input.trim_start();
+        while !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (word, tail) = rest.split_at(end);
+            node = match self.nodes[node].children.get(word) {
+                Some(&next) => next,
+                None => break,
+            };
+            rest = tail.trim_start();
+            if let Some(value) = &self.nodes[node].value {
+                found.replace((value, rest));
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie() -> Trie<&'static str> {
+        let mut trie = Trie::new();
+        for key in &["!poll", "!poll start", "!poll stop", "!version", "!a b c"] {
+            assert_eq!(trie.insert(key, *key), None);
+        }
+        trie
+    }
+
+    #[test]
+    fn find_whole_words() {
+        let trie = trie();
+        assert_eq!(trie.find("!poll"), Some((&"!poll", "")));
+        assert_eq!(
+            trie.find("!poll title | a | b"),
+            Some((&"!poll", "title | a | b"))
+        );
+        assert_eq!(
+            trie.find("!poll  start   30s"),
+            Some((&"!poll start", "30s"))
+        );
+        assert_eq!(trie.find("!poll starting"), Some((&"!poll", "starting")));
+
+        assert_eq!(trie.find("!pollster"), None);
+        assert_eq!(trie.find("!versions"), None);
+        assert_eq!(trie.find("!vers"), None);
+        assert_eq!(trie.find(""), None);
+
+        // the longest key that has a value
+        assert_eq!(trie.find("!a b"), None);
+        assert_eq!(trie.find("!a b c d"), Some((&"!a b c", "d")));
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut trie = trie();
+        assert_eq!(trie.insert("!version", "replaced"), Some("!version"));
+        assert_eq!(trie.insert("!poll  start", "changed"), Some("!poll start"));
+        assert_eq!(trie.find("!version 2"), Some((&"replaced", "2")));
+        assert_eq!(trie.find("!poll start 30s"), Some((&"changed", "30s")));
+        assert_eq!(trie.find("!poll 30s"), Some((&"!poll", "30s")));
+    }
+}
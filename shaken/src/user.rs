@@ -118,7 +118,6 @@ impl UserStore {
                 &if bot { 1 } else { 0 },
             ],
         ) {
-            Ok(row) if row == 0 => {}
             Ok(_row) => {}
             Err(err) => error!("cannot insert user({:?}) into table: {}", user, err),
         };
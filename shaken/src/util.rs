@@ -76,7 +76,7 @@ impl Timestamp for Duration {
         if len > 1 {
             if len > 2 {
                 for e in &mut time.iter_mut().take(len - 2) {
-                    e.push_str(",")
+                    e.push(',')
                 }
             }
             time.insert(len - 1, "and".into())
@@ -169,6 +169,18 @@ pub fn get_timestamp() -> u64 {
     ts.as_secs() * 1000 + u64::from(ts.subsec_nanos()) / 1_000_000
 }
 
+/// Converts `at` to milliseconds since the unix epoch, so it can be saved
+pub fn instant_to_timestamp(at: std::time::Instant) -> u64 {
+    get_timestamp().saturating_sub(at.elapsed().as_millis() as u64)
+}
+
+/// Converts a saved timestamp back into an `Instant`, or now if it can't be represented
+pub fn timestamp_to_instant(ts: u64) -> std::time::Instant {
+    use std::time::Instant;
+    let ago = Duration::from_millis(get_timestamp().saturating_sub(ts));
+    Instant::now().checked_sub(ago).unwrap_or_else(Instant::now)
+}
+
 pub fn get_log_level(var: &str) -> simplelog::LevelFilter {
     use simplelog::LevelFilter;
     match std::env::var(var)
@@ -182,7 +194,7 @@ pub fn get_log_level(var: &str) -> simplelog::LevelFilter {
         "ERROR" => LevelFilter::Error,
 
         // default
-        "INFO" | _ => LevelFilter::Info,
+        _ => LevelFilter::Info,
     }
 }
 
@@ -192,7 +204,7 @@ where
 {
     std::fs::metadata(path.as_ref())
         .ok()
-        .and_then(|s| Some(s.len() / 1024))
+        .map(|s| s.len() / 1024)
 }
 
 #[macro_export]